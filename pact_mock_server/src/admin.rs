@@ -0,0 +1,164 @@
+//! Admin API that can be enabled on a mock server under a reserved path prefix (i.e. `/__pact`).
+//! This allows test processes that are not able to use the mock server directly (for instance,
+//! tests written in a different language) to inspect and control it over HTTP.
+//!
+//! | Request                     | Description                                                    |
+//! |-----------------------------|----------------------------------------------------------------|
+//! | `GET {prefix}/status`       | Details of the mock server (the same as `MockServer::to_json`) |
+//! | `GET {prefix}/interactions` | The interactions from the Pact the mock server is based on     |
+//! | `GET {prefix}/matches`      | All the match results for the requests received                |
+//! | `GET {prefix}/mismatches`   | Any mismatches, including interactions without a request      |
+//! | `POST {prefix}/reset`       | Clears the recorded match results                              |
+//! | `POST {prefix}/write-pact`  | Writes the Pact file out to the admin output directory of the mock server. The `outputPath` and `overwrite` query parameters can be used to set a sub-directory to write to and if any existing file should be overwritten. |
+//!
+//! As the admin API can be reachable from the network, the Pact file can only be written under the
+//! output directory configured on the mock server (`MockServerConfig::admin_output_dir`, or the
+//! current working directory if not set). The `outputPath` query parameter must be a relative path,
+//! and can not contain any `..` components.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{Method, Response};
+use pact_models::query_strings::parse_query_string;
+use serde_json::{json, Value};
+use tracing::{debug, warn};
+
+use crate::matching::MatchResult;
+use crate::mock_server::MockServer;
+
+/// Returns the path relative to the admin path prefix, if the request path is under it
+pub(crate) fn admin_sub_path<'a>(admin_path: &str, path: &'a str) -> Option<&'a str> {
+  let prefix = admin_path.trim_end_matches('/');
+  path.strip_prefix(prefix)
+    .filter(|sub_path| sub_path.is_empty() || sub_path.starts_with('/'))
+    .map(|sub_path| sub_path.trim_matches('/'))
+}
+
+/// Handles a request to the admin API. `sub_path` is the path of the request relative to the
+/// admin path prefix, and `mock_server` a detached instance of the mock server the request was
+/// received by.
+pub(crate) fn admin_response(
+  mock_server: &MockServer,
+  method: &Method,
+  sub_path: &str,
+  query: Option<&str>
+) -> Response<Full<Bytes>> {
+  debug!(server_id = %mock_server.id, %method, sub_path, "Received admin request");
+  match (sub_path, method) {
+    ("status", &Method::GET) => json_response(200, mock_server.to_json()),
+    ("interactions", &Method::GET) => json_response(200, json!({
+      "interactions": mock_server.pact.interactions.iter()
+        .map(|interaction| interaction.to_json())
+        .collect::<Vec<_>>()
+    })),
+    ("matches", &Method::GET) => json_response(200, json!({
+      "matches": to_json_list(mock_server.matches())
+    })),
+    ("mismatches", &Method::GET) => json_response(200, json!({
+      "mismatches": to_json_list(mock_server.mismatches())
+    })),
+    ("reset", &Method::POST) => {
+      mock_server.reset();
+      empty_response(204)
+    }
+    ("write-pact", &Method::POST) => {
+      let query = query.and_then(parse_query_string).unwrap_or_default();
+      let output_path = match resolve_output_path(mock_server.config.admin_output_dir.as_deref(),
+        query_value(&query, "outputPath").as_deref()) {
+        Ok(output_path) => output_path,
+        Err(err) => {
+          warn!("Admin request to write the Pact file was rejected - {}", err);
+          return json_response(400, json!({ "error": err }))
+        }
+      };
+      let overwrite = query_value(&query, "overwrite")
+        .map(|value| value == "true")
+        .unwrap_or_default();
+      match mock_server.write_pact(&output_path, overwrite) {
+        Ok(_) => empty_response(204),
+        Err(err) => {
+          warn!("Admin request to write the Pact file failed - {}", err);
+          json_response(500, json!({ "error": format!("Failed to write the Pact file - {}", err) }))
+        }
+      }
+    }
+    ("status" | "interactions" | "matches" | "mismatches" | "reset" | "write-pact", _) => {
+      json_response(405, json!({ "error": format!("{} is not supported for this path", method) }))
+    }
+    _ => json_response(404, json!({ "error": format!("'{}' is not a known admin path", sub_path) }))
+  }
+}
+
+/// Resolves the directory to write the Pact file to for the admin API. The output path from the
+/// request must be a relative path under the output directory of the mock server.
+fn resolve_output_path(output_dir: Option<&str>, output_path: Option<&str>) -> Result<Option<String>, String> {
+  match output_path {
+    Some(output_path) => {
+      let path = Path::new(output_path);
+      if path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        let dir = PathBuf::from(output_dir.unwrap_or(".")).join(path);
+        Ok(Some(dir.to_string_lossy().to_string()))
+      } else {
+        Err(format!("The output path '{}' must be a relative path under the output directory of the mock server", output_path))
+      }
+    }
+    None => Ok(output_dir.map(|dir| dir.to_string()))
+  }
+}
+
+fn to_json_list(results: Vec<MatchResult>) -> Vec<Value> {
+  results.iter().map(|result| result.to_json()).collect()
+}
+
+fn query_value(query: &HashMap<String, Vec<Option<String>>>, name: &str) -> Option<String> {
+  query.get(name)
+    .and_then(|values| values.first().cloned().flatten())
+    .filter(|value| !value.is_empty())
+}
+
+fn json_response(status: u16, body: Value) -> Response<Full<Bytes>> {
+  Response::builder()
+    .status(status)
+    .header(hyper::header::CONTENT_TYPE, "application/json")
+    .body(Full::new(Bytes::from(body.to_string())))
+    .unwrap()
+}
+
+fn empty_response(status: u16) -> Response<Full<Bytes>> {
+  Response::builder()
+    .status(status)
+    .body(Full::new(Bytes::new()))
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+
+  use super::*;
+
+  #[test]
+  fn admin_sub_path_test() {
+    expect!(admin_sub_path("/__pact", "/__pact")).to(be_some().value(""));
+    expect!(admin_sub_path("/__pact", "/__pact/status")).to(be_some().value("status"));
+    expect!(admin_sub_path("/__pact/", "/__pact/status/")).to(be_some().value("status"));
+    expect!(admin_sub_path("/__pact", "/__pactual")).to(be_none());
+    expect!(admin_sub_path("/__pact", "/status")).to(be_none());
+  }
+
+  #[test]
+  fn resolve_output_path_test() {
+    expect!(resolve_output_path(None, None)).to(be_ok().value(None));
+    expect!(resolve_output_path(Some("pacts"), None)).to(be_ok().value(Some("pacts".to_string())));
+    expect!(resolve_output_path(Some("pacts"), Some("consumer/v1")))
+      .to(be_ok().value(Some(PathBuf::from("pacts").join("consumer/v1").to_string_lossy().to_string())));
+    expect!(resolve_output_path(None, Some("./consumer")))
+      .to(be_ok().value(Some(PathBuf::from(".").join("./consumer").to_string_lossy().to_string())));
+    expect!(resolve_output_path(Some("pacts"), Some("../other"))).to(be_err());
+    expect!(resolve_output_path(Some("pacts"), Some("consumer/../../other"))).to(be_err());
+    expect!(resolve_output_path(Some("pacts"), Some("/tmp/pacts"))).to(be_err());
+  }
+}
@@ -0,0 +1,122 @@
+//! Publishing of Pacts to a Pact Broker (or any server that implements the same API). The consumer
+//! version is assigned to the branch and tagged with any tags first, and then the Pact is published
+//! for that consumer version.
+
+use anyhow::{anyhow, Context};
+use pact_models::json_utils::json_to_string;
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+use tracing::{debug, info};
+use url::Url;
+
+/// Authentication to use with the Pact Broker
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrokerAuth {
+  /// Bearer token
+  Token(String),
+  /// Username and optional password for basic authentication
+  Basic(String, Option<String>)
+}
+
+/// Options for publishing a Pact to a Pact Broker
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PublishOptions {
+  /// Base URL of the Pact Broker
+  pub broker_url: String,
+  /// Version of the consumer the Pact is for
+  pub consumer_version: String,
+  /// Branch the consumer version is from
+  pub branch: Option<String>,
+  /// Tags to apply to the consumer version
+  pub tags: Vec<String>,
+  /// Authentication to use
+  pub auth: Option<BrokerAuth>
+}
+
+/// Publishes the Pact JSON to the Pact Broker for the consumer version, assigning the version to
+/// the branch and tags first.
+pub async fn publish_pact(pact: &Value, options: &PublishOptions) -> anyhow::Result<()> {
+  let consumer = pacticipant_name(pact, "consumer")?;
+  let provider = pacticipant_name(pact, "provider")?;
+  if options.consumer_version.is_empty() {
+    return Err(anyhow!("A consumer version is required to publish a pact"));
+  }
+  let version = options.consumer_version.as_str();
+  let client = Client::new();
+
+  if let Some(branch) = &options.branch {
+    let url = broker_url(&options.broker_url, &["pacticipants", &consumer, "branches", branch, "versions", version])?;
+    info!("Adding version {} of '{}' to branch '{}'", version, consumer, branch);
+    send(client.put(url).json(&json!({})), options).await?;
+  }
+
+  for tag in &options.tags {
+    let url = broker_url(&options.broker_url, &["pacticipants", &consumer, "versions", version, "tags", tag])?;
+    info!("Tagging version {} of '{}' with '{}'", version, consumer, tag);
+    send(client.put(url).json(&json!({})), options).await?;
+  }
+
+  let url = broker_url(&options.broker_url, &["pacts", "provider", &provider, "consumer", &consumer, "version", version])?;
+  info!("Publishing pact for '{}' version {} and provider '{}' to {}", consumer, version, provider, url);
+  send(client.put(url).json(pact), options).await
+}
+
+fn pacticipant_name(pact: &Value, key: &str) -> anyhow::Result<String> {
+  pact.get(key)
+    .and_then(|pacticipant| pacticipant.get("name"))
+    .map(json_to_string)
+    .filter(|name| !name.is_empty())
+    .ok_or_else(|| anyhow!("The pact does not have a {} name", key))
+}
+
+/// Appends the path segments to the base URL of the broker, encoding them as required
+fn broker_url(base_url: &str, segments: &[&str]) -> anyhow::Result<Url> {
+  let mut url = Url::parse(base_url)
+    .with_context(|| format!("'{}' is not a valid Pact Broker URL", base_url))?;
+  url.path_segments_mut()
+    .map_err(|_| anyhow!("'{}' is not a valid Pact Broker URL", base_url))?
+    .pop_if_empty()
+    .extend(segments);
+  Ok(url)
+}
+
+async fn send(request: RequestBuilder, options: &PublishOptions) -> anyhow::Result<()> {
+  let request = match &options.auth {
+    Some(BrokerAuth::Token(token)) => request.bearer_auth(token),
+    Some(BrokerAuth::Basic(username, password)) => request.basic_auth(username, password.as_ref()),
+    None => request
+  };
+  let response = request.send().await.context("Failed to send the request to the Pact Broker")?;
+  let status = response.status();
+  debug!("Pact Broker response status: {}", status);
+  if status.is_success() {
+    Ok(())
+  } else {
+    let url = response.url().clone();
+    let body = response.text().await.unwrap_or_default();
+    Err(anyhow!("Request to the Pact Broker '{}' failed with status {} {}", url, status, body.trim()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+
+  use super::*;
+
+  #[test]
+  fn broker_url_test() {
+    expect!(broker_url("http://localhost:9292", &["pacts", "provider", "Order Service"]).unwrap().to_string())
+      .to(be_equal_to("http://localhost:9292/pacts/provider/Order%20Service"));
+    expect!(broker_url("https://broker.example.com/base/", &["pacticipants", "web/app"]).unwrap().to_string())
+      .to(be_equal_to("https://broker.example.com/base/pacticipants/web%2Fapp"));
+    expect!(broker_url("not a url", &["pacts"])).to(be_err());
+  }
+
+  #[test]
+  fn pacticipant_name_test() {
+    let pact = json!({ "consumer": { "name": "web-app" }, "provider": {} });
+    expect!(pacticipant_name(&pact, "consumer").unwrap()).to(be_equal_to("web-app"));
+    expect!(pacticipant_name(&pact, "provider")).to(be_err());
+  }
+}
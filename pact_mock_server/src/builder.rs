@@ -1,6 +1,7 @@
 //! Provides a builder for constructing mock servers
 
 use std::net::Ipv4Addr;
+use std::time::Duration;
 #[allow(unused_imports)] use anyhow::{anyhow, Context};
 use pact_models::pact::Pact;
 use pact_models::PactSpecification;
@@ -14,8 +15,11 @@ use pact_models::v4::pact::V4Pact;
 #[allow(unused_imports)] use tracing::warn;
 
 use crate::configure_core_catalogue;
+use crate::cors::CorsPolicy;
+use crate::guard::MockServerGuard;
 #[allow(deprecated)] use crate::MANAGER;
 use crate::mock_server::{MockServer, MockServerConfig};
+use crate::pact_writer::MergePolicy;
 use crate::server_manager::ServerManager;
 
 /// Builder for constructing mock servers
@@ -83,6 +87,24 @@ impl MockServerBuilder {
     self
   }
 
+  /// Sets the mock server to also bind to the given address, in addition to the main address set
+  /// with `bind_to`, etc. The mock server will share the same match results for all the addresses.
+  /// Specify 0 for the port to use the same port as the main address.
+  pub fn also_bind_to<S: Into<String>>(mut self, address: S) -> Self {
+    self.config.additional_addresses.push(address.into());
+    self
+  }
+
+  /// Sets the mock server to bind to the given port on both the IP4 (`127.0.0.1`) and IP6
+  /// (`[::1]`) loopback adapters. Specify 0 for the port to get a random OS assigned port, which
+  /// will be the same for both adapters. This avoids failures when `localhost` resolves to a
+  /// different adapter than the one the mock server is bound to.
+  pub fn bind_to_loopbacks(mut self, port: u16) -> Self {
+    self.config.address = format!("{}:{}", Ipv4Addr::LOCALHOST, port);
+    self.config.additional_addresses = vec![ format!("[::1]:{}", port) ];
+    self
+  }
+
   /// Provide the config used to setup the mock server. Note that this will override any values
   /// that have been set with functions like `bind_to`, etc.
   pub fn with_config(mut self, config: MockServerConfig) -> Self {
@@ -159,6 +181,77 @@ impl MockServerBuilder {
     self
   }
 
+  /// Sets the CORS policy that controls the `Access-Control-*` headers added to responses. Use
+  /// `CorsPolicy::disabled()` to not add any CORS headers.
+  pub fn with_cors_policy(mut self, cors_policy: CorsPolicy) -> Self {
+    self.config.cors_policy = cors_policy;
+    self
+  }
+
+  /// If generated responses should be compressed using an encoding from the request
+  /// `Accept-Encoding` header. Requires the `compression` feature.
+  pub fn with_response_compression(mut self, compress_responses: bool) -> Self {
+    self.config.compress_responses = compress_responses;
+    self
+  }
+
+  /// If the original case of header names should be preserved for HTTP/1 connections. Request
+  /// headers will be captured with the case they were received with (and recorded in the order they
+  /// were received), and response headers will be written with the case they have in the Pact file.
+  pub fn with_preserve_header_case(mut self, preserve_header_case: bool) -> Self {
+    self.config.preserve_header_case = preserve_header_case;
+    self
+  }
+
+  /// Enables the admin API of the mock server under the given path prefix (i.e. `/__pact`). This
+  /// allows processes that can't use the mock server directly to inspect and control it over HTTP.
+  pub fn with_admin_path<S: Into<String>>(mut self, path: S) -> Self {
+    self.config.admin_path = Some(path.into());
+    self
+  }
+
+  /// Sets the directory that the admin API writes Pact files to. Requests to write the Pact file
+  /// can only give a relative path under this directory.
+  pub fn with_admin_output_dir<S: Into<String>>(mut self, dir: S) -> Self {
+    self.config.admin_output_dir = Some(dir.into());
+    self
+  }
+
+  /// Adds a delay before each response is sent, to simulate a slow provider
+  pub fn with_response_delay(mut self, delay: Duration) -> Self {
+    self.config.response_delay = Some(delay);
+    self
+  }
+
+  /// If writing the Pact file should fail when the Pact has features that can not be represented
+  /// in the Pact specification version it will be written as, instead of logging a warning
+  pub fn with_strict_spec_validation(mut self, strict: bool) -> Self {
+    self.config.strict_spec_validation = strict;
+    self
+  }
+
+  /// If only the interactions that received a matching request should be written to the Pact file.
+  /// Interactions that did not receive a request are still reported as mismatches.
+  pub fn with_exercised_interactions_only(mut self, exercised_only: bool) -> Self {
+    self.config.write_exercised_only = exercised_only;
+    self
+  }
+
+  /// If the number of requests that matched each interaction should be added to the interaction
+  /// comments when the Pact file is written (V4 Pact files only)
+  pub fn with_match_count_annotations(mut self, annotate: bool) -> Self {
+    self.config.annotate_match_counts = annotate;
+    self
+  }
+
+  /// Set how conflicts with the interactions in an existing Pact file are resolved when the Pact
+  /// is merged with it. The default is to replace the conflicting interactions in the Pact file
+  /// with the new ones.
+  pub fn with_merge_policy(mut self, policy: MergePolicy) -> Self {
+    self.config.merge_policy = policy;
+    self
+  }
+
   /// Set the transport to use. The default transports are 'http' and 'https'. Additional transports
   /// can be provided by plugins.
   #[cfg(feature = "plugins")]
@@ -187,6 +280,19 @@ impl MockServerBuilder {
     MockServer::create_https(self.pact.clone(), self.config.clone()).await
   }
 
+  /// Start the mock server, consuming this builder and returning a guard that will shut the mock
+  /// server down and verify it when it is dropped. See `MockServerGuard` for details.
+  pub async fn start_guarded(self) -> anyhow::Result<MockServerGuard> {
+    self.start().await.map(MockServerGuard::new)
+  }
+
+  /// Start the mock server serving HTTPS, consuming this builder and returning a guard that will
+  /// shut the mock server down and verify it when it is dropped. See `MockServerGuard` for details.
+  #[cfg(feature = "tls")]
+  pub async fn start_https_guarded(self) -> anyhow::Result<MockServerGuard> {
+    self.start_https().await.map(MockServerGuard::new)
+  }
+
   /// Starts the mockserver, consuming this builder and registers it with the global server manager.
   /// The mock server tasks will be spawned on the server manager's runtime.
   /// Returns the mock server instance.
@@ -310,6 +416,66 @@ mod tests {
     expect!(mismatches).to(be_equal_to(vec![]));
   }
 
+  #[test_log::test]
+  fn mock_server_bound_to_multiple_addresses() {
+    if std::env::var("NO_IP6").is_err() {
+      let pact = V4Pact {
+        interactions: vec![
+          SynchronousHttp {
+            request: HttpRequest {
+              path: "/ip4".to_string(),
+              .. HttpRequest::default()
+            },
+            .. SynchronousHttp::default()
+          }.boxed_v4(),
+          SynchronousHttp {
+            request: HttpRequest {
+              path: "/ip6".to_string(),
+              .. HttpRequest::default()
+            },
+            .. SynchronousHttp::default()
+          }.boxed_v4()
+        ],
+        .. V4Pact::default()
+      };
+
+      let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+      let mut mock_server = runtime.block_on(async {
+        MockServerBuilder::new()
+          .bind_to_loopbacks(0)
+          .with_v4_pact(pact)
+          .start()
+          .await
+          .unwrap()
+      });
+
+      let port = mock_server.port();
+      expect!(mock_server.addresses().iter().map(|a| a.to_string()).collect::<Vec<_>>()).to(be_equal_to(vec![
+        format!("127.0.0.1:{}", port),
+        format!("[::1]:{}", port)
+      ]));
+      expect!(mock_server.urls()).to(be_equal_to(vec![
+        format!("http://127.0.0.1:{}", port),
+        format!("http://[::1]:{}", port)
+      ]));
+
+      let client = reqwest::blocking::Client::new();
+      let response1 = client.get(format!("http://127.0.0.1:{}/ip4", port).as_str()).send();
+      let response2 = client.get(format!("http://[::1]:{}/ip6", port).as_str()).send();
+
+      mock_server.shutdown().unwrap();
+
+      expect!(response1.unwrap().status()).to(be_equal_to(200));
+      expect!(response2.unwrap().status()).to(be_equal_to(200));
+      expect!(mock_server.matches().len()).to(be_equal_to(2));
+      expect!(mock_server.all_matched()).to(be_true());
+    }
+  }
+
   #[test_log::test]
   #[cfg(feature = "tls")]
   fn basic_mock_server_https_test() {
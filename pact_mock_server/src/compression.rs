@@ -0,0 +1,245 @@
+//! Support for compressed request and response bodies (the `Content-Encoding` header)
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use itertools::Itertools;
+
+/// Maximum size of a decoded body (64 MiB). Decoding a body that would be larger than this fails,
+/// so a small compressed request can not make the mock server use a lot of memory.
+pub const MAX_DECODED_BODY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Content encodings that are supported by the mock server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+  /// GZip (`gzip` or `x-gzip`)
+  Gzip,
+  /// Deflate (`deflate`). This is the zlib format, but raw deflate streams are also accepted.
+  Deflate,
+  /// Brotli (`br`)
+  Brotli,
+  /// Zstandard (`zstd`)
+  Zstd,
+  /// No encoding (`identity`)
+  Identity
+}
+
+impl ContentEncoding {
+  /// Parse a single content encoding token. Returns `None` if the encoding is not supported.
+  pub fn parse(value: &str) -> Option<ContentEncoding> {
+    match value.trim().to_lowercase().as_str() {
+      "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+      "deflate" => Some(ContentEncoding::Deflate),
+      "br" => Some(ContentEncoding::Brotli),
+      "zstd" => Some(ContentEncoding::Zstd),
+      "identity" => Some(ContentEncoding::Identity),
+      _ => None
+    }
+  }
+
+  /// Value to use in the `Content-Encoding` header
+  pub fn header_value(&self) -> &'static str {
+    match self {
+      ContentEncoding::Gzip => "gzip",
+      ContentEncoding::Deflate => "deflate",
+      ContentEncoding::Brotli => "br",
+      ContentEncoding::Zstd => "zstd",
+      ContentEncoding::Identity => "identity"
+    }
+  }
+
+  /// Decode the bytes using this encoding. Fails if the decoded data would be larger than
+  /// [`MAX_DECODED_BODY_SIZE`].
+  pub fn decode(&self, data: &[u8]) -> anyhow::Result<Bytes> {
+    self.decode_with_limit(data, MAX_DECODED_BODY_SIZE)
+  }
+
+  /// Decode the bytes using this encoding. Fails if the decoded data would be larger than the
+  /// limit (in bytes).
+  pub fn decode_with_limit(&self, data: &[u8], limit: u64) -> anyhow::Result<Bytes> {
+    let mut buffer = vec![];
+    match self {
+      ContentEncoding::Gzip => {
+        read_limited(flate2::read::MultiGzDecoder::new(data), limit, &mut buffer)?;
+      }
+      ContentEncoding::Deflate => {
+        // Some clients send a raw deflate stream instead of the zlib format
+        if let Err(err) = read_limited(flate2::read::ZlibDecoder::new(data), limit, &mut buffer) {
+          if buffer.len() as u64 > limit {
+            return Err(err);
+          }
+          buffer.clear();
+          read_limited(flate2::read::DeflateDecoder::new(data), limit, &mut buffer)?;
+        }
+      }
+      ContentEncoding::Brotli => {
+        read_limited(brotli::Decompressor::new(data, 4096), limit, &mut buffer)?;
+      }
+      ContentEncoding::Zstd => {
+        read_limited(zstd::stream::read::Decoder::new(data)?, limit, &mut buffer)?;
+      }
+      ContentEncoding::Identity => read_limited(data, limit, &mut buffer)?
+    }
+    Ok(Bytes::from(buffer))
+  }
+
+  /// Encode the bytes using this encoding
+  pub fn encode(&self, data: &[u8]) -> anyhow::Result<Bytes> {
+    let buffer = match self {
+      ContentEncoding::Gzip => {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()?
+      }
+      ContentEncoding::Deflate => {
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()?
+      }
+      ContentEncoding::Brotli => {
+        let mut buffer = vec![];
+        {
+          let mut encoder = brotli::CompressorWriter::new(&mut buffer, 4096, 5, 22);
+          encoder.write_all(data)?;
+        }
+        buffer
+      }
+      ContentEncoding::Zstd => zstd::stream::encode_all(data, 0)?,
+      ContentEncoding::Identity => data.to_vec()
+    };
+    Ok(Bytes::from(buffer))
+  }
+}
+
+/// Reads all the data from the reader into the buffer, failing if there is more than the limit
+fn read_limited<R: Read>(reader: R, limit: u64, buffer: &mut Vec<u8>) -> anyhow::Result<()> {
+  reader.take(limit.saturating_add(1)).read_to_end(buffer)?;
+  if buffer.len() as u64 > limit {
+    Err(anyhow!("Decoded body is larger than the maximum size of {} bytes", limit))
+  } else {
+    Ok(())
+  }
+}
+
+/// Parses the value of a `Content-Encoding` header into the list of encodings that have been
+/// applied, in the order they were applied. Returns an error if any of the encodings are not
+/// supported.
+pub fn parse_content_encoding(values: &[String]) -> anyhow::Result<Vec<ContentEncoding>> {
+  values.iter()
+    .flat_map(|value| value.split(','))
+    .map(|value| value.trim())
+    .filter(|value| !value.is_empty())
+    .map(|value| ContentEncoding::parse(value)
+      .ok_or_else(|| anyhow!("'{}' is not a supported content encoding", value)))
+    .filter(|result| !matches!(result, Ok(ContentEncoding::Identity)))
+    .collect()
+}
+
+/// Decodes a body that has had the given encodings applied (in the order they were applied)
+pub fn decode_body(body: &[u8], encodings: &[ContentEncoding]) -> anyhow::Result<Bytes> {
+  let mut data = Bytes::copy_from_slice(body);
+  for encoding in encodings.iter().rev() {
+    data = encoding.decode(&data)?;
+  }
+  Ok(data)
+}
+
+/// Selects the preferred supported encoding from the values of an `Accept-Encoding` header,
+/// taking any quality values into account. Returns `None` if no supported encoding is acceptable.
+pub fn select_accepted_encoding(values: &[String]) -> Option<ContentEncoding> {
+  values.iter()
+    .flat_map(|value| value.split(','))
+    .filter_map(|value| {
+      let mut parts = value.split(';');
+      let encoding = parts.next().unwrap_or_default().trim();
+      let quality = parts
+        .filter_map(|param| param.trim().strip_prefix("q="))
+        .next()
+        .and_then(|q| q.trim().parse::<f32>().ok())
+        .unwrap_or(1.0);
+      ContentEncoding::parse(encoding)
+        .filter(|encoding| *encoding != ContentEncoding::Identity && quality > 0.0)
+        .map(|encoding| (encoding, quality))
+    })
+    .sorted_by(|(_, q1), (_, q2)| q2.total_cmp(q1))
+    .map(|(encoding, _)| encoding)
+    .next()
+}
+
+/// Looks up a header value, ignoring the case of the header name
+pub(crate) fn find_header<'a>(
+  headers: &'a Option<HashMap<String, Vec<String>>>,
+  name: &str
+) -> Option<&'a Vec<String>> {
+  headers.as_ref()
+    .and_then(|headers| headers.iter()
+      .find(|(k, _)| k.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v))
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+
+  use super::*;
+
+  #[test]
+  fn parse_content_encoding_test() {
+    expect!(parse_content_encoding(&[]).unwrap()).to(be_equal_to(vec![]));
+    expect!(parse_content_encoding(&["gzip".to_string()]).unwrap())
+      .to(be_equal_to(vec![ContentEncoding::Gzip]));
+    expect!(parse_content_encoding(&["identity".to_string()]).unwrap()).to(be_equal_to(vec![]));
+    expect!(parse_content_encoding(&["deflate, br".to_string(), "zstd".to_string()]).unwrap())
+      .to(be_equal_to(vec![ContentEncoding::Deflate, ContentEncoding::Brotli, ContentEncoding::Zstd]));
+    expect!(parse_content_encoding(&["compress".to_string()])).to(be_err());
+  }
+
+  #[test]
+  fn round_trip_all_encodings() {
+    let data = b"{\"id\": 100, \"name\": \"a very compressible name name name name\"}";
+    for encoding in [ContentEncoding::Gzip, ContentEncoding::Deflate, ContentEncoding::Brotli,
+      ContentEncoding::Zstd, ContentEncoding::Identity] {
+      let encoded = encoding.encode(data).unwrap();
+      expect!(encoding.decode(&encoded).unwrap()).to(be_equal_to(Bytes::from_static(data)));
+    }
+  }
+
+  #[test]
+  fn decode_body_applies_encodings_in_reverse() {
+    let data = b"hello world";
+    let gzipped = ContentEncoding::Gzip.encode(data).unwrap();
+    let encoded = ContentEncoding::Brotli.encode(&gzipped).unwrap();
+    expect!(decode_body(&encoded, &[ContentEncoding::Gzip, ContentEncoding::Brotli]).unwrap())
+      .to(be_equal_to(Bytes::from_static(data)));
+  }
+
+  #[test]
+  fn decode_accepts_raw_deflate_streams() {
+    let mut encoder = flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(b"raw deflate").unwrap();
+    let encoded = encoder.finish().unwrap();
+    expect!(ContentEncoding::Deflate.decode(&encoded).unwrap()).to(be_equal_to(Bytes::from_static(b"raw deflate")));
+  }
+
+  #[test]
+  fn decode_fails_if_the_decoded_data_is_larger_than_the_limit() {
+    let data = vec![b'a'; 10_000];
+    for encoding in [ContentEncoding::Gzip, ContentEncoding::Deflate, ContentEncoding::Brotli,
+      ContentEncoding::Zstd, ContentEncoding::Identity] {
+      let encoded = encoding.encode(&data).unwrap();
+      expect!(encoding.decode_with_limit(&encoded, 10_000).unwrap().len()).to(be_equal_to(10_000));
+      expect!(encoding.decode_with_limit(&encoded, 9_999)).to(be_err());
+    }
+  }
+
+  #[test]
+  fn select_accepted_encoding_test() {
+    expect!(select_accepted_encoding(&[])).to(be_none());
+    expect!(select_accepted_encoding(&["identity".to_string()])).to(be_none());
+    expect!(select_accepted_encoding(&["compress, gzip".to_string()])).to(be_some().value(ContentEncoding::Gzip));
+    expect!(select_accepted_encoding(&["gzip;q=0.5, br".to_string()])).to(be_some().value(ContentEncoding::Brotli));
+    expect!(select_accepted_encoding(&["gzip;q=0, zstd;q=0.1".to_string()])).to(be_some().value(ContentEncoding::Zstd));
+  }
+}
@@ -0,0 +1,255 @@
+//! CORS (Cross-Origin Resource Sharing) policy applied to the responses from a mock server
+
+use hyper::header::{
+  ACCESS_CONTROL_ALLOW_CREDENTIALS,
+  ACCESS_CONTROL_ALLOW_HEADERS,
+  ACCESS_CONTROL_ALLOW_METHODS,
+  ACCESS_CONTROL_ALLOW_ORIGIN,
+  ACCESS_CONTROL_EXPOSE_HEADERS,
+  ACCESS_CONTROL_MAX_AGE,
+  HeaderName
+};
+use pact_models::json_utils::json_to_string;
+use serde_json::Value;
+
+use crate::utils::json_to_bool;
+
+/// CORS policy for a mock server. This controls the `Access-Control-*` headers that are added to
+/// responses, and the responses to CORS pre-flight requests (if they have been enabled with the
+/// `cors_preflight` config setting).
+///
+/// The default policy allows any origin, method and header with credentials, which matches the
+/// behaviour of previous versions of the mock server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsPolicy {
+  /// If CORS headers should be added to responses. If this is false, no `Access-Control-*`
+  /// headers will be added to any response, and pre-flight requests will not be responded to.
+  pub enabled: bool,
+  /// Origins that are allowed. An empty list (or a list containing `*`) allows any origin. The
+  /// origin from the request is returned if it is allowed, otherwise no CORS headers are added.
+  pub allowed_origins: Vec<String>,
+  /// Methods that are allowed
+  pub allowed_methods: Vec<String>,
+  /// Request headers that are allowed. An empty list allows any header.
+  pub allowed_headers: Vec<String>,
+  /// Response headers that the browser is allowed to expose
+  pub exposed_headers: Vec<String>,
+  /// If the `Access-Control-Allow-Credentials` header should be set
+  pub allow_credentials: bool,
+  /// Number of seconds that the result of a pre-flight request can be cached for
+  pub max_age: Option<u64>
+}
+
+impl CorsPolicy {
+  /// Policy that will not add any CORS headers to responses
+  pub fn disabled() -> Self {
+    CorsPolicy {
+      enabled: false,
+      .. CorsPolicy::default()
+    }
+  }
+
+  /// Convert a JSON value into a CORS policy. This method is tolerant of invalid JSON formats.
+  /// A boolean value can be used to enable or disable the default policy.
+  pub fn from_json(value: &Value) -> CorsPolicy {
+    let mut policy = CorsPolicy::default();
+
+    match value {
+      Value::Bool(enabled) => policy.enabled = *enabled,
+      Value::Object(map) => for (k, v) in map {
+        match k.as_str() {
+          "enabled" => policy.enabled = json_to_bool(v).unwrap_or(true),
+          "allowedOrigins" => policy.allowed_origins = json_to_list(v),
+          "allowedMethods" => policy.allowed_methods = json_to_list(v),
+          "allowedHeaders" => policy.allowed_headers = json_to_list(v),
+          "exposedHeaders" => policy.exposed_headers = json_to_list(v),
+          "allowCredentials" => policy.allow_credentials = json_to_bool(v).unwrap_or_default(),
+          "maxAge" => policy.max_age = v.as_u64().or_else(|| json_to_string(v).parse().ok()),
+          _ => {}
+        }
+      },
+      _ => {}
+    }
+
+    policy
+  }
+
+  /// Returns the value for the `Access-Control-Allow-Origin` header for a request with the given
+  /// `Origin` header, or `None` if CORS is disabled or the origin is not allowed.
+  pub fn allow_origin(&self, origin: Option<&str>) -> Option<String> {
+    if !self.enabled {
+      return None;
+    }
+
+    let any_origin = self.allowed_origins.is_empty() || self.allowed_origins.iter().any(|o| o == "*");
+    match origin {
+      Some(origin) if any_origin || self.allowed_origins.iter().any(|o| o.eq_ignore_ascii_case(origin)) =>
+        Some(origin.to_string()),
+      None if any_origin => Some("*".to_string()),
+      _ => None
+    }
+  }
+
+  /// Returns the CORS headers to add to a response for a request with the given `Origin` header
+  pub fn response_headers(&self, origin: Option<&str>) -> Vec<(HeaderName, String)> {
+    match self.allow_origin(origin) {
+      Some(allow_origin) => {
+        let mut headers = vec![
+          (ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin),
+          (ACCESS_CONTROL_ALLOW_HEADERS, self.allowed_headers_value(None)),
+          (ACCESS_CONTROL_ALLOW_METHODS, self.allowed_methods.join(", "))
+        ];
+        self.add_common_headers(&mut headers);
+        headers
+      }
+      None => vec![]
+    }
+  }
+
+  /// Returns the headers to add to the response to a CORS pre-flight request, given the request
+  /// `Origin` and `Access-Control-Request-Headers` headers.
+  pub fn preflight_headers(&self, origin: Option<&str>, request_headers: Option<&str>) -> Vec<(HeaderName, String)> {
+    match self.allow_origin(origin) {
+      Some(allow_origin) => {
+        let mut headers = vec![
+          (ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin),
+          (ACCESS_CONTROL_ALLOW_METHODS, self.allowed_methods.join(", ")),
+          (ACCESS_CONTROL_ALLOW_HEADERS, self.allowed_headers_value(request_headers))
+        ];
+        self.add_common_headers(&mut headers);
+        if let Some(max_age) = self.max_age {
+          headers.push((ACCESS_CONTROL_MAX_AGE, max_age.to_string()));
+        }
+        headers
+      }
+      None => vec![]
+    }
+  }
+
+  fn allowed_headers_value(&self, request_headers: Option<&str>) -> String {
+    if self.allowed_headers.is_empty() {
+      match request_headers {
+        Some(headers) if !headers.is_empty() => format!("{}, *", headers),
+        _ => "*".to_string()
+      }
+    } else {
+      self.allowed_headers.join(", ")
+    }
+  }
+
+  fn add_common_headers(&self, headers: &mut Vec<(HeaderName, String)>) {
+    if !self.exposed_headers.is_empty() {
+      headers.push((ACCESS_CONTROL_EXPOSE_HEADERS, self.exposed_headers.join(", ")));
+    }
+    if self.allow_credentials {
+      headers.push((ACCESS_CONTROL_ALLOW_CREDENTIALS, "true".to_string()));
+    }
+  }
+}
+
+impl Default for CorsPolicy {
+  fn default() -> Self {
+    CorsPolicy {
+      enabled: true,
+      allowed_origins: vec![],
+      allowed_methods: ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"]
+        .iter().map(|m| m.to_string()).collect(),
+      allowed_headers: vec![],
+      exposed_headers: vec!["Location".to_string(), "Link".to_string()],
+      allow_credentials: true,
+      max_age: None
+    }
+  }
+}
+
+fn json_to_list(value: &Value) -> Vec<String> {
+  match value {
+    Value::Array(values) => values.iter().map(json_to_string).collect(),
+    Value::Null => vec![],
+    _ => json_to_string(value).split(',')
+      .map(|v| v.trim().to_string())
+      .filter(|v| !v.is_empty())
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn default_policy_echoes_the_origin() {
+    let policy = CorsPolicy::default();
+    expect!(policy.allow_origin(Some("http://localhost:3000"))).to(be_some().value("http://localhost:3000"));
+    expect!(policy.allow_origin(None)).to(be_some().value("*"));
+
+    let headers = policy.response_headers(Some("http://localhost:3000"));
+    expect!(headers.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>()).to(be_equal_to(vec![
+      "access-control-allow-origin",
+      "access-control-allow-headers",
+      "access-control-allow-methods",
+      "access-control-expose-headers",
+      "access-control-allow-credentials"
+    ]));
+  }
+
+  #[test]
+  fn disabled_policy_adds_no_headers() {
+    let policy = CorsPolicy::disabled();
+    expect!(policy.allow_origin(Some("http://localhost:3000"))).to(be_none());
+    expect!(policy.response_headers(Some("http://localhost:3000"))).to(be_equal_to(vec![]));
+    expect!(policy.preflight_headers(None, Some("x-token"))).to(be_equal_to(vec![]));
+  }
+
+  #[test]
+  fn restricts_origins() {
+    let policy = CorsPolicy {
+      allowed_origins: vec!["https://example.com".to_string()],
+      .. CorsPolicy::default()
+    };
+    expect!(policy.allow_origin(Some("https://example.com"))).to(be_some().value("https://example.com"));
+    expect!(policy.allow_origin(Some("https://other.com"))).to(be_none());
+    expect!(policy.allow_origin(None)).to(be_none());
+  }
+
+  #[test]
+  fn preflight_headers_test() {
+    let policy = CorsPolicy {
+      allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+      allow_credentials: false,
+      exposed_headers: vec![],
+      max_age: Some(600),
+      .. CorsPolicy::default()
+    };
+    expect!(policy.preflight_headers(Some("https://example.com"), Some("x-token"))).to(be_equal_to(vec![
+      (ACCESS_CONTROL_ALLOW_ORIGIN, "https://example.com".to_string()),
+      (ACCESS_CONTROL_ALLOW_METHODS, "GET, POST".to_string()),
+      (ACCESS_CONTROL_ALLOW_HEADERS, "x-token, *".to_string()),
+      (ACCESS_CONTROL_MAX_AGE, "600".to_string())
+    ]));
+  }
+
+  #[test]
+  fn from_json_test() {
+    expect!(CorsPolicy::from_json(&json!(false))).to(be_equal_to(CorsPolicy::disabled()));
+    expect!(CorsPolicy::from_json(&json!({
+      "allowedOrigins": ["https://example.com"],
+      "allowedMethods": "GET, POST",
+      "allowedHeaders": ["Authorization"],
+      "exposedHeaders": [],
+      "allowCredentials": false,
+      "maxAge": 60
+    }))).to(be_equal_to(CorsPolicy {
+      enabled: true,
+      allowed_origins: vec!["https://example.com".to_string()],
+      allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+      allowed_headers: vec!["Authorization".to_string()],
+      exposed_headers: vec![],
+      allow_credentials: false,
+      max_age: Some(60)
+    }));
+  }
+}
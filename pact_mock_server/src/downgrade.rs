@@ -0,0 +1,270 @@
+//! Validation of a Pact before it is written as an older version of the Pact specification.
+//! Mock servers always work with V4 Pacts, and any features that can not be represented in the
+//! specification version the Pact file is written as (i.e. V4 matching rules or message
+//! interactions in a V3 Pact) will be silently dropped or written in a form that other Pact
+//! implementations will not understand.
+
+use std::fmt::{Display, Formatter};
+
+use pact_models::generators::{Generator, Generators};
+use pact_models::matchingrules::{Category, MatchingRule, MatchingRules};
+use pact_models::PactSpecification;
+use pact_models::v4::interaction::V4Interaction;
+use pact_models::v4::pact::V4Pact;
+
+/// Feature of a Pact that can not be represented in the specification version it is being
+/// written as
+#[derive(Debug, Clone, PartialEq)]
+pub struct DowngradeIssue {
+  /// Description of the interaction the issue is for, or `None` if it is for the Pact itself
+  pub interaction: Option<String>,
+  /// Description of the feature that will be lost
+  pub message: String
+}
+
+impl DowngradeIssue {
+  fn pact(message: String) -> Self {
+    DowngradeIssue { interaction: None, message }
+  }
+
+  fn interaction(interaction: &dyn V4Interaction, message: String) -> Self {
+    DowngradeIssue { interaction: Some(interaction.description()), message }
+  }
+}
+
+impl Display for DowngradeIssue {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match &self.interaction {
+      Some(interaction) => write!(f, "Interaction '{}': {}", interaction, self.message),
+      None => write!(f, "{}", self.message)
+    }
+  }
+}
+
+/// Returns all the features of the Pact that can not be represented in the given specification
+/// version. Returns an empty list for V4 or an unknown version.
+pub fn validate_downgrade(pact: &V4Pact, specification: PactSpecification) -> Vec<DowngradeIssue> {
+  if specification == PactSpecification::Unknown || specification >= PactSpecification::V4 {
+    return vec![];
+  }
+
+  let mut issues = vec![];
+  if !pact.plugin_data.is_empty() {
+    issues.push(DowngradeIssue::pact(format!("plugin data is not supported in {} Pact files", specification)));
+  }
+  for interaction in &pact.interactions {
+    validate_interaction(interaction.as_ref(), specification, &mut issues);
+  }
+  issues
+}
+
+fn validate_interaction(interaction: &dyn V4Interaction, specification: PactSpecification, issues: &mut Vec<DowngradeIssue>) {
+  let mut add_issue = |message: String| issues.push(DowngradeIssue::interaction(interaction, message));
+
+  if !interaction.plugin_config().is_empty() {
+    add_issue(format!("plugin configuration is not supported in {} Pact files", specification));
+  }
+  if !interaction.comments().is_empty() {
+    add_issue(format!("comments are not supported in {} Pact files", specification));
+  }
+  if interaction.pending() {
+    add_issue(format!("pending interactions are not supported in {} Pact files", specification));
+  }
+
+  let provider_states = interaction.provider_states();
+  if specification < PactSpecification::V3 {
+    if provider_states.len() > 1 {
+      add_issue(format!("only the first provider state will be written to a {} Pact file", specification));
+    }
+    if provider_states.iter().any(|state| !state.params.is_empty()) {
+      add_issue(format!("provider state parameters are not supported in {} Pact files", specification));
+    }
+  }
+
+  match interaction.as_v4_http() {
+    Some(http) => {
+      validate_matching_rules("request", &http.request.matching_rules, specification, &mut add_issue);
+      validate_generators("request", &http.request.generators, specification, &mut add_issue);
+      validate_matching_rules("response", &http.response.matching_rules, specification, &mut add_issue);
+      validate_generators("response", &http.response.generators, specification, &mut add_issue);
+    }
+    None => add_issue(format!("{} interactions can not be written to a {} HTTP Pact file",
+      interaction.type_of(), specification))
+  }
+}
+
+fn validate_matching_rules(
+  part: &str,
+  matching_rules: &MatchingRules,
+  specification: PactSpecification,
+  add_issue: &mut impl FnMut(String)
+) {
+  for (category, rules) in &matching_rules.rules {
+    if *category == Category::STATUS && !rules.is_empty() {
+      add_issue(format!("matching rules on the {} status are not supported in {} Pact files", part, specification));
+      continue;
+    }
+    for (path, rule_list) in &rules.rules {
+      if specification < PactSpecification::V3 && rule_list.rules.len() > 1 {
+        add_issue(format!("multiple matching rules for {} {} '{}' are not supported in {} Pact files",
+          part, category, path, specification));
+      }
+      for rule in &rule_list.rules {
+        let required = matching_rule_specification(rule);
+        if required > specification {
+          add_issue(format!("'{}' matching rule for {} {} '{}' requires a {} Pact file",
+            rule.name(), part, category, path, required));
+        }
+      }
+    }
+  }
+}
+
+fn validate_generators(
+  part: &str,
+  generators: &Generators,
+  specification: PactSpecification,
+  add_issue: &mut impl FnMut(String)
+) {
+  for (category, generators) in &generators.categories {
+    for (path, generator) in generators {
+      let required = generator_specification(generator);
+      if required > specification {
+        let category: &str = (*category).into();
+        add_issue(format!("'{}' generator for {} {} '{}' requires a {} Pact file",
+          generator.name(), part, category, path, required));
+      }
+    }
+  }
+}
+
+/// Minimum specification version that supports the matching rule
+fn matching_rule_specification(rule: &MatchingRule) -> PactSpecification {
+  match rule {
+    MatchingRule::Regex(_) | MatchingRule::Type | MatchingRule::MinType(_) | MatchingRule::MaxType(_) |
+    MatchingRule::MinMaxType(_, _) => PactSpecification::V2,
+    MatchingRule::ArrayContains(_) | MatchingRule::StatusCode(_) | MatchingRule::NotEmpty |
+    MatchingRule::Semver | MatchingRule::EachKey(_) | MatchingRule::EachValue(_) => PactSpecification::V4,
+    _ => PactSpecification::V3
+  }
+}
+
+/// Minimum specification version that supports the generator
+fn generator_specification(generator: &Generator) -> PactSpecification {
+  match generator {
+    Generator::ProviderStateGenerator(_, _) | Generator::MockServerURL(_, _) |
+    Generator::ArrayContains(_) => PactSpecification::V4,
+    _ => PactSpecification::V3
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use maplit::hashmap;
+  use pact_models::generators;
+  use pact_models::matchingrules;
+  use pact_models::provider_states::ProviderState;
+  use pact_models::v4::http_parts::{HttpRequest, HttpResponse};
+  use pact_models::v4::message_parts::MessageContents;
+  use pact_models::v4::async_message::AsynchronousMessage;
+  use pact_models::v4::synch_http::SynchronousHttp;
+  use serde_json::json;
+
+  use super::*;
+
+  fn messages(issues: &[DowngradeIssue]) -> Vec<String> {
+    issues.iter().map(|issue| issue.to_string()).collect()
+  }
+
+  #[test]
+  fn no_issues_for_v4_or_a_simple_pact() {
+    let pact = V4Pact {
+      interactions: vec![ SynchronousHttp::default().boxed_v4() ],
+      .. V4Pact::default()
+    };
+    expect!(validate_downgrade(&pact, PactSpecification::V4).iter()).to(be_empty());
+    expect!(validate_downgrade(&pact, PactSpecification::V3).iter()).to(be_empty());
+    expect!(validate_downgrade(&pact, PactSpecification::V2).iter()).to(be_empty());
+  }
+
+  #[test]
+  fn reports_matching_rules_and_generators_not_supported_by_the_version() {
+    let pact = V4Pact {
+      interactions: vec![
+        SynchronousHttp {
+          description: "get orders".to_string(),
+          request: HttpRequest {
+            matching_rules: matchingrules! {
+              "query" => { "id" => [ MatchingRule::Integer ] }
+            },
+            .. HttpRequest::default()
+          },
+          response: HttpResponse {
+            matching_rules: matchingrules! {
+              "body" => {
+                "$.name" => [ MatchingRule::Type ],
+                "$.tags" => [ MatchingRule::NotEmpty ]
+              }
+            },
+            generators: generators! {
+              "BODY" => { "$.id" => Generator::RandomInt(1, 10) }
+            },
+            .. HttpResponse::default()
+          },
+          .. SynchronousHttp::default()
+        }.boxed_v4()
+      ],
+      .. V4Pact::default()
+    };
+
+    expect!(messages(&validate_downgrade(&pact, PactSpecification::V3))).to(be_equal_to(vec![
+      "Interaction 'get orders': 'not-empty' matching rule for response body '$.tags' requires a V4 Pact file".to_string()
+    ]));
+
+    let mut v2_issues = messages(&validate_downgrade(&pact, PactSpecification::V2));
+    v2_issues.sort();
+    expect!(v2_issues).to(be_equal_to(vec![
+      "Interaction 'get orders': 'RandomInt' generator for response body '$.id' requires a V3 Pact file".to_string(),
+      "Interaction 'get orders': 'integer' matching rule for request query 'id' requires a V3 Pact file".to_string(),
+      "Interaction 'get orders': 'not-empty' matching rule for response body '$.tags' requires a V4 Pact file".to_string()
+    ]));
+  }
+
+  #[test]
+  fn reports_v4_only_interaction_features() {
+    let pact = V4Pact {
+      interactions: vec![
+        SynchronousHttp {
+          description: "get orders".to_string(),
+          provider_states: vec![
+            ProviderState { name: "an order exists".to_string(), params: hashmap!{ "id".to_string() => json!(100) } },
+            ProviderState::default("a user exists")
+          ],
+          comments: hashmap!{ "text".to_string() => json!(["a comment"]) },
+          pending: true,
+          .. SynchronousHttp::default()
+        }.boxed_v4(),
+        AsynchronousMessage {
+          description: "an order event".to_string(),
+          contents: MessageContents::default(),
+          .. AsynchronousMessage::default()
+        }.boxed_v4()
+      ],
+      .. V4Pact::default()
+    };
+
+    expect!(messages(&validate_downgrade(&pact, PactSpecification::V3))).to(be_equal_to(vec![
+      "Interaction 'get orders': comments are not supported in V3 Pact files".to_string(),
+      "Interaction 'get orders': pending interactions are not supported in V3 Pact files".to_string(),
+      "Interaction 'an order event': V4 Asynchronous/Messages interactions can not be written to a V3 HTTP Pact file".to_string()
+    ]));
+    expect!(messages(&validate_downgrade(&pact, PactSpecification::V2))).to(be_equal_to(vec![
+      "Interaction 'get orders': comments are not supported in V2 Pact files".to_string(),
+      "Interaction 'get orders': pending interactions are not supported in V2 Pact files".to_string(),
+      "Interaction 'get orders': only the first provider state will be written to a V2 Pact file".to_string(),
+      "Interaction 'get orders': provider state parameters are not supported in V2 Pact files".to_string(),
+      "Interaction 'an order event': V4 Asynchronous/Messages interactions can not be written to a V2 HTTP Pact file".to_string()
+    ]));
+  }
+}
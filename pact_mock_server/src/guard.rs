@@ -0,0 +1,263 @@
+//! Guard that automatically shuts a mock server down when it goes out of scope. This is useful in
+//! tests, as the mock server will be shut down and verified even if the test panics before the
+//! end of the test is reached.
+
+use std::fmt::Write;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::task::block_in_place;
+use tracing::{debug, error, warn};
+
+use crate::mock_server::MockServer;
+
+/// Time to wait for in-flight requests to complete when the guard shuts the mock server down
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// RAII guard for a running mock server. When the guard is dropped, the mock server is shut down
+/// and then (unless disabled) verified, panicking with a report of the mismatches if any requests
+/// did not match. It can also write the pact file if all the requests matched.
+///
+/// The guard dereferences to the mock server, so can be used anywhere the mock server can.
+#[derive(Debug)]
+pub struct MockServerGuard {
+  mock_server: Option<MockServer>,
+  verify: bool,
+  write_pact: Option<Option<String>>,
+  overwrite: bool
+}
+
+impl MockServerGuard {
+  /// Create a new guard for the mock server. By default, the mock server will be verified when
+  /// the guard is dropped, but the pact file will not be written.
+  pub fn new(mock_server: MockServer) -> Self {
+    MockServerGuard {
+      mock_server: Some(mock_server),
+      verify: true,
+      write_pact: None,
+      overwrite: false
+    }
+  }
+
+  /// If the guard should panic with a mismatch report when it is dropped and not all the
+  /// requests matched. Defaults to true.
+  pub fn with_verification(mut self, verify: bool) -> Self {
+    self.verify = verify;
+    self
+  }
+
+  /// Write the pact file to the output directory (or the current directory if `None`) when the
+  /// guard is dropped and all the requests matched. If `overwrite` is false, the interactions will
+  /// be merged with any existing pact file.
+  pub fn write_pact_on_success(mut self, output_path: Option<String>, overwrite: bool) -> Self {
+    self.write_pact = Some(output_path);
+    self.overwrite = overwrite;
+    self
+  }
+
+  /// Returns the mock server, without shutting it down or verifying it
+  pub fn into_inner(mut self) -> MockServer {
+    self.mock_server.take().expect("Mock server has already been taken from the guard")
+  }
+
+  /// Shuts the mock server down (if it has not already been shut down), and then returns an error
+  /// with the mismatch report if not all the requests matched. If all the requests matched and
+  /// the guard was configured to write the pact file, it will be written.
+  pub fn finish(mut self) -> anyhow::Result<()> {
+    match self.mock_server.take() {
+      Some(mut mock_server) => self.shutdown_and_verify(&mut mock_server),
+      None => Ok(())
+    }
+  }
+
+  fn shutdown_and_verify(&self, mock_server: &mut MockServer) -> anyhow::Result<()> {
+    if let Err(err) = shutdown(mock_server) {
+      debug!("Mock server {} was not shut down by the guard: {}", mock_server.id, err);
+    }
+
+    let mismatches = mock_server.mismatches();
+    if mismatches.is_empty() {
+      match &self.write_pact {
+        Some(output_path) => mock_server.write_pact(output_path, self.overwrite),
+        None => Ok(())
+      }
+    } else if self.verify {
+      Err(anyhow::anyhow!(mismatch_report(mock_server)))
+    } else {
+      warn!("{}", mismatch_report(mock_server));
+      Ok(())
+    }
+  }
+}
+
+impl Deref for MockServerGuard {
+  type Target = MockServer;
+
+  fn deref(&self) -> &Self::Target {
+    self.mock_server.as_ref().expect("Mock server has already been taken from the guard")
+  }
+}
+
+impl DerefMut for MockServerGuard {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    self.mock_server.as_mut().expect("Mock server has already been taken from the guard")
+  }
+}
+
+impl Drop for MockServerGuard {
+  fn drop(&mut self) {
+    if let Some(mut mock_server) = self.mock_server.take() {
+      let result = self.shutdown_and_verify(&mut mock_server);
+      if let Err(err) = result {
+        if std::thread::panicking() {
+          // Don't panic again, as that will abort the process
+          error!("{}", err);
+        } else {
+          panic!("{}", err);
+        }
+      }
+    }
+  }
+}
+
+/// Shuts the mock server down, waiting for all the requests to be recorded. This is not possible
+/// with a current thread Tokio runtime (as blocking would stop the mock server tasks from running),
+/// so in that case the standard shutdown is used.
+fn shutdown(mock_server: &mut MockServer) -> anyhow::Result<()> {
+  match Handle::try_current() {
+    Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+      block_in_place(|| handle.block_on(mock_server.shutdown_async(DRAIN_TIMEOUT))).map(|_| ())
+    }
+    Ok(_) => mock_server.shutdown(),
+    Err(_) => futures::executor::block_on(mock_server.shutdown_async(DRAIN_TIMEOUT)).map(|_| ())
+  }
+}
+
+/// Formats the mismatches of the mock server into a report
+pub fn mismatch_report(mock_server: &MockServer) -> String {
+  let mismatches = mock_server.mismatches();
+  let mut report = format!("Mock server {} failed verification with {} mismatch(es):\n",
+    mock_server.id, mismatches.len());
+  for (i, mismatch) in mismatches.iter().enumerate() {
+    let _ = writeln!(report, "  {}) {}", i + 1, mismatch);
+  }
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use pact_models::prelude::v4::{SynchronousHttp, V4Pact};
+  use pact_models::v4::http_parts::HttpRequest;
+  use pact_models::v4::interaction::V4Interaction;
+
+  use crate::builder::MockServerBuilder;
+
+  fn pact() -> V4Pact {
+    V4Pact {
+      interactions: vec![
+        SynchronousHttp {
+          request: HttpRequest { path: "/expected".to_string(), .. HttpRequest::default() },
+          .. SynchronousHttp::default()
+        }.boxed_v4()
+      ],
+      .. V4Pact::default()
+    }
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  async fn guard_verifies_the_mock_server() {
+    let guard = MockServerBuilder::new()
+      .with_v4_pact(pact())
+      .bind_to("127.0.0.1:0")
+      .start_guarded()
+      .await
+      .unwrap();
+
+    let response = reqwest::get(format!("{}/expected", guard.url())).await.unwrap();
+    expect!(response.status()).to(be_equal_to(200));
+    expect!(guard.finish()).to(be_ok());
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  async fn guard_reports_mismatches() {
+    let guard = MockServerBuilder::new()
+      .with_v4_pact(pact())
+      .bind_to("127.0.0.1:0")
+      .start_guarded()
+      .await
+      .unwrap();
+
+    let response = reqwest::get(format!("{}/unexpected", guard.url())).await.unwrap();
+    expect!(response.status()).to(be_equal_to(500));
+    let err = guard.finish().unwrap_err().to_string();
+    expect!(err.contains("failed verification with 2 mismatch(es)")).to(be_true());
+    expect!(err.contains("1) Request was not expected")).to(be_true());
+    expect!(err.contains("2) Request was not received")).to(be_true());
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  #[should_panic(expected = "failed verification")]
+  async fn guard_panics_on_drop_with_mismatches() {
+    let _guard = MockServerBuilder::new()
+      .with_v4_pact(pact())
+      .bind_to("127.0.0.1:0")
+      .start_guarded()
+      .await
+      .unwrap();
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  async fn guard_does_not_panic_when_verification_is_disabled() {
+    let guard = MockServerBuilder::new()
+      .with_v4_pact(pact())
+      .bind_to("127.0.0.1:0")
+      .start_guarded()
+      .await
+      .unwrap()
+      .with_verification(false);
+    drop(guard);
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  async fn guard_writes_the_pact_file_on_success() {
+    let output_dir = std::env::temp_dir().join(format!("guard_writes_the_pact_file_{}", std::process::id()));
+    let pact = V4Pact {
+      consumer: pact_models::prelude::Consumer { name: "guard-consumer".to_string() },
+      provider: pact_models::prelude::Provider { name: "guard-provider".to_string() },
+      .. pact()
+    };
+    let guard = MockServerBuilder::new()
+      .with_v4_pact(pact)
+      .bind_to("127.0.0.1:0")
+      .start_guarded()
+      .await
+      .unwrap()
+      .write_pact_on_success(Some(output_dir.to_string_lossy().to_string()), true);
+
+    reqwest::get(format!("{}/expected", guard.url())).await.unwrap();
+    drop(guard);
+
+    let pact_file = output_dir.join("guard-consumer-guard-provider.json");
+    expect!(pact_file.exists()).to(be_true());
+    let _ = std::fs::remove_dir_all(output_dir);
+  }
+
+  #[test_log::test]
+  fn guard_works_without_a_runtime() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+      .enable_all()
+      .build()
+      .unwrap();
+    let guard = runtime.block_on(MockServerBuilder::new()
+      .with_v4_pact(pact())
+      .bind_to("127.0.0.1:0")
+      .start_guarded())
+      .unwrap();
+
+    let response = reqwest::blocking::get(format!("{}/expected", guard.url())).unwrap();
+    expect!(response.status()).to(be_equal_to(200));
+    expect!(guard.finish()).to(be_ok());
+  }
+}
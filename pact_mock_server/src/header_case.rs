@@ -0,0 +1,570 @@
+//! Support for preserving the original case and order of HTTP/1 header names.
+//!
+//! Hyper normalises all header names to lower case, and while it has an option to preserve the
+//! case of header names (`http1::Builder::preserve_header_case`), the original names are kept in a
+//! private extension type. They can not be read from a request, or set on a response, by a
+//! service, so that option is not able to echo the case from the Pact file. So when this is
+//! enabled, the raw bytes of the connection are inspected. The request heads that are read are
+//! parsed to recover the original header names in the order they were received, and the header
+//! names of the response heads that are written are rewritten to have the case from the Pact file.
+//!
+//! The headers of the Pact request that is matched are a map, so only keep the case of the names
+//! and the order of the values of each header. The headers in the order they were received are
+//! kept on the recorded request (`RecordedRequest::raw_headers`). Pact files do not have an order
+//! for the response headers, so they are written in the order of their names.
+//!
+//! Changing the case of a header name does not change the number of bytes, so the request and
+//! response bodies are left untouched. This only applies to HTTP/1 connections.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{trace, warn};
+
+/// Header names and values, in the order and with the case they were received
+pub(crate) type RawHeaders = Vec<(String, String)>;
+
+/// Maximum size of a request or response head that will be inspected
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Original case of the header names for a response, attached to the response extensions by the
+/// request handler.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ResponseHeaderCase {
+  /// Map of lower case header name to the header name to write
+  names: HashMap<String, String>,
+  /// If the response will have a body (it is not a response to a HEAD request)
+  has_body: bool
+}
+
+impl ResponseHeaderCase {
+  /// Creates the header case for a response. Header names that are not in the provided list of
+  /// names will be written in Title-Case.
+  pub(crate) fn new<'a, I>(names: I, has_body: bool) -> Self
+    where I: IntoIterator<Item = &'a String> {
+    ResponseHeaderCase {
+      names: names.into_iter()
+        .map(|name| (name.to_lowercase(), name.clone()))
+        .collect(),
+      has_body
+    }
+  }
+
+  /// Sets if the response will have a body
+  pub(crate) fn with_body(mut self, has_body: bool) -> Self {
+    self.has_body = has_body;
+    self
+  }
+
+  fn header_name(&self, name: &[u8]) -> Vec<u8> {
+    let lower = String::from_utf8_lossy(name).to_lowercase();
+    match self.names.get(&lower) {
+      Some(original) if original.len() == name.len() => original.as_bytes().to_vec(),
+      _ => title_case(name)
+    }
+  }
+}
+
+fn title_case(name: &[u8]) -> Vec<u8> {
+  let mut upper = true;
+  name.iter().map(|ch| {
+    let result = if upper { ch.to_ascii_uppercase() } else { ch.to_ascii_lowercase() };
+    upper = *ch == b'-';
+    result
+  }).collect()
+}
+
+/// State shared between a connection and the request handler
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HeaderCaseState {
+  requests: Arc<Mutex<VecDeque<RawHeaders>>>,
+  responses: Arc<Mutex<VecDeque<ResponseHeaderCase>>>
+}
+
+impl HeaderCaseState {
+  /// Returns the raw headers for the next request that has been read from the connection
+  pub(crate) fn next_request_headers(&self) -> Option<RawHeaders> {
+    self.requests.lock().unwrap().pop_front()
+  }
+
+  /// Queues up the header case to use for the next response written to the connection
+  pub(crate) fn push_response(&self, header_case: ResponseHeaderCase) {
+    self.responses.lock().unwrap().push_back(header_case);
+  }
+
+  fn push_request(&self, headers: RawHeaders) {
+    self.requests.lock().unwrap().push_back(headers);
+  }
+
+  fn next_response(&self) -> Option<ResponseHeaderCase> {
+    self.responses.lock().unwrap().pop_front()
+  }
+}
+
+/// Replaces the keys of the header map (which will be lower case) with the original header names
+pub(crate) fn apply_original_case(
+  headers: HashMap<String, Vec<String>>,
+  raw_headers: &RawHeaders
+) -> HashMap<String, Vec<String>> {
+  headers.into_iter()
+    .map(|(name, values)| {
+      let original = raw_headers.iter()
+        .find(|(raw_name, _)| raw_name.eq_ignore_ascii_case(&name))
+        .map(|(raw_name, _)| raw_name.clone())
+        .unwrap_or(name);
+      (original, values)
+    })
+    .collect()
+}
+
+#[derive(Debug, Clone)]
+enum ChunkState {
+  Size(Vec<u8>),
+  Data(u64),
+  DataEnd(u8),
+  Trailers(Vec<u8>)
+}
+
+#[derive(Debug, Clone)]
+enum ReadState {
+  Head(Vec<u8>),
+  Body(u64),
+  Chunked(ChunkState),
+  Passthrough
+}
+
+/// Scans the bytes read from the connection for request heads
+#[derive(Debug, Clone)]
+struct RequestScanner {
+  state: ReadState
+}
+
+impl RequestScanner {
+  fn new() -> Self {
+    RequestScanner { state: ReadState::Head(vec![]) }
+  }
+
+  fn scan(&mut self, mut data: &[u8], shared: &HeaderCaseState) {
+    while !data.is_empty() {
+      match &mut self.state {
+        ReadState::Passthrough => return,
+        ReadState::Body(remaining) => {
+          let n = (*remaining).min(data.len() as u64);
+          data = &data[n as usize..];
+          *remaining -= n;
+          if *remaining == 0 {
+            self.state = ReadState::Head(vec![]);
+          }
+        }
+        ReadState::Head(buffer) => {
+          let ch = data[0];
+          data = &data[1..];
+          if buffer.is_empty() && (ch == b'\r' || ch == b'\n') {
+            // Ignore any empty lines before the request line
+            continue;
+          }
+          buffer.push(ch);
+          if buffer.ends_with(b"\r\n\r\n") || buffer.ends_with(b"\n\n") {
+            let head = std::mem::take(buffer);
+            self.state = self.parse_head(&head, shared);
+          } else if buffer.len() > MAX_HEAD_SIZE {
+            warn!("Request head is too large, not preserving header case");
+            self.state = ReadState::Passthrough;
+          }
+        }
+        ReadState::Chunked(chunk) => {
+          match chunk {
+            ChunkState::Size(line) => {
+              let ch = data[0];
+              data = &data[1..];
+              line.push(ch);
+              if ch == b'\n' {
+                let line = String::from_utf8_lossy(line);
+                let size = line.split(';').next().unwrap_or_default().trim();
+                match u64::from_str_radix(size, 16) {
+                  Ok(0) => *chunk = ChunkState::Trailers(vec![]),
+                  Ok(size) => *chunk = ChunkState::Data(size),
+                  Err(_) => self.state = ReadState::Passthrough
+                }
+              }
+            }
+            ChunkState::Data(remaining) => {
+              let n = (*remaining).min(data.len() as u64);
+              data = &data[n as usize..];
+              *remaining -= n;
+              if *remaining == 0 {
+                *chunk = ChunkState::DataEnd(2);
+              }
+            }
+            ChunkState::DataEnd(remaining) => {
+              let ch = data[0];
+              data = &data[1..];
+              *remaining -= 1;
+              if ch == b'\n' || *remaining == 0 {
+                *chunk = ChunkState::Size(vec![]);
+              }
+            }
+            ChunkState::Trailers(line) => {
+              let ch = data[0];
+              data = &data[1..];
+              if ch == b'\n' {
+                if line.is_empty() || line == b"\r" {
+                  self.state = ReadState::Head(vec![]);
+                } else {
+                  line.clear();
+                }
+              } else {
+                line.push(ch);
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+
+  fn parse_head(&self, head: &[u8], shared: &HeaderCaseState) -> ReadState {
+    let mut headers = [httparse::EMPTY_HEADER; 128];
+    let mut request = httparse::Request::new(&mut headers);
+    match request.parse(head) {
+      Ok(httparse::Status::Complete(_)) => {
+        let raw_headers: RawHeaders = request.headers.iter()
+          .map(|h| (h.name.to_string(), String::from_utf8_lossy(h.value).to_string()))
+          .collect();
+        trace!(?raw_headers, "Read request head");
+        let header_value = |name: &str| raw_headers.iter()
+          .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+          .map(|(_, v)| v.clone())
+          .collect::<Vec<_>>();
+        let upgrade = request.method == Some("CONNECT") || !header_value("upgrade").is_empty();
+        let chunked = header_value("transfer-encoding").iter()
+          .any(|v| v.to_lowercase().contains("chunked"));
+        let content_length = header_value("content-length").first()
+          .and_then(|v| v.trim().parse::<u64>().ok());
+        shared.push_request(raw_headers);
+
+        if upgrade {
+          ReadState::Passthrough
+        } else if chunked {
+          ReadState::Chunked(ChunkState::Size(vec![]))
+        } else {
+          match content_length {
+            Some(length) if length > 0 => ReadState::Body(length),
+            _ => ReadState::Head(vec![])
+          }
+        }
+      }
+      _ => {
+        // Either not HTTP/1 (i.e. HTTP/2 prior knowledge), or not something we can process
+        trace!("Could not parse request head, not preserving header case");
+        ReadState::Passthrough
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+enum WriteState {
+  StatusLine(Vec<u8>),
+  HeaderName(Vec<u8>),
+  HeaderValue(String, Vec<u8>),
+  Body(u64),
+  Passthrough
+}
+
+/// Rewrites the header names of the response heads written to the connection
+#[derive(Debug, Clone)]
+struct ResponseRewriter {
+  state: WriteState,
+  status: u16,
+  content_length: Option<u64>,
+  chunked: bool,
+  header_case: ResponseHeaderCase
+}
+
+impl ResponseRewriter {
+  fn new() -> Self {
+    ResponseRewriter {
+      state: WriteState::StatusLine(vec![]),
+      status: 0,
+      content_length: None,
+      chunked: false,
+      header_case: ResponseHeaderCase::default()
+    }
+  }
+
+  fn rewrite(&mut self, mut data: &[u8], output: &mut Vec<u8>, shared: &HeaderCaseState) {
+    while !data.is_empty() {
+      match &mut self.state {
+        WriteState::Passthrough => {
+          output.extend_from_slice(data);
+          return;
+        }
+        WriteState::Body(remaining) => {
+          let n = (*remaining).min(data.len() as u64);
+          output.extend_from_slice(&data[..n as usize]);
+          data = &data[n as usize..];
+          *remaining -= n;
+          if *remaining == 0 {
+            self.state = WriteState::StatusLine(vec![]);
+          }
+        }
+        WriteState::StatusLine(line) => {
+          let ch = data[0];
+          data = &data[1..];
+          output.push(ch);
+          line.push(ch);
+          if ch == b'\n' {
+            let line = String::from_utf8_lossy(line);
+            self.status = line.split_whitespace().nth(1)
+              .and_then(|status| status.parse().ok())
+              .unwrap_or_default();
+            self.content_length = None;
+            self.chunked = false;
+            // Interim responses (like 100 Continue) are written by Hyper, and are not associated
+            // with a response from the request handler
+            self.header_case = if self.status >= 200 {
+              shared.next_response().unwrap_or_default()
+            } else {
+              ResponseHeaderCase::default()
+            };
+            self.state = WriteState::HeaderName(vec![]);
+          } else if line.len() > MAX_HEAD_SIZE {
+            self.state = WriteState::Passthrough;
+          }
+        }
+        WriteState::HeaderName(name) => {
+          let ch = data[0];
+          data = &data[1..];
+          if name.is_empty() && ch == b'\r' {
+            output.push(ch);
+          } else if name.is_empty() && ch == b'\n' {
+            output.push(ch);
+            self.state = self.end_of_head();
+          } else if ch == b':' {
+            output.extend_from_slice(&self.header_case.header_name(name));
+            output.push(ch);
+            let name = String::from_utf8_lossy(name).to_lowercase();
+            self.state = WriteState::HeaderValue(name, vec![]);
+          } else {
+            name.push(ch);
+            if name.len() > MAX_HEAD_SIZE {
+              output.extend_from_slice(name);
+              self.state = WriteState::Passthrough;
+            }
+          }
+        }
+        WriteState::HeaderValue(name, value) => {
+          let ch = data[0];
+          data = &data[1..];
+          output.push(ch);
+          if ch == b'\n' {
+            let value = String::from_utf8_lossy(value).trim().to_string();
+            if name == "content-length" {
+              self.content_length = value.parse().ok();
+            } else if name == "transfer-encoding" && value.to_lowercase().contains("chunked") {
+              self.chunked = true;
+            }
+            self.state = WriteState::HeaderName(vec![]);
+          } else {
+            value.push(ch);
+          }
+        }
+      }
+    }
+  }
+
+  fn end_of_head(&self) -> WriteState {
+    // Informational responses are followed by the final response, and these ones never have a body
+    if self.status < 200 || !self.header_case.has_body || self.status == 204 || self.status == 304 {
+      WriteState::StatusLine(vec![])
+    } else if self.chunked {
+      // Our responses always have a known length, so stop rewriting anything
+      WriteState::Passthrough
+    } else {
+      match self.content_length {
+        Some(0) => WriteState::StatusLine(vec![]),
+        Some(length) => WriteState::Body(length),
+        None => WriteState::Passthrough
+      }
+    }
+  }
+}
+
+/// Wraps the IO stream of a connection to preserve the case of header names. If not enabled,
+/// all bytes are passed straight through.
+pub(crate) struct HeaderCaseIo<T> {
+  inner: T,
+  enabled: bool,
+  shared: HeaderCaseState,
+  scanner: RequestScanner,
+  rewriter: ResponseRewriter,
+  pending: Vec<u8>
+}
+
+impl<T> HeaderCaseIo<T> {
+  /// Wraps the IO stream
+  pub(crate) fn new(inner: T, enabled: bool, shared: HeaderCaseState) -> Self {
+    HeaderCaseIo {
+      inner,
+      enabled,
+      shared,
+      scanner: RequestScanner::new(),
+      rewriter: ResponseRewriter::new(),
+      pending: vec![]
+    }
+  }
+}
+
+impl<T: AsyncWrite + Unpin> HeaderCaseIo<T> {
+  fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    while !self.pending.is_empty() {
+      match Pin::new(&mut self.inner).poll_write(cx, &self.pending) {
+        Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+        Poll::Ready(Ok(n)) => {
+          self.pending.drain(..n);
+        }
+        Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+        Poll::Pending => return Poll::Pending
+      }
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for HeaderCaseIo<T> {
+  fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    let before = buf.filled().len();
+    let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+    if this.enabled && matches!(result, Poll::Ready(Ok(()))) {
+      this.scanner.scan(&buf.filled()[before..], &this.shared);
+    }
+    result
+  }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for HeaderCaseIo<T> {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    if !this.enabled {
+      return Pin::new(&mut this.inner).poll_write(cx, buf);
+    }
+
+    match this.poll_write_pending(cx) {
+      Poll::Ready(Ok(())) => {
+        let mut output = Vec::with_capacity(buf.len());
+        this.rewriter.rewrite(buf, &mut output, &this.shared);
+        this.pending = output;
+        // The bytes have been accepted, errors will be reported on the next write or flush
+        let _ = this.poll_write_pending(cx);
+        Poll::Ready(Ok(buf.len()))
+      }
+      Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+      Poll::Pending => Poll::Pending
+    }
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    match this.poll_write_pending(cx) {
+      Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+      result => result
+    }
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    match this.poll_write_pending(cx) {
+      Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+      result => result
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use maplit::hashmap;
+
+  use super::*;
+
+  #[test]
+  fn scanner_records_the_raw_headers_of_each_request() {
+    let shared = HeaderCaseState::default();
+    let mut scanner = RequestScanner::new();
+    scanner.scan(b"POST /one HTTP/1.1\r\nHost: localhost\r\nX-API-KEY: 1234\r\nContent-Length: 5\r\n\r\nhel", &shared);
+    scanner.scan(b"lo\r\nGET /two HTTP/1.1\r\nhost: localhost\r\nAccept: */*\r\n", &shared);
+    scanner.scan(b"\r\n", &shared);
+
+    expect!(shared.next_request_headers()).to(be_some().value(vec![
+      ("Host".to_string(), "localhost".to_string()),
+      ("X-API-KEY".to_string(), "1234".to_string()),
+      ("Content-Length".to_string(), "5".to_string())
+    ]));
+    expect!(shared.next_request_headers()).to(be_some().value(vec![
+      ("host".to_string(), "localhost".to_string()),
+      ("Accept".to_string(), "*/*".to_string())
+    ]));
+    expect!(shared.next_request_headers()).to(be_none());
+  }
+
+  #[test]
+  fn scanner_skips_chunked_bodies() {
+    let shared = HeaderCaseState::default();
+    let mut scanner = RequestScanner::new();
+    scanner.scan(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n0\r\nX-Trailer: a\r\n\r\n", &shared);
+    scanner.scan(b"GET / HTTP/1.1\r\nX-Test: b\r\n\r\n", &shared);
+
+    expect!(shared.next_request_headers().map(|h| h.len())).to(be_some().value(1));
+    expect!(shared.next_request_headers()).to(be_some().value(vec![
+      ("X-Test".to_string(), "b".to_string())
+    ]));
+  }
+
+  #[test]
+  fn scanner_ignores_http2() {
+    let shared = HeaderCaseState::default();
+    let mut scanner = RequestScanner::new();
+    scanner.scan(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n", &shared);
+    expect!(shared.next_request_headers()).to(be_none());
+    expect!(matches!(scanner.state, ReadState::Passthrough)).to(be_true());
+  }
+
+  #[test]
+  fn rewriter_uses_the_header_case_for_each_response() {
+    let shared = HeaderCaseState::default();
+    shared.push_response(ResponseHeaderCase::new(&["X-API-KEY".to_string()], true));
+    shared.push_response(ResponseHeaderCase::new(&[], false));
+    let mut rewriter = ResponseRewriter::new();
+    let mut output = vec![];
+    rewriter.rewrite(b"HTTP/1.1 100 Continue\r\n\r\n", &mut output, &shared);
+    rewriter.rewrite(b"HTTP/1.1 200 OK\r\nx-api", &mut output, &shared);
+    rewriter.rewrite(b"-key: 1\r\ncontent-length: 12\r\n\r\nx-api-key: 1", &mut output, &shared);
+    rewriter.rewrite(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\naccess-control-allow-origin: *\r\n\r\n", &mut output, &shared);
+    rewriter.rewrite(b"HTTP/1.1 204 No Content\r\nx-api-key: 2\r\n\r\n", &mut output, &shared);
+
+    expect!(String::from_utf8(output).unwrap()).to(be_equal_to(
+      "HTTP/1.1 100 Continue\r\n\r\n\
+      HTTP/1.1 200 OK\r\nX-API-KEY: 1\r\nContent-Length: 12\r\n\r\nx-api-key: 1\
+      HTTP/1.1 200 OK\r\nContent-Length: 100\r\nAccess-Control-Allow-Origin: *\r\n\r\n\
+      HTTP/1.1 204 No Content\r\nX-Api-Key: 2\r\n\r\n".to_string()));
+  }
+
+  #[test]
+  fn apply_original_case_test() {
+    let headers = hashmap! {
+      "x-api-key".to_string() => vec!["1234".to_string()],
+      "accept".to_string() => vec!["*/*".to_string()]
+    };
+    let raw = vec![("X-API-KEY".to_string(), "1234".to_string())];
+    expect!(apply_original_case(headers, &raw)).to(be_equal_to(hashmap! {
+      "X-API-KEY".to_string() => vec!["1234".to_string()],
+      "accept".to_string() => vec!["*/*".to_string()]
+    }));
+  }
+}
@@ -4,28 +4,31 @@ use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::net::SocketAddr;
-#[cfg(feature = "tls")] use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 #[allow(unused_imports)] use anyhow::anyhow;
 use bytes::Bytes;
 use http_body_util::{BodyExt, Full};
-use hyper::{Request, Response};
-use hyper::body::Incoming;
+use hyper::{Method, Request, Response, Version};
+use hyper::body::{Body, Incoming};
 use hyper::header::{HeaderName, HeaderValue};
 use hyper::http::response::Builder;
 use hyper::service::service_fn;
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
 use itertools::Itertools;
 use maplit::hashmap;
 use pact_models::bodies::OptionalBody;
+#[cfg(feature = "compression")] use pact_models::content_types::ContentTypeHint;
 use pact_models::generators::GeneratorTestMode;
 use pact_models::headers::parse_header;
 use pact_models::http_parts::HttpPart;
 use pact_models::query_strings::parse_query_string;
 use pact_models::v4::calc_content_type;
-use pact_models::v4::http_parts::HttpRequest;
+use pact_models::v4::http_parts::{HttpRequest, HttpResponse};
 use pact_models::v4::pact::V4Pact;
 #[cfg(feature = "tls")] use rcgen::{CertifiedKey, generate_simple_self_signed};
 #[cfg(feature = "tls")] use rustls::crypto::CryptoProvider;
@@ -33,16 +36,35 @@ use pact_models::v4::pact::V4Pact;
 #[cfg(feature = "tls")] use rustls::pki_types::PrivateKeyDer;
 #[cfg(feature = "tls")] use rustls::ServerConfig;
 use serde_json::json;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::select;
 use tokio::sync::{mpsc, oneshot};
 use tokio::sync::mpsc::Sender;
 use tokio::task::{JoinHandle, JoinSet};
 #[cfg(feature = "tls")] use tokio_rustls::TlsAcceptor;
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, error, info, info_span, Instrument, Span, trace, warn};
+use tracing::field::Empty;
 
-use crate::matching::{match_request, MatchResult};
-use crate::mock_server::{MockServerConfig, MockServerEvent};
+use crate::admin::{admin_response, admin_sub_path};
+#[cfg(feature = "compression")] use crate::compression::{
+  decode_body,
+  find_header,
+  parse_content_encoding,
+  select_accepted_encoding
+};
+use crate::header_case::{apply_original_case, HeaderCaseIo, HeaderCaseState, RawHeaders, ResponseHeaderCase};
+use crate::logs::mock_server_span;
+use crate::matching::{match_request_to_interaction, MatchResult};
+use crate::metrics::{MockServerMetrics, OPENMETRICS_CONTENT_TYPE};
+use crate::mock_server::{
+  MockServer,
+  MockServerConfig,
+  MockServerEvent,
+  MockServerScheme,
+  RecordedRequest,
+  RequestDetails,
+  ResponseDetails
+};
 use crate::LOG_ID;
 
 #[derive(Debug, Clone)]
@@ -66,21 +88,75 @@ impl Display for InteractionError {
 
 impl std::error::Error for InteractionError {}
 
-/// Create and bind the server, spawning the server loop onto the runtime and returning the bound
-/// address, the send end of the shutdown channel and the receive end of the event channel
+/// Binds a listener to each of the addresses. Any additional address with a port of zero will be
+/// bound to the same port as the first listener, so that all the listeners share the same port.
+async fn bind_listeners(addresses: &[SocketAddr]) -> anyhow::Result<Vec<(TcpListener, SocketAddr)>> {
+  let mut listeners: Vec<(TcpListener, SocketAddr)> = vec![];
+  for address in addresses {
+    let mut address = *address;
+    if let Some((_, first_address)) = listeners.first().filter(|_| address.port() == 0) {
+      address.set_port(first_address.port());
+    }
+    let listener = TcpListener::bind(address).await
+      .map_err(|err| anyhow!("Failed to bind to {} - {}", address, err))?;
+    let local_addr = listener.local_addr()?;
+    listeners.push((listener, local_addr));
+  }
+
+  if listeners.is_empty() {
+    Err(anyhow!("No addresses were provided to bind the mock server to"))
+  } else {
+    Ok(listeners)
+  }
+}
+
+/// Connection accepted by a listener, with the remote and local addresses
+type AcceptedConnection = std::io::Result<(TcpStream, SocketAddr, SocketAddr)>;
+
+/// Spawns a task for each listener to accept connections, which are forwarded to the returned
+/// channel along with the local address of the listener. The tasks are aborted when the returned
+/// join set is dropped.
+fn accept_connections(
+  listeners: Vec<(TcpListener, SocketAddr)>
+) -> (mpsc::Receiver<AcceptedConnection>, JoinSet<()>) {
+  let (accept_send, accept_recv) = mpsc::channel(listeners.len());
+  let mut accept_tasks = JoinSet::new();
+  for (listener, local_addr) in listeners {
+    let accept_send = accept_send.clone();
+    accept_tasks.spawn(async move {
+      loop {
+        let connection = listener.accept().await
+          .map(|(stream, remote_address)| (stream, remote_address, local_addr));
+        if accept_send.send(connection).await.is_err() {
+          break;
+        }
+      }
+    });
+  }
+  (accept_recv, accept_tasks)
+}
+
+/// Create and bind the server to all the given addresses, spawning the server loop onto the
+/// runtime and returning the bound addresses, the send end of the shutdown channel and the receive
+/// end of the event channel
 pub(crate) async fn create_and_bind(
   server_id: String,
   pact: V4Pact,
-  addr: SocketAddr,
-  config: MockServerConfig
-) -> anyhow::Result<(SocketAddr, oneshot::Sender<()>, mpsc::Receiver<MockServerEvent>, JoinHandle<()>)> {
-  let listener = TcpListener::bind(addr).await?;
-  let local_addr = listener.local_addr()?;
+  addresses: &[SocketAddr],
+  config: MockServerConfig,
+  metrics: Arc<Mutex<MockServerMetrics>>,
+  requests: Arc<Mutex<Vec<RecordedRequest>>>
+) -> anyhow::Result<(Vec<SocketAddr>, oneshot::Sender<Option<Duration>>, mpsc::Receiver<MockServerEvent>, JoinHandle<()>)> {
+  let listeners = bind_listeners(addresses).await?;
+  let local_addresses = listeners.iter().map(|(_, addr)| *addr).collect();
+  let (mut accept_recv, mut accept_tasks) = accept_connections(listeners);
 
   let mut join_set = JoinSet::new();
-  let graceful = hyper_util::server::graceful::GracefulShutdown::new();
-  let (shutdown_send, mut shutdown_recv) = oneshot::channel::<()>();
+  let graceful = GracefulShutdown::new();
+  let (shutdown_send, mut shutdown_recv) = oneshot::channel::<Option<Duration>>();
+  let server_state = ServerState::new(server_id.clone(), MockServerScheme::HTTP, metrics, requests);
   let (event_send, event_recv) = mpsc::channel::<MockServerEvent>(256);
+  let span = mock_server_span(&server_id);
 
   let handle = tokio::spawn(async move {
     loop {
@@ -88,13 +164,15 @@ pub(crate) async fn create_and_bind(
       let server_id = server_id.clone();
       let pact = pact.clone();
       let config = config.clone();
+      let server_state = server_state.clone();
 
       select! {
-        connection = listener.accept() => {
+        Some(connection) = accept_recv.recv() => {
           match connection {
-            Ok((stream, remote_address)) => {
+            Ok((stream, remote_address, local_addr)) => {
               debug!("Received connection from remote {}", remote_address);
-              let io = TokioIo::new(Box::pin(stream));
+              let connection = ConnectionState::new(&server_state, local_addr, remote_address);
+              let io = TokioIo::new(Box::pin(HeaderCaseIo::new(stream, config.preserve_header_case, connection.header_case.clone())));
               let sid = server_id.clone();
               let ev = event_send.clone();
               let mut server = auto::Builder::new(TokioExecutor::new());
@@ -106,24 +184,15 @@ pub(crate) async fn create_and_bind(
                   let event_send = ev.clone();
                   let config = config.clone();
                   let server_id = sid.clone();
+                  let connection = connection.clone();
                   LOG_ID.scope(server_id, async move {
-                    handle_mock_request_error(
-                      handle_request(req, pact.clone(), event_send.clone(), &local_addr, &config).await
-                    )
-                  })
+                    serve_request(req, pact.clone(), event_send.clone(), &config, &connection).await
+                  }.in_current_span())
                 })
               );
 
               let conn = graceful.watch(conn.into_owned());
-              join_set.spawn(LOG_ID.scope(server_id.clone(), async move {
-                if let Err(err) = conn.await {
-                    error!("failed to serve connection: {err}");
-                    if let Err(err) = event_send.send(MockServerEvent::ConnectionFailed(err.to_string())).await {
-                      error!("Failed to send ConnectionFailed event: {}", err);
-                    }
-                }
-                trace!("Connection dropped: {}", remote_address);
-              }));
+              join_set.spawn(LOG_ID.scope(server_id.clone(), serve_connection(conn, remote_address, event_send)).in_current_span());
             },
             Err(e) => {
               error!("failed to accept connection: {e}");
@@ -134,11 +203,10 @@ pub(crate) async fn create_and_bind(
           }
         }
 
-        _ = &mut shutdown_recv => {
+        drain_timeout = &mut shutdown_recv => {
           trace!("Received shutdown signal, signalling server shutdown");
-          graceful.shutdown().await;
-          trace!("Waiting for existing connections to complete");
-          while let Some(_) = join_set.join_next().await {};
+          accept_tasks.abort_all();
+          drain_connections(graceful, join_set, &server_state.in_flight, drain_timeout.ok().flatten(), &event_send).await;
           trace!("Existing connections complete, exiting main loop");
           if let Err(err) = event_send.send(MockServerEvent::ServerShutdown).await {
             error!("Failed to send ServerShutdown event: {}", err);
@@ -149,34 +217,40 @@ pub(crate) async fn create_and_bind(
     }
 
     trace!("Mock server main loop done");
-  });
+  }.instrument(span));
 
-  Ok((local_addr, shutdown_send, event_recv, handle))
+  Ok((local_addresses, shutdown_send, event_recv, handle))
 }
 
-/// Create and bind the HTTPS server, spawning the server loop onto the runtime and returning the bound
-/// address, the send end of the shutdown channel and the receive end of the event channel. If no
-/// HTTPS configuration has been supplied, a self-signed certificate will be created to be used.
+/// Create and bind the HTTPS server to all the given addresses, spawning the server loop onto the
+/// runtime and returning the bound addresses, the send end of the shutdown channel and the receive
+/// end of the event channel. If no HTTPS configuration has been supplied, a self-signed certificate
+/// will be created to be used.
 #[cfg(feature = "tls")]
 pub(crate) async fn create_and_bind_https(
   server_id: String,
   pact: V4Pact,
-  addr: SocketAddr,
-  config: MockServerConfig
-) -> anyhow::Result<(SocketAddr, oneshot::Sender<()>, mpsc::Receiver<MockServerEvent>, JoinHandle<()>)> {
+  addresses: &[SocketAddr],
+  config: MockServerConfig,
+  metrics: Arc<Mutex<MockServerMetrics>>,
+  requests: Arc<Mutex<Vec<RecordedRequest>>>
+) -> anyhow::Result<(Vec<SocketAddr>, oneshot::Sender<Option<Duration>>, mpsc::Receiver<MockServerEvent>, JoinHandle<()>)> {
   if CryptoProvider::get_default().is_none() {
     warn!("No TLS cryptographic provider has been configured, defaulting to the standard FIPS provider");
     CryptoProvider::install_default(default_provider())
       .map_err(|_| anyhow!("Failed to install the standard FIPS provider"))?;
   }
 
-  let listener = TcpListener::bind(addr).await?;
-  let local_addr = listener.local_addr()?;
+  let listeners = bind_listeners(addresses).await?;
+  let local_addresses = listeners.iter().map(|(_, addr)| *addr).collect();
+  let (mut accept_recv, mut accept_tasks) = accept_connections(listeners);
 
   let mut join_set = JoinSet::new();
-  let graceful = hyper_util::server::graceful::GracefulShutdown::new();
-  let (shutdown_send, mut shutdown_recv) = oneshot::channel::<()>();
+  let graceful = GracefulShutdown::new();
+  let (shutdown_send, mut shutdown_recv) = oneshot::channel::<Option<Duration>>();
+  let server_state = ServerState::new(server_id.clone(), MockServerScheme::HTTPS, metrics, requests);
   let (event_send, event_recv) = mpsc::channel::<MockServerEvent>(256);
+  let span = mock_server_span(&server_id);
 
   let tls_config = match &config.tls_config {
     Some(config) => config.clone(),
@@ -197,11 +271,12 @@ pub(crate) async fn create_and_bind_https(
       let server_id = server_id.clone();
       let pact = pact.clone();
       let config = config.clone();
+      let server_state = server_state.clone();
 
       select! {
-        connection = listener.accept() => {
+        Some(connection) = accept_recv.recv() => {
           match connection {
-            Ok((stream, remote_address)) => {
+            Ok((stream, remote_address, local_addr)) => {
               debug!("Received connection from remote {}", remote_address);
 
               let tls_acceptor = tls_acceptor.clone();
@@ -209,7 +284,8 @@ pub(crate) async fn create_and_bind_https(
                 Ok(tls_stream) => {
                   let sid = server_id.clone();
                   let ev = event_send.clone();
-                  let io = TokioIo::new(Box::pin(tls_stream));
+                  let connection = ConnectionState::new(&server_state, local_addr, remote_address);
+                  let io = TokioIo::new(Box::pin(HeaderCaseIo::new(tls_stream, config.preserve_header_case, connection.header_case.clone())));
                   let mut server = auto::Builder::new(TokioExecutor::new());
                   server.http1().keep_alive(config.keep_alive);
                   server.http2().keep_alive_interval( if config.keep_alive { None } else { Some(Duration::from_secs(1)) });
@@ -219,29 +295,20 @@ pub(crate) async fn create_and_bind_https(
                       let event_send = ev.clone();
                       let config = config.clone();
                       let server_id = sid.clone();
+                      let connection = connection.clone();
                       LOG_ID.scope(server_id, async move {
-                        handle_mock_request_error(
-                          handle_request(req, pact.clone(), event_send.clone(), &local_addr, &config).await
-                        )
-                      })
+                        serve_request(req, pact.clone(), event_send.clone(), &config, &connection).await
+                      }.in_current_span())
                     })
                   );
 
                   let conn = graceful.watch(conn.into_owned());
-                  join_set.spawn(LOG_ID.scope(server_id.clone(), async move {
-                    if let Err(err) = conn.await {
-                        error!("failed to serve connection: {err}");
-                        if let Err(err) = event_send.send(MockServerEvent::ConnectionFailed(err.to_string())).await {
-                          error!("Failed to send ConnectionFailed event: {}", err);
-                        }
-                    }
-                    trace!("Connection dropped: {}", remote_address);
-                  }));
+                  join_set.spawn(LOG_ID.scope(server_id.clone(), serve_connection(conn, remote_address, event_send)).in_current_span());
                 },
                 Err(err) => {
                   error!("failed to perform tls handshake: {err:#}");
-                  if let Err(err) = event_send.send(MockServerEvent::ConnectionFailed(err.to_string())).await {
-                    error!("Failed to send ConnectionFailed event: {}", err);
+                  if let Err(err) = event_send.send(MockServerEvent::TlsHandshakeFailed(err.to_string())).await {
+                    error!("Failed to send TlsHandshakeFailed event: {}", err);
                   }
                 }
               };
@@ -255,11 +322,10 @@ pub(crate) async fn create_and_bind_https(
           }
         }
 
-        _ = &mut shutdown_recv => {
+        drain_timeout = &mut shutdown_recv => {
           trace!("Received shutdown signal, signalling server shutdown");
-          graceful.shutdown().await;
-          trace!("Waiting for existing connections to complete");
-          while let Some(_) = join_set.join_next().await {};
+          accept_tasks.abort_all();
+          drain_connections(graceful, join_set, &server_state.in_flight, drain_timeout.ok().flatten(), &event_send).await;
           trace!("Existing connections complete, exiting main loop");
           if let Err(err) = event_send.send(MockServerEvent::ServerShutdown).await {
             error!("Failed to send ServerShutdown event: {}", err);
@@ -268,9 +334,301 @@ pub(crate) async fn create_and_bind_https(
         }
       }
     }
-  });
+  }.instrument(span));
+
+  Ok((local_addresses, shutdown_send, event_recv, handle))
+}
+
+/// Serves the requests on a connection until it is closed, sending the connection events
+async fn serve_connection<C, E>(conn: C, remote_address: SocketAddr, event_send: Sender<MockServerEvent>)
+  where C: Future<Output = Result<(), E>>,
+        E: Display {
+  if let Err(err) = event_send.send(MockServerEvent::ConnectionOpened(remote_address)).await {
+    error!("Failed to send ConnectionOpened event: {}", err);
+  }
+  if let Err(err) = conn.await {
+    error!("failed to serve connection: {err}");
+    if let Err(err) = event_send.send(MockServerEvent::ConnectionFailed(err.to_string())).await {
+      error!("Failed to send ConnectionFailed event: {}", err);
+    }
+  }
+  trace!("Connection dropped: {}", remote_address);
+  if let Err(err) = event_send.send(MockServerEvent::ConnectionClosed(remote_address)).await {
+    error!("Failed to send ConnectionClosed event: {}", err);
+  }
+}
+
+/// Requests that are currently being processed by the mock server, so that any that are cut off
+/// when the server is shut down can be reported
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InFlightRequests {
+  next_id: Arc<AtomicU64>,
+  requests: Arc<Mutex<HashMap<u64, String>>>
+}
+
+impl InFlightRequests {
+  /// Records the start of a request. The request is removed when the returned guard is dropped.
+  /// The ID of the request is available from the guard.
+  fn start(&self, request: String) -> InFlightGuard {
+    let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+    self.requests.lock().unwrap().insert(id, request);
+    InFlightGuard { id, requests: self.requests.clone() }
+  }
+
+  /// Returns the requests that are currently being processed, in the order they were received
+  fn current(&self) -> Vec<String> {
+    self.requests.lock().unwrap().iter()
+      .sorted_by_key(|(id, _)| **id)
+      .map(|(_, request)| request.clone())
+      .collect()
+  }
+}
+
+struct InFlightGuard {
+  id: u64,
+  requests: Arc<Mutex<HashMap<u64, String>>>
+}
+
+impl Drop for InFlightGuard {
+  fn drop(&mut self) {
+    self.requests.lock().unwrap().remove(&self.id);
+  }
+}
+
+/// State of the mock server that is shared by all the connections
+#[derive(Debug, Clone)]
+struct ServerState {
+  server_id: String,
+  scheme: MockServerScheme,
+  in_flight: InFlightRequests,
+  metrics: Arc<Mutex<MockServerMetrics>>,
+  requests: Arc<Mutex<Vec<RecordedRequest>>>
+}
+
+impl ServerState {
+  fn new(
+    server_id: String,
+    scheme: MockServerScheme,
+    metrics: Arc<Mutex<MockServerMetrics>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>
+  ) -> Self {
+    ServerState {
+      server_id,
+      scheme,
+      in_flight: InFlightRequests::default(),
+      metrics,
+      requests
+    }
+  }
+}
+
+/// State for a connection to the mock server that is shared by all the requests on it
+#[derive(Debug, Clone)]
+struct ConnectionState {
+  server: ServerState,
+  local_address: SocketAddr,
+  remote_address: SocketAddr,
+  header_case: HeaderCaseState
+}
+
+impl ConnectionState {
+  fn new(server: &ServerState, local_address: SocketAddr, remote_address: SocketAddr) -> Self {
+    ConnectionState {
+      server: server.clone(),
+      local_address,
+      remote_address,
+      header_case: HeaderCaseState::default()
+    }
+  }
+}
+
+/// Waits for the in-flight requests to complete after the shutdown signal has been received. If
+/// a drain timeout is given, any connections that are still open after the timeout are closed and
+/// a `DrainTimeout` event is sent with the requests that were cut off.
+async fn drain_connections(
+  graceful: GracefulShutdown,
+  mut join_set: JoinSet<()>,
+  in_flight: &InFlightRequests,
+  drain_timeout: Option<Duration>,
+  event_send: &Sender<MockServerEvent>
+) {
+  trace!(?drain_timeout, "Waiting for existing connections to complete");
+  let drain = async {
+    graceful.shutdown().await;
+    while join_set.join_next().await.is_some() {}
+  };
+
+  let drained = match drain_timeout {
+    Some(timeout) => tokio::time::timeout(timeout, drain).await.is_ok(),
+    None => {
+      drain.await;
+      true
+    }
+  };
 
-  Ok((local_addr, shutdown_send, event_recv, handle))
+  if !drained {
+    let requests = in_flight.current();
+    let connections = join_set.len();
+    warn!(connections, ?requests, "Drain timeout expired, closing any remaining connections");
+    join_set.abort_all();
+    while join_set.join_next().await.is_some() {}
+    if let Err(err) = event_send.send(MockServerEvent::DrainTimeout(connections, requests)).await {
+      error!("Failed to send DrainTimeout event: {}", err);
+    }
+  }
+}
+
+/// Service function for each request. This takes care of the original case of headers for HTTP/1
+/// connections if that has been enabled, and sends the event with the details of the response.
+async fn serve_request(
+  req: Request<Incoming>,
+  pact: V4Pact,
+  event_send: Sender<MockServerEvent>,
+  config: &MockServerConfig,
+  connection: &ConnectionState
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+  // The raw headers are queued for every request read from the connection, and the header case
+  // is taken for every response written, so this needs to be done for the metrics and admin
+  // requests as well, otherwise the next request on the connection would get the wrong ones
+  let preserve_case = config.preserve_header_case && req.version() < Version::HTTP_2;
+  let has_body = req.method() != Method::HEAD;
+  let raw_headers = if preserve_case {
+    connection.header_case.next_request_headers()
+  } else {
+    None
+  };
+
+  let reserved_response = match metrics_response(&req, config, &connection.server) {
+    Some(response) => Some(response),
+    None => admin_request_response(&req, &pact, config, connection).await
+  };
+  if let Some(response) = reserved_response {
+    if preserve_case {
+      connection.header_case.push_response(ResponseHeaderCase::default().with_body(has_body));
+    }
+    return Ok(response);
+  }
+
+  let start = Instant::now();
+  let in_flight_guard = connection.server.in_flight.start(format!("{} {}", req.method(), req.uri().path()));
+  let request_details = RequestDetails {
+    request_id: in_flight_guard.id,
+    remote_address: connection.remote_address,
+    local_address: connection.local_address,
+    http_version: format!("{:?}", req.version()),
+    received_at: SystemTime::now()
+  };
+  let span = request_span(&req, &request_details);
+
+  let mut result = handle_mock_request_error(
+    handle_request(req, pact, event_send.clone(), &request_details, config, raw_headers, &connection.server)
+      .instrument(span.clone())
+      .await
+  );
+  if let Some(delay) = config.response_delay {
+    trace!(?delay, "Delaying the response");
+    tokio::time::sleep(delay).await;
+  }
+
+  async {
+    if let (true, Ok(response)) = (preserve_case, &mut result) {
+      let response_header_case = response.extensions_mut()
+        .remove::<ResponseHeaderCase>()
+        .unwrap_or_default()
+        .with_body(has_body);
+      connection.header_case.push_response(response_header_case);
+    }
+
+    if let Ok(response) = &result {
+      let body_size = if has_body {
+        response.body().size_hint().exact().unwrap_or_default() as usize
+      } else {
+        0
+      };
+      span.record("http.response.status_code", response.status().as_u16());
+      let response_details = ResponseDetails {
+        request_id: request_details.request_id,
+        status: response.status().as_u16(),
+        body_size,
+        prepared_at: SystemTime::now(),
+        duration: start.elapsed()
+      };
+      if event_send.send(MockServerEvent::ResponsePrepared(response_details)).await.is_err() {
+        error!("Failed to send ResponsePrepared event");
+      }
+    }
+  }.instrument(info_span!(parent: &span, "send_response")).await;
+
+  result
+}
+
+/// Creates the span for a received request. If OpenTelemetry support is enabled, the trace
+/// context from the request headers will be used as the parent of the span.
+fn request_span(req: &Request<Incoming>, request_details: &RequestDetails) -> Span {
+  let span = info_span!("mock_server_request",
+    otel.name = %format!("{} {}", req.method(), req.uri().path()),
+    otel.kind = "server",
+    http.request.method = %req.method(),
+    url.path = %req.uri().path(),
+    network.protocol.version = %request_details.http_version,
+    client.address = %request_details.remote_address.ip(),
+    client.port = request_details.remote_address.port(),
+    request_id = request_details.request_id,
+    http.response.status_code = Empty
+  );
+  #[cfg(feature = "otel")] crate::otel::set_parent_context(&span, req.headers());
+  span
+}
+
+/// Returns the response for a request to the reserved metrics path, if that has been configured
+fn metrics_response(
+  req: &Request<Incoming>,
+  config: &MockServerConfig,
+  server: &ServerState
+) -> Option<Response<Full<Bytes>>> {
+  config.metrics_path.as_ref()
+    .filter(|path| req.method() == Method::GET && req.uri().path() == path.as_str())
+    .map(|_| {
+      let body = server.metrics.lock().unwrap().to_openmetrics(&server.server_id);
+      Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+    })
+}
+
+/// Returns the response for a request to the admin API, if that has been enabled and the request
+/// path is under the admin path prefix
+async fn admin_request_response(
+  req: &Request<Incoming>,
+  pact: &V4Pact,
+  config: &MockServerConfig,
+  connection: &ConnectionState
+) -> Option<Response<Full<Bytes>>> {
+  let sub_path = config.admin_path.as_ref()
+    .and_then(|admin_path| admin_sub_path(admin_path, req.uri().path()))?
+    .to_string();
+  let server = &connection.server;
+  let mock_server = MockServer::detached(server.server_id.clone(), server.scheme.clone(),
+    connection.local_address, pact.clone(), config.clone(), server.requests.clone(), server.metrics.clone());
+  let method = req.method().clone();
+  let query = req.uri().query().map(|query| query.to_string());
+  // Writing the Pact file can block while waiting for the lock on the file, so the admin requests
+  // are handled on the blocking thread pool
+  let response = tokio::task::spawn_blocking(move || {
+    admin_response(&mock_server, &method, &sub_path, query.as_deref())
+  }).await;
+  match response {
+    Ok(response) => Some(response),
+    Err(err) => {
+      error!("Admin request failed - {}", err);
+      Some(Response::builder()
+        .status(500)
+        .body(Full::new(Bytes::new()))
+        .unwrap())
+    }
+  }
 }
 
 /// Main hyper request handler
@@ -278,17 +636,21 @@ async fn handle_request(
   req: Request<Incoming>,
   pact: V4Pact,
   event_send: Sender<MockServerEvent>,
-  local_addr: &SocketAddr,
-  config: &MockServerConfig
+  request_details: &RequestDetails,
+  config: &MockServerConfig,
+  raw_headers: Option<RawHeaders>,
+  server: &ServerState
 ) -> Result<Response<Full<Bytes>>, InteractionError> {
   let path = req.uri().path().to_string();
-  debug!(%path, "Creating pact request from hyper request");
+  debug!(%path, request_id = request_details.request_id, remote_address = %request_details.remote_address,
+    "Creating pact request from hyper request");
 
-  if let Err(_) = event_send.send(MockServerEvent::RequestReceived(path)).await {
+  server.metrics.lock().unwrap().add_path(path.clone());
+  if event_send.send(MockServerEvent::RequestReceived(path)).await.is_err() {
     error!("Failed to send RequestReceived event");
   }
 
-  let pact_request = hyper_request_to_pact_request(req).await?;
+  let (pact_request, raw_body) = hyper_request_to_pact_request(req, raw_headers.as_ref()).await?;
   info!("Received request {} {}", pact_request.method, pact_request.path);
   if pact_request.has_text_body() {
     debug!(
@@ -320,13 +682,34 @@ async fn handle_request(
     );
   }
 
-  let match_result = match_request(&pact_request, &pact).await;
+  let (match_result, interaction) = match_request_to_interaction(&pact_request, &pact)
+    .instrument(info_span!("match_request"))
+    .await;
 
-  if let Err(_) = event_send.send(MockServerEvent::RequestMatch(match_result.clone())).await {
+  let recorded = RecordedRequest {
+    result: match_result.clone(),
+    interaction,
+    details: request_details.clone(),
+    raw_body,
+    raw_headers
+  };
+  // The request is recorded before the response is sent, so it is always visible to a client
+  // once it has received the response (the same applies to the request metrics above)
+  let description = interaction
+    .and_then(|index| pact.interactions.get(index))
+    .map(|interaction| interaction.description());
+  server.metrics.lock().unwrap().add_match_result(&match_result, description);
+  server.requests.lock().unwrap().push(recorded.clone());
+  if event_send.send(MockServerEvent::RequestRecorded(recorded)).await.is_err() {
+    error!("Failed to send RequestRecorded event");
+  }
+  if event_send.send(MockServerEvent::RequestMatch(match_result.clone())).await.is_err() {
     error!("Failed to send RequestMatch event");
   }
 
-  match_result_to_hyper_response(&pact_request, &match_result, local_addr, config).await
+  match_result_to_hyper_response(&pact_request, &match_result, &request_details.local_address, config)
+    .instrument(info_span!("generate_response"))
+    .await
 }
 
 fn handle_mock_request_error(result: Result<Response<Full<Bytes>>, InteractionError>) -> Result<Response<Full<Bytes>>, hyper::Error> {
@@ -401,11 +784,53 @@ fn extract_body(bytes: Bytes) -> OptionalBody {
   }
 }
 
-async fn hyper_request_to_pact_request(req: Request<Incoming>) -> Result<HttpRequest, InteractionError> {
+/// Decodes the request body if the request has a `Content-Encoding` header, so that it can be
+/// matched against the expected body. Returns the body to match with, and the raw bytes as they
+/// were received if the body was decoded. If the body can not be decoded, the raw bytes are used.
+#[cfg(feature = "compression")]
+fn decode_request_body(bytes: Bytes, headers: &Option<HashMap<String, Vec<String>>>) -> (Bytes, Option<Bytes>) {
+  match find_header(headers, "content-encoding") {
+    Some(values) if !bytes.is_empty() => match parse_content_encoding(values) {
+      Ok(encodings) if encodings.is_empty() => (bytes, None),
+      Ok(encodings) => match decode_body(&bytes, &encodings) {
+        Ok(decoded) => {
+          debug!("Decoded request body with content encoding {:?} ({} bytes -> {} bytes)",
+            values, bytes.len(), decoded.len());
+          (decoded, Some(bytes))
+        }
+        Err(err) => {
+          warn!("Failed to decode request body with content encoding {:?}, using the raw bytes: {}", values, err);
+          (bytes, None)
+        }
+      },
+      Err(err) => {
+        warn!("Not decoding request body, using the raw bytes: {}", err);
+        (bytes, None)
+      }
+    },
+    _ => (bytes, None)
+  }
+}
+
+#[cfg(not(feature = "compression"))]
+fn decode_request_body(bytes: Bytes, _headers: &Option<HashMap<String, Vec<String>>>) -> (Bytes, Option<Bytes>) {
+  (bytes, None)
+}
+
+/// Converts the hyper request into a Pact request. Also returns the raw body of the request if
+/// the body was decoded.
+async fn hyper_request_to_pact_request(
+  req: Request<Incoming>,
+  raw_headers: Option<&RawHeaders>
+) -> Result<(HttpRequest, Option<Bytes>), InteractionError> {
   let method = req.method().to_string();
   let path = req.uri().path().to_string();
   let query = extract_query_string(req.uri());
-  let headers = extract_headers(req.headers())?;
+  let mut headers = extract_headers(req.headers())?;
+  if let Some(raw_headers) = raw_headers {
+    debug!(?raw_headers, "Request headers as received");
+    headers = headers.map(|headers| apply_original_case(headers, raw_headers));
+  }
 
   let body_bytes = req.collect().await
     .map(|b| b.to_bytes())
@@ -413,17 +838,18 @@ async fn hyper_request_to_pact_request(req: Request<Incoming>) -> Result<HttpReq
       error!("Failed to read request body: {}", err);
       InteractionError::RequestBodyError
     })?;
+  let (body_bytes, raw_body) = decode_request_body(body_bytes, &headers);
   let body = extract_body(body_bytes);
   let content_type = calc_content_type(&body, &headers);
 
-  Ok(HttpRequest {
+  Ok((HttpRequest {
     method,
     path,
     query,
     headers,
     body: body.with_content_type(content_type),
     .. HttpRequest::default()
-  })
+  }, raw_body))
 }
 
 async fn match_result_to_hyper_response(
@@ -432,7 +858,7 @@ async fn match_result_to_hyper_response(
   local_addr: &SocketAddr,
   config: &MockServerConfig
 ) -> Result<Response<Full<Bytes>>, InteractionError> {
-  let cors_preflight = config.cors_preflight;
+  let cors_preflight = config.cors_preflight && config.cors_policy.enabled;
   let context = hashmap!{
     "mockServer" => json!({
       "url": format!("http://{}", local_addr),
@@ -440,17 +866,15 @@ async fn match_result_to_hyper_response(
     })
   };
 
-  let origin = match request.headers.clone() {
-    Some(ref h) => h.iter()
-      .find(|kv| kv.0.to_lowercase() == "origin")
-      .map(|kv| kv.1.clone().join(", ")).unwrap_or("*".to_string()),
-    None => "*".to_string()
-  };
+  let origin = request.headers.as_ref()
+    .and_then(|h| h.iter().find(|kv| kv.0.to_lowercase() == "origin"))
+    .map(|kv| kv.1.join(", "));
+  let cors_policy = &config.cors_policy;
 
   match match_result {
     MatchResult::RequestMatch(_, response, _) => {
       debug!("Test context = {:?}", context);
-      let response = pact_matching::generate_response(response, &GeneratorTestMode::Consumer, &context).await;
+      let mut response = pact_matching::generate_response(response, &GeneratorTestMode::Consumer, &context).await;
       info!("Request matched, sending response");
       if response.has_text_body() {
         debug!(
@@ -475,45 +899,43 @@ async fn match_result_to_hyper_response(
       }
 
       let mut builder = Response::builder()
-        .status(response.status)
-        .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, &origin)
-        .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
-        .header(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, POST, PUT, DELETE, CONNECT, OPTIONS, TRACE, PATCH")
-        .header(hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS, "Location, Link")
-        .header(hyper::header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        .status(response.status);
+      for (name, value) in cors_policy.response_headers(origin.as_deref()) {
+        builder = builder.header(name, value);
+      }
 
+      let body = encode_response_body(request, &mut response, config);
       set_hyper_headers(&mut builder, &response.headers)?;
+      if let (true, Some(headers)) = (config.preserve_header_case, &response.headers) {
+        builder = builder.extension(ResponseHeaderCase::new(headers.keys(), true));
+      }
 
-      builder.body(match response.body {
-        OptionalBody::Present(b, _, _) => Full::new(b),
-        _ => Full::new(Bytes::new())
-      })
+      builder.body(Full::new(body))
         .map_err(|_| InteractionError::ResponseBodyError)
     },
     _ => {
       debug!("Request did not match: {}", match_result);
       if cors_preflight && request.method.to_uppercase() == "OPTIONS" {
         info!("Responding to CORS pre-flight request");
-        let cors_headers = match request.headers.clone() {
-          Some(ref h) => h.iter()
-            .find(|kv| kv.0.to_lowercase() == "access-control-request-headers")
-            .map(|kv| kv.1.clone().join(", ") + ", *").unwrap_or("*".to_string()),
-          None => "*".to_string()
-        };
-
-        Response::builder()
-          .status(204)
-          .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, &origin)
-          .header(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, POST, PUT, DELETE, CONNECT, OPTIONS, TRACE, PATCH")
-          .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, cors_headers)
-          .header(hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS, "Location, Link")
-          .header(hyper::header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")
+        let request_headers = request.headers.as_ref()
+          .and_then(|h| h.iter().find(|kv| kv.0.to_lowercase() == "access-control-request-headers"))
+          .map(|kv| kv.1.join(", "));
+
+        let mut builder = Response::builder()
+          .status(204);
+        for (name, value) in cors_policy.preflight_headers(origin.as_deref(), request_headers.as_deref()) {
+          builder = builder.header(name, value);
+        }
+        builder
           .body(Full::new(Bytes::new()))
           .map_err(|_| InteractionError::ResponseBodyError)
       } else {
-        Response::builder()
-          .status(500)
-          .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        let mut builder = Response::builder()
+          .status(500);
+        if let Some(allow_origin) = cors_policy.allow_origin(origin.as_deref()) {
+          builder = builder.header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        }
+        builder
           .header(hyper::header::CONTENT_TYPE, "application/json; charset=utf-8")
           .header("X-Pact", match_result.match_key())
           .body(Full::new(Bytes::from(error_body(&request, &match_result.match_key()))))
@@ -523,11 +945,93 @@ async fn match_result_to_hyper_response(
   }
 }
 
+/// Returns the bytes of the response body, compressing them if either the response has a
+/// `Content-Encoding` header (and the body is not already encoded), or response compression has
+/// been enabled and the request `Accept-Encoding` header allows it. A body in the Pact file is
+/// treated as already encoded if it is a binary body (it has a binary content type, or the
+/// `BINARY` content type hint), otherwise it is encoded using the `Content-Encoding` header.
+#[cfg(feature = "compression")]
+fn encode_response_body(
+  request: &HttpRequest,
+  response: &mut HttpResponse,
+  config: &MockServerConfig
+) -> Bytes {
+  let body = match &response.body {
+    OptionalBody::Present(b, _, _) if !b.is_empty() => b.clone(),
+    _ => return Bytes::new()
+  };
+
+  if let Some(values) = find_header(&response.headers, "content-encoding") {
+    match parse_content_encoding(values) {
+      Ok(_) if is_binary_body(&response.body) => {
+        debug!("Response body is binary, sending it as already encoded with content encoding {:?}", values);
+        body
+      }
+      Ok(encodings) => encodings.iter()
+        .try_fold(body.clone(), |data, encoding| encoding.encode(&data))
+        .unwrap_or_else(|err| {
+          warn!("Failed to encode response body with content encoding {:?}, sending the raw bytes: {}", values, err);
+          body
+        }),
+      Err(err) => {
+        warn!("Not encoding response body: {}", err);
+        body
+      }
+    }
+  } else if config.compress_responses {
+    match find_header(&request.headers, "accept-encoding").and_then(|values| select_accepted_encoding(values)) {
+      Some(encoding) => match encoding.encode(&body) {
+        Ok(encoded) => {
+          debug!("Compressed response body with content encoding '{}'", encoding.header_value());
+          let headers = response.headers.get_or_insert_with(HashMap::new);
+          headers.insert("Content-Encoding".to_string(), vec![encoding.header_value().to_string()]);
+          match headers.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case("vary")) {
+            Some((_, vary)) => vary.push("Accept-Encoding".to_string()),
+            None => {
+              headers.insert("Vary".to_string(), vec!["Accept-Encoding".to_string()]);
+            }
+          }
+          encoded
+        }
+        Err(err) => {
+          warn!("Failed to compress response body, sending the raw bytes: {}", err);
+          body
+        }
+      },
+      None => body
+    }
+  } else {
+    body
+  }
+}
+
+/// If the body from the Pact file is a binary body, based on its content type and content type hint
+#[cfg(feature = "compression")]
+fn is_binary_body(body: &OptionalBody) -> bool {
+  match body {
+    OptionalBody::Present(_, content_type, hint) => *hint == Some(ContentTypeHint::BINARY)
+      || content_type.as_ref().map(|content_type| content_type.is_binary()).unwrap_or(false),
+    _ => false
+  }
+}
+
+#[cfg(not(feature = "compression"))]
+fn encode_response_body(
+  _request: &HttpRequest,
+  response: &mut HttpResponse,
+  _config: &MockServerConfig
+) -> Bytes {
+  match &response.body {
+    OptionalBody::Present(b, _, _) => b.clone(),
+    _ => Bytes::new()
+  }
+}
+
 fn set_hyper_headers(builder: &mut Builder, headers: &Option<HashMap<String, Vec<String>>>) -> Result<(), InteractionError> {
   let hyper_headers = builder.headers_mut().unwrap();
   match headers {
     Some(header_map) => {
-      for (k, v) in header_map {
+      for (k, v) in header_map.iter().sorted_by(|(a, _), (b, _)| Ord::cmp(a, b)) {
         for val in v {
           hyper_headers.append(
           HeaderName::from_bytes(k.as_bytes())
@@ -557,7 +1061,7 @@ fn error_body(request: &HttpRequest, error: &String) -> String {
 #[cfg(test)]
 mod tests {
   use expectest::prelude::*;
-  use hyper::header::{ACCEPT, CONTENT_TYPE, USER_AGENT};
+  use hyper::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, USER_AGENT};
   use hyper::HeaderMap;
   use pact_models::pact::Pact;
   use pact_models::prelude::RequestResponseInteraction;
@@ -569,14 +1073,16 @@ mod tests {
 
   #[tokio::test]
   async fn can_fetch_results_on_current_thread() {
-    let (_addr, shutdown, mut events, handle) = create_and_bind(
+    let (_addresses, shutdown, mut events, handle) = create_and_bind(
       "can_fetch_results_on_current_thread".to_string(),
       RequestResponsePact::default().as_v4_pact().unwrap(),
-      ([0, 0, 0, 0], 0u16).into(),
-      MockServerConfig::default()
+      &[([0, 0, 0, 0], 0u16).into()],
+      MockServerConfig::default(),
+      Default::default(),
+      Default::default()
     ).await.unwrap();
 
-    shutdown.send(()).unwrap();
+    shutdown.send(None).unwrap();
     let _ = handle.await;
 
     // Only the shutdown event should be generated
@@ -599,35 +1105,417 @@ mod tests {
     })));
   }
 
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  #[cfg(feature = "compression")]
+  async fn decodes_compressed_request_bodies() {
+    use crate::compression::ContentEncoding;
+
+    let pact = RequestResponsePact {
+      interactions: vec![ RequestResponseInteraction {
+        request: pact_models::request::Request {
+          method: "POST".to_string(),
+          headers: Some(hashmap!{ "Content-Type".to_string() => vec!["application/json".to_string()] }),
+          body: OptionalBody::Present(json!({ "id": 100, "name": "Fred" }).to_string().into(), None, None),
+          .. pact_models::request::Request::default()
+        },
+        .. RequestResponseInteraction::default()
+      } ],
+      .. RequestResponsePact::default()
+    };
+    let (addresses, shutdown, mut events, handle) = create_and_bind(
+      "decodes_compressed_request_bodies".to_string(),
+      pact.as_v4_pact().unwrap(),
+      &[([127, 0, 0, 1], 0u16).into()],
+      MockServerConfig::default(),
+      Default::default(),
+      Default::default()
+    ).await.unwrap();
+
+    let client = reqwest::Client::new();
+    let body = ContentEncoding::Gzip.encode(json!({ "id": 100, "name": "Fred" }).to_string().as_bytes()).unwrap();
+    let response = client.post(format!("http://127.0.0.1:{}", addresses[0].port()))
+      .header(CONTENT_TYPE, "application/json")
+      .header(CONTENT_ENCODING, "gzip")
+      .body(body.clone())
+      .send()
+      .await
+      .unwrap();
+    expect!(response.status()).to(be_equal_to(StatusCode::OK));
+
+    shutdown.send(None).unwrap();
+    let _ = handle.await;
+
+    let mut events_list = vec![];
+    events.recv_many(&mut events_list, 10).await;
+    let matched = events_list.iter().any(|event| match event {
+      MockServerEvent::RequestMatch(result) => result.matched(),
+      _ => false
+    });
+    expect!(matched).to(be_true());
+    let raw_body = events_list.iter().find_map(|event| match event {
+      MockServerEvent::RequestRecorded(request) => request.raw_body.clone(),
+      _ => None
+    });
+    expect!(raw_body).to(be_some().value(body));
+  }
+
+  #[test]
+  #[cfg(feature = "compression")]
+  fn encode_response_body_uses_the_body_metadata_to_detect_encoded_bodies() {
+    use pact_models::content_types::{ContentType, ContentTypeHint};
+    use crate::compression::ContentEncoding;
+
+    let encoded = ContentEncoding::Gzip.encode(b"hello").unwrap();
+    let headers = Some(hashmap!{ "Content-Encoding".to_string() => vec!["gzip".to_string()] });
+    let request = HttpRequest::default();
+    let config = MockServerConfig::default();
+
+    // A text body is always encoded, even if it happens to be valid gzip data
+    let mut response = HttpResponse {
+      headers: headers.clone(),
+      body: OptionalBody::Present(encoded.clone(), Some(ContentType::from("text/plain")), None),
+      .. HttpResponse::default()
+    };
+    let body = encode_response_body(&request, &mut response, &config);
+    expect!(ContentEncoding::Gzip.decode(&body).unwrap()).to(be_equal_to(encoded.clone()));
+
+    // Binary bodies are sent as they are
+    let mut response = HttpResponse {
+      headers: headers.clone(),
+      body: OptionalBody::Present(encoded.clone(), Some(ContentType::from("text/plain")), Some(ContentTypeHint::BINARY)),
+      .. HttpResponse::default()
+    };
+    expect!(encode_response_body(&request, &mut response, &config)).to(be_equal_to(encoded.clone()));
+    let mut response = HttpResponse {
+      headers,
+      body: OptionalBody::Present(encoded.clone(), Some(ContentType::from("application/octet-stream")), None),
+      .. HttpResponse::default()
+    };
+    expect!(encode_response_body(&request, &mut response, &config)).to(be_equal_to(encoded));
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  #[cfg(feature = "compression")]
+  async fn compresses_responses_when_accepted() {
+    use crate::compression::ContentEncoding;
+
+    let pact = RequestResponsePact {
+      interactions: vec![ RequestResponseInteraction {
+        response: pact_models::response::Response {
+          body: OptionalBody::from("Hello from the mock server"),
+          .. pact_models::response::Response::default()
+        },
+        .. RequestResponseInteraction::default()
+      } ],
+      .. RequestResponsePact::default()
+    };
+    let (addresses, shutdown, _events, handle) = create_and_bind(
+      "compresses_responses_when_accepted".to_string(),
+      pact.as_v4_pact().unwrap(),
+      &[([127, 0, 0, 1], 0u16).into()],
+      MockServerConfig { compress_responses: true, .. MockServerConfig::default() },
+      Default::default(),
+      Default::default()
+    ).await.unwrap();
+
+    let client = reqwest::Client::new();
+    let response = client.get(format!("http://127.0.0.1:{}", addresses[0].port()))
+      .header(ACCEPT_ENCODING, "gzip;q=0.5, br")
+      .send()
+      .await
+      .unwrap();
+    expect!(response.status()).to(be_equal_to(StatusCode::OK));
+    expect!(response.headers().get(CONTENT_ENCODING).unwrap().to_str().unwrap()).to(be_equal_to("br"));
+    let body = response.bytes().await.unwrap();
+    expect!(ContentEncoding::Brotli.decode(&body).unwrap()).to(be_equal_to(Bytes::from("Hello from the mock server")));
+
+    shutdown.send(None).unwrap();
+    let _ = handle.await;
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  async fn preserves_header_case_for_http1() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let pact = RequestResponsePact {
+      interactions: vec![ RequestResponseInteraction {
+        response: pact_models::response::Response {
+          headers: Some(hashmap!{
+            "X-Custom-HEADER".to_string() => vec!["1".to_string()],
+            "content-TYPE".to_string() => vec!["text/plain".to_string()]
+          }),
+          body: OptionalBody::from("ok"),
+          .. pact_models::response::Response::default()
+        },
+        .. RequestResponseInteraction::default()
+      } ],
+      .. RequestResponsePact::default()
+    };
+    let (addresses, shutdown, mut events, handle) = create_and_bind(
+      "preserves_header_case_for_http1".to_string(),
+      pact.as_v4_pact().unwrap(),
+      &[([127, 0, 0, 1], 0u16).into()],
+      MockServerConfig { preserve_header_case: true, keep_alive: false, .. MockServerConfig::default() },
+      Default::default(),
+      Default::default()
+    ).await.unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(addresses[0]).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-API-KEY: 1234\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    shutdown.send(None).unwrap();
+    let _ = handle.await;
+
+    expect!(response.starts_with("HTTP/1.1 200 OK\r\n")).to(be_true());
+    expect!(response.contains("\r\nX-Custom-HEADER: 1\r\n")).to(be_true());
+    expect!(response.contains("\r\ncontent-TYPE: text/plain\r\n")).to(be_true());
+    expect!(response.contains("\r\nAccess-Control-Allow-Origin: *\r\n")).to(be_true());
+    expect!(response.ends_with("\r\n\r\nok")).to(be_true());
+
+    let mut events_list = vec![];
+    events.recv_many(&mut events_list, 10).await;
+    let headers = events_list.iter().find_map(|event| match event {
+      MockServerEvent::RequestMatch(MatchResult::RequestMatch(_, _, request)) => request.headers.clone(),
+      _ => None
+    }).unwrap();
+    expect!(headers.get("X-API-KEY")).to(be_some().value(&vec!["1234".to_string()]));
+    expect!(headers.get("Host")).to(be_some().value(&vec!["localhost".to_string()]));
+
+    let raw_headers = events_list.iter().find_map(|event| match event {
+      MockServerEvent::RequestRecorded(recorded) => recorded.raw_headers.clone(),
+      _ => None
+    });
+    expect!(raw_headers).to(be_some().value(vec![
+      ("Host".to_string(), "localhost".to_string()),
+      ("X-API-KEY".to_string(), "1234".to_string()),
+      ("Connection".to_string(), "close".to_string())
+    ]));
+    // Response headers are written in the order of their names
+    expect!(response.find("X-Custom-HEADER")).to(be_some());
+    expect!(response.find("X-Custom-HEADER") < response.find("content-TYPE")).to(be_true());
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  async fn preserves_header_case_after_an_admin_request_on_the_same_connection() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let pact = RequestResponsePact {
+      interactions: vec![ RequestResponseInteraction {
+        response: pact_models::response::Response {
+          headers: Some(hashmap!{
+            "X-Custom-HEADER".to_string() => vec!["1".to_string()]
+          }),
+          body: OptionalBody::from("ok"),
+          .. pact_models::response::Response::default()
+        },
+        .. RequestResponseInteraction::default()
+      } ],
+      .. RequestResponsePact::default()
+    };
+    let (addresses, shutdown, mut events, handle) = create_and_bind(
+      "preserves_header_case_after_an_admin_request".to_string(),
+      pact.as_v4_pact().unwrap(),
+      &[([127, 0, 0, 1], 0u16).into()],
+      MockServerConfig {
+        preserve_header_case: true,
+        admin_path: Some("/__pact".to_string()),
+        .. MockServerConfig::default()
+      },
+      Default::default(),
+      Default::default()
+    ).await.unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(addresses[0]).await.unwrap();
+    stream.write_all(b"GET /__pact/status HTTP/1.1\r\nHost: localhost\r\nX-ADMIN: 1\r\n\r\n\
+      GET / HTTP/1.1\r\nHost: localhost\r\nX-API-KEY: 1234\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    shutdown.send(None).unwrap();
+    let _ = handle.await;
+
+    expect!(response.matches("HTTP/1.1 200 OK\r\n").count()).to(be_equal_to(2));
+    expect!(response.contains("\r\nX-Custom-HEADER: 1\r\n")).to(be_true());
+    expect!(response.ends_with("\r\n\r\nok")).to(be_true());
+
+    let mut events_list = vec![];
+    events.recv_many(&mut events_list, 10).await;
+    let raw_headers = events_list.iter().find_map(|event| match event {
+      MockServerEvent::RequestRecorded(recorded) => recorded.raw_headers.clone(),
+      _ => None
+    });
+    expect!(raw_headers).to(be_some().value(vec![
+      ("Host".to_string(), "localhost".to_string()),
+      ("X-API-KEY".to_string(), "1234".to_string()),
+      ("Connection".to_string(), "close".to_string())
+    ]));
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  async fn keeps_the_order_of_header_values() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let pact = RequestResponsePact {
+      interactions: vec![ RequestResponseInteraction {
+        response: pact_models::response::Response {
+          headers: Some(hashmap!{
+            "X-Value".to_string() => vec!["2".to_string(), "1".to_string(), "3".to_string()]
+          }),
+          .. pact_models::response::Response::default()
+        },
+        .. RequestResponseInteraction::default()
+      } ],
+      .. RequestResponsePact::default()
+    };
+
+    for preserve_header_case in [false, true] {
+      let (addresses, shutdown, mut events, handle) = create_and_bind(
+        "keeps_the_order_of_header_values".to_string(),
+        pact.as_v4_pact().unwrap(),
+        &[([127, 0, 0, 1], 0u16).into()],
+        MockServerConfig { preserve_header_case, keep_alive: false, .. MockServerConfig::default() },
+        Default::default(),
+        Default::default()
+      ).await.unwrap();
+
+      let mut stream = tokio::net::TcpStream::connect(addresses[0]).await.unwrap();
+      stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Value: b\r\nX-Value: a\r\nX-Value: c\r\nConnection: close\r\n\r\n").await.unwrap();
+      let mut response = String::new();
+      stream.read_to_string(&mut response).await.unwrap();
+
+      shutdown.send(None).unwrap();
+      let _ = handle.await;
+
+      let values = response.split("\r\n")
+        .filter_map(|line| line.split_once(": "))
+        .filter(|(name, _)| name.eq_ignore_ascii_case("x-value"))
+        .map(|(_, value)| value)
+        .collect::<Vec<_>>();
+      expect!(values).to(be_equal_to(vec!["2", "1", "3"]));
+
+      let mut events_list = vec![];
+      events.recv_many(&mut events_list, 10).await;
+      let headers = events_list.iter().find_map(|event| match event {
+        MockServerEvent::RequestMatch(MatchResult::RequestMatch(_, _, request)) => request.headers.clone(),
+        _ => None
+      }).unwrap();
+      let values = headers.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("x-value"))
+        .map(|(_, values)| values.clone());
+      expect!(values).to(be_some().value(vec!["b".to_string(), "a".to_string(), "c".to_string()]));
+    }
+  }
+
+  #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+  async fn applies_the_cors_policy() {
+    use crate::cors::CorsPolicy;
+
+    let pact = RequestResponsePact {
+      interactions: vec![ RequestResponseInteraction::default() ],
+      .. RequestResponsePact::default()
+    };
+    let client = reqwest::Client::new();
+
+    let (addresses, shutdown, _events, handle) = create_and_bind(
+      "applies_the_cors_policy_disabled".to_string(),
+      pact.as_v4_pact().unwrap(),
+      &[([127, 0, 0, 1], 0u16).into()],
+      MockServerConfig { cors_policy: CorsPolicy::disabled(), .. MockServerConfig::default() },
+      Default::default(),
+      Default::default()
+    ).await.unwrap();
+    let response = client.get(format!("http://127.0.0.1:{}", addresses[0].port()))
+      .header("Origin", "http://localhost:3000")
+      .send()
+      .await
+      .unwrap();
+    expect!(response.status()).to(be_equal_to(StatusCode::OK));
+    expect!(response.headers().keys().any(|k| k.as_str().starts_with("access-control-"))).to(be_false());
+    shutdown.send(None).unwrap();
+    let _ = handle.await;
+
+    let (addresses, shutdown, _events, handle) = create_and_bind(
+      "applies_the_cors_policy_restricted".to_string(),
+      pact.as_v4_pact().unwrap(),
+      &[([127, 0, 0, 1], 0u16).into()],
+      MockServerConfig {
+        cors_preflight: true,
+        cors_policy: CorsPolicy {
+          allowed_origins: vec!["https://example.com".to_string()],
+          allowed_methods: vec!["GET".to_string()],
+          allow_credentials: false,
+          max_age: Some(60),
+          .. CorsPolicy::default()
+        },
+        .. MockServerConfig::default()
+      },
+      Default::default(),
+      Default::default()
+    ).await.unwrap();
+    let response = client.request(Method::OPTIONS, format!("http://127.0.0.1:{}/path", addresses[0].port()))
+      .header("Origin", "https://example.com")
+      .header("Access-Control-Request-Headers", "x-token")
+      .send()
+      .await
+      .unwrap();
+    expect!(response.status()).to(be_equal_to(StatusCode::NO_CONTENT));
+    let headers = response.headers();
+    expect!(headers.get("access-control-allow-origin").unwrap().to_str().unwrap()).to(be_equal_to("https://example.com"));
+    expect!(headers.get("access-control-allow-methods").unwrap().to_str().unwrap()).to(be_equal_to("GET"));
+    expect!(headers.get("access-control-allow-headers").unwrap().to_str().unwrap()).to(be_equal_to("x-token, *"));
+    expect!(headers.get("access-control-max-age").unwrap().to_str().unwrap()).to(be_equal_to("60"));
+    expect!(headers.get("access-control-allow-credentials")).to(be_none());
+
+    let response = client.get(format!("http://127.0.0.1:{}", addresses[0].port()))
+      .header("Origin", "https://other.com")
+      .send()
+      .await
+      .unwrap();
+    expect!(response.status()).to(be_equal_to(StatusCode::OK));
+    expect!(response.headers().get("access-control-allow-origin")).to(be_none());
+    shutdown.send(None).unwrap();
+    let _ = handle.await;
+  }
+
   #[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
   async fn support_http2() {
     let pact = RequestResponsePact {
       interactions: vec![ RequestResponseInteraction::default() ],
       .. RequestResponsePact::default()
     };
-    let (addr, shutdown, mut events, handle) = create_and_bind(
+    let (addresses, shutdown, mut events, handle) = create_and_bind(
       "can_fetch_results_on_current_thread".to_string(),
       pact.as_v4_pact().unwrap(),
-      ([127, 0, 0, 1], 0u16).into(),
-      MockServerConfig::default()
+      &[([127, 0, 0, 1], 0u16).into()],
+      MockServerConfig::default(),
+      Default::default(),
+      Default::default()
     ).await.unwrap();
 
     let client = reqwest::ClientBuilder::new()
       .http2_prior_knowledge()
       .build()
       .unwrap();
-    let response = client.get(format!("http://127.0.0.1:{}", addr.port()))
+    let response = client.get(format!("http://127.0.0.1:{}", addresses[0].port()))
       .send()
       .await
       .unwrap();
     expect!(response.status()).to(be_equal_to(StatusCode::OK));
 
-    shutdown.send(()).unwrap();
+    shutdown.send(None).unwrap();
     let _ = handle.await;
 
-    // Should be at least 3 events
-    expect!(events.len()).to(be_greater_or_equal_to(3));
+    // Should be at least 4 events
+    expect!(events.len()).to(be_greater_or_equal_to(4));
+    expect!(matches!(events.recv().await.unwrap(), MockServerEvent::ConnectionOpened(_))).to(be_true());
     assert_eq!(events.recv().await.unwrap(), MockServerEvent::RequestReceived("/".to_string()));
+    if let MockServerEvent::RequestRecorded(request) = events.recv().await.unwrap() {
+      expect!(request.details.http_version).to(be_equal_to("HTTP/2.0"));
+    } else {
+      panic!("Was expected a request recorded event");
+    }
     if let MockServerEvent::RequestMatch(_) = events.recv().await.unwrap() {
       // expected
     } else {
@@ -635,7 +1523,8 @@ mod tests {
     }
     // For some reason, a http2 connection returns an error once the server is shutdown on Linux
     let mut events_list = vec![];
-    events.recv_many(&mut events_list, 2).await;
+    events.recv_many(&mut events_list, 4).await;
+    expect!(matches!(events_list.first(), Some(MockServerEvent::ResponsePrepared(_)))).to(be_true());
     assert_eq!(events_list.last().unwrap(), &MockServerEvent::ServerShutdown);
   }
 }
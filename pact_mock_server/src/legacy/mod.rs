@@ -369,7 +369,8 @@ pub fn write_pact_file(
             };
 
             info!("Writing pact out to '{}'", filename.display());
-            match pact_models::pact::write_pact(pact.boxed(), filename.as_path(), pact_models::PactSpecification::V4, overwrite) {
+            match crate::pact_writer::write_pact(&pact, filename.as_path(), pact_models::PactSpecification::V4, overwrite,
+              crate::pact_writer::MergePolicy::default()) {
               Ok(_) => Ok(()),
               Err(err) => {
                 warn!("Failed to write pact to file - {}", err);
@@ -11,6 +11,11 @@
 //! * `plugins`: Enables support for using plugins.
 //! * `multipart`: Enables support for MIME multipart bodies.
 //! * `tls`: Enables support for mock servers using TLS. This will add the following dependencies: hyper-rustls, rustls, rustls-pemfile, tokio-rustls.
+//! * `compression`: Enables support for compressed request and response bodies (gzip, deflate, br and zstd). This will add the following dependencies: flate2, brotli, zstd.
+//!
+//! The following feature is not enabled by default
+//!
+//! * `otel`: Uses the W3C trace context headers (`traceparent`) of received requests as the parent of the request spans, so they can be exported with OpenTelemetry. This will add the following dependencies: opentelemetry, tracing-opentelemetry.
 //!
 //! ## Creating a mock server
 //! Mock servers can be created by using the mock server builder in the `builder` package. The
@@ -74,6 +79,12 @@
 //! # Ok::<(), anyhow::Error>(())
 //! # });
 //! ```
+//!
+//! In tests, the `start_guarded` function on the builder can be used instead of `start`. This
+//! returns a `MockServerGuard` that will shut the mock server down when it is dropped, and panic
+//! with a report of the mismatches if not all the requests matched (even if the test fails
+//! before reaching the end). It can also be configured to write the pact file if all the requests
+//! matched.
 
 #![warn(missing_docs)]
 
@@ -96,17 +107,28 @@ use tokio::task_local;
 use crate::server_manager::ServerManager;
 
 pub mod matching;
+pub mod metrics;
 pub mod mock_server;
 pub mod server_manager;
 mod utils;
 pub mod legacy;
+pub mod logs;
 pub mod builder;
+mod admin;
+pub mod cors;
+pub mod downgrade;
+pub mod guard;
+pub mod pact_writer;
 pub mod hyper_server;
+mod header_case;
+#[cfg(feature = "compression")] pub mod compression;
+#[cfg(feature = "otel")] mod otel;
+#[cfg(feature = "broker")] pub mod broker;
 
 task_local! {
   /// Log ID to accumulate logs against
   #[allow(missing_docs)]
-  #[deprecated(note = "This must be moved to the FFI crate")]
+  #[deprecated(note = "This must be moved to the FFI crate. Use the logs::MockServerLogLayer to capture the logs for a mock server")]
   pub static LOG_ID: String;
 }
 
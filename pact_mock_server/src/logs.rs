@@ -0,0 +1,245 @@
+//! Capturing of the log entries for each mock server. This is done with a tracing `Layer`
+//! ([`MockServerLogLayer`]) that needs to be added to the tracing subscriber of the application.
+//! Any event that is logged within the span of a mock server (which has a `mock_server_id` field)
+//! is formatted and added to a bounded buffer for that mock server. The log entries can then be
+//! retrieved with `MockServer::logs()`. The buffer is owned by the mock server, and is discarded
+//! when the mock server is dropped.
+//!
+//! ```rust
+//! use pact_mock_server::logs::MockServerLogLayer;
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! let subscriber = tracing_subscriber::registry()
+//!   .with(MockServerLogLayer::with_capacity(500));
+//! let _ = tracing::subscriber::set_global_default(subscriber);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Write};
+use std::sync::{Arc, Mutex, Weak};
+
+use chrono::{SecondsFormat, Utc};
+use tracing::{Event, info_span, Span, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Name of the span field that contains the ID of the mock server
+pub const MOCK_SERVER_ID_FIELD: &str = "mock_server_id";
+
+/// Default number of log entries kept for each mock server
+pub const DEFAULT_LOG_CAPACITY: usize = 1000;
+
+/// Buffer of the log entries captured for a mock server
+pub(crate) type LogBuffer = Arc<Mutex<VecDeque<String>>>;
+
+lazy_static::lazy_static! {
+  static ref LOG_BUFFERS: Mutex<HashMap<String, Weak<Mutex<VecDeque<String>>>>> = Mutex::new(HashMap::new());
+}
+
+/// Tracing layer that captures the log entries for each mock server. Only the most recent entries
+/// (up to the capacity of the layer) are kept for each mock server.
+#[derive(Debug, Clone, Copy)]
+pub struct MockServerLogLayer {
+  capacity: usize
+}
+
+impl MockServerLogLayer {
+  /// Creates a new layer that keeps the default number of entries for each mock server
+  pub fn new() -> Self {
+    MockServerLogLayer::with_capacity(DEFAULT_LOG_CAPACITY)
+  }
+
+  /// Creates a new layer that keeps at most `capacity` entries for each mock server
+  pub fn with_capacity(capacity: usize) -> Self {
+    MockServerLogLayer { capacity }
+  }
+}
+
+impl Default for MockServerLogLayer {
+  fn default() -> Self {
+    MockServerLogLayer::new()
+  }
+}
+
+/// Span extension to store the mock server ID in
+struct MockServerId(String);
+
+impl<S> Layer<S> for MockServerLogLayer
+  where S: Subscriber + for<'span> LookupSpan<'span> {
+  fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+    let mut visitor = MockServerIdVisitor(None);
+    attrs.record(&mut visitor);
+    if let (Some(server_id), Some(span)) = (visitor.0, ctx.span(id)) {
+      span.extensions_mut().insert(MockServerId(server_id));
+    }
+  }
+
+  fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+    let server_id = ctx.event_scope(event)
+      .and_then(|mut scope| scope.find_map(|span| {
+        span.extensions().get::<MockServerId>().map(|id| id.0.clone())
+      }));
+    if let Some(server_id) = server_id {
+      add_log_entry(server_id, format_event(event), self.capacity);
+    }
+  }
+}
+
+struct MockServerIdVisitor(Option<String>);
+
+impl Visit for MockServerIdVisitor {
+  fn record_str(&mut self, field: &Field, value: &str) {
+    if field.name() == MOCK_SERVER_ID_FIELD {
+      self.0 = Some(value.to_string());
+    }
+  }
+
+  fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+    if field.name() == MOCK_SERVER_ID_FIELD {
+      self.0 = Some(format!("{:?}", value));
+    }
+  }
+}
+
+/// Formats the message and any other fields of an event
+#[derive(Default)]
+struct EventVisitor {
+  message: String,
+  fields: String
+}
+
+impl Visit for EventVisitor {
+  fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+    if field.name() == "message" {
+      let _ = write!(self.message, "{:?}", value);
+    } else {
+      let _ = write!(self.fields, " {}={:?}", field.name(), value);
+    }
+  }
+}
+
+fn format_event(event: &Event<'_>) -> String {
+  let mut visitor = EventVisitor::default();
+  event.record(&mut visitor);
+  let metadata = event.metadata();
+  format!("{} {:>5} {}: {}{}", Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+    metadata.level(), metadata.target(), visitor.message, visitor.fields)
+}
+
+fn add_log_entry(server_id: String, entry: String, capacity: usize) {
+  let buffer = {
+    let mut buffers = LOG_BUFFERS.lock().unwrap();
+    match buffers.get(&server_id).map(Weak::upgrade) {
+      Some(Some(buffer)) => buffer,
+      Some(None) => {
+        // The mock server has been dropped
+        buffers.remove(&server_id);
+        return;
+      }
+      None => return
+    }
+  };
+  let mut buffer = buffer.lock().unwrap();
+  while !buffer.is_empty() && buffer.len() >= capacity {
+    buffer.pop_front();
+  }
+  if capacity > 0 {
+    buffer.push_back(entry);
+  }
+}
+
+/// Creates the buffer for the log entries of a mock server. Log entries are only captured while
+/// the returned buffer has not been dropped. This replaces any buffer for a previous mock server
+/// with the same ID.
+pub(crate) fn register_log_buffer(server_id: &str) -> LogBuffer {
+  let buffer = LogBuffer::default();
+  let mut buffers = LOG_BUFFERS.lock().unwrap();
+  buffers.retain(|_, buffer| buffer.strong_count() > 0);
+  buffers.insert(server_id.to_string(), Arc::downgrade(&buffer));
+  buffer
+}
+
+/// Creates the span that the tasks of a mock server run in, so that their log entries can be
+/// captured against the mock server
+pub(crate) fn mock_server_span(server_id: &str) -> Span {
+  info_span!("mock_server", mock_server_id = %server_id)
+}
+
+/// Returns the captured log entries for the mock server with the given ID, oldest first. This will
+/// be empty if no `MockServerLogLayer` has been added to the tracing subscriber.
+pub fn mock_server_logs(server_id: &str) -> Vec<String> {
+  let buffer = LOG_BUFFERS.lock().unwrap()
+    .get(server_id)
+    .and_then(Weak::upgrade);
+  buffer
+    .map(|buffer| buffer.lock().unwrap().iter().cloned().collect())
+    .unwrap_or_default()
+}
+
+/// Discards the captured log entries for the mock server with the given ID, and stops capturing
+/// any more entries for it
+pub fn clear_mock_server_logs(server_id: &str) {
+  let buffer = LOG_BUFFERS.lock().unwrap()
+    .remove(server_id)
+    .and_then(|buffer| buffer.upgrade());
+  if let Some(buffer) = buffer {
+    buffer.lock().unwrap().clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use tracing::{info, info_span, warn};
+  use tracing_subscriber::layer::SubscriberExt;
+
+  use super::*;
+
+  #[test]
+  fn captures_the_log_entries_for_each_mock_server() {
+    let _buffer_1 = register_log_buffer("log-test-1");
+    let _buffer_2 = register_log_buffer("log-test-2");
+    let subscriber = tracing_subscriber::registry().with(MockServerLogLayer::with_capacity(2));
+    tracing::subscriber::with_default(subscriber, || {
+      info!("not in a mock server span");
+      info_span!("mock_server", mock_server_id = "log-test-1").in_scope(|| {
+        info!("first");
+        info_span!("request", path = "/").in_scope(|| {
+          warn!(status = 404, "second");
+        });
+        info!("third");
+      });
+      info_span!("mock_server", mock_server_id = %"log-test-2").in_scope(|| {
+        info!("other");
+      });
+    });
+
+    let logs = mock_server_logs("log-test-1");
+    expect!(logs.len()).to(be_equal_to(2));
+    expect!(logs[0].ends_with(" WARN pact_mock_server::logs::tests: second status=404")).to(be_true());
+    expect!(logs[1].ends_with(" INFO pact_mock_server::logs::tests: third")).to(be_true());
+    expect!(mock_server_logs("log-test-2").len()).to(be_equal_to(1));
+
+    clear_mock_server_logs("log-test-1");
+    expect!(mock_server_logs("log-test-1")).to(be_equal_to(Vec::<String>::new()));
+    expect!(mock_server_logs("unknown")).to(be_equal_to(Vec::<String>::new()));
+  }
+
+  #[test]
+  fn discards_the_log_entries_once_the_buffer_is_dropped() {
+    let buffer = register_log_buffer("log-test-3");
+    let subscriber = tracing_subscriber::registry().with(MockServerLogLayer::new());
+    tracing::subscriber::with_default(subscriber, || {
+      info_span!("mock_server", mock_server_id = "log-test-3").in_scope(|| info!("first"));
+      expect!(buffer.lock().unwrap().len()).to(be_equal_to(1));
+
+      drop(buffer);
+      expect!(mock_server_logs("log-test-3")).to(be_equal_to(Vec::<String>::new()));
+      info_span!("mock_server", mock_server_id = "log-test-3").in_scope(|| info!("second"));
+      expect!(LOG_BUFFERS.lock().unwrap().contains_key("log-test-3")).to(be_false());
+    });
+  }
+}
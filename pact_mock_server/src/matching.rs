@@ -10,7 +10,6 @@ use itertools::Itertools;
 use serde_json::json;
 
 use pact_matching::{Mismatch, RequestMatchResult};
-use pact_models::interaction::Interaction;
 use pact_models::PactSpecification;
 use pact_models::prelude::Pact;
 use pact_models::prelude::v4::SynchronousHttp;
@@ -121,36 +120,46 @@ pub async fn match_request(
   req: &HttpRequest,
   pact: &V4Pact,
 ) -> MatchResult {
-  let interactions = pact.filter_interactions(V4InteractionType::Synchronous_HTTP);
+  match_request_to_interaction(req, pact).await.0
+}
+
+/// Matches a request against the interactions of the Pact, returning the match result along with
+/// the index of the interaction in the Pact that the request was matched (or mismatched) against.
+/// The index is `None` if the request did not match any interaction.
+pub(crate) async fn match_request_to_interaction(
+  req: &HttpRequest,
+  pact: &V4Pact,
+) -> (MatchResult, Option<usize>) {
+  let interactions = pact.interactions.iter()
+    .enumerate()
+    .filter(|(_, i)| i.v4_type() == V4InteractionType::Synchronous_HTTP && i.is_request_response());
   let match_results = futures::stream::iter(interactions)
-    .filter(|i| future::ready(i.is_request_response()))
-    .filter_map(|i| async move {
+    .filter_map(|(index, i)| async move {
       let interaction = i.as_v4_http().unwrap();
       let result = pact_matching::match_request(interaction.request.clone(),
-        req.clone(), &pact.boxed(), &i).await;
+        req.clone(), &pact.boxed(), &i.boxed()).await;
       match result {
-        Ok(match_result) => Some((interaction.clone(), match_result)),
+        Ok(match_result) => Some((index, interaction, match_result)),
         Err(err) => {
           error!("Failed to match request for interaction '{}': {}", interaction.description, err);
           None
         }
       }
     })
-    .collect::<Vec<(SynchronousHttp, RequestMatchResult)>>().await;
-  let mut sorted = match_results.iter().sorted_by(|(_, i1), (_, i2)| {
+    .collect::<Vec<(usize, SynchronousHttp, RequestMatchResult)>>().await;
+  let mut sorted = match_results.into_iter().sorted_by(|(_, _, i1), (_, _, i2)| {
     Ord::cmp(&i2.score(), &i1.score())
   });
   match sorted.next() {
-    Some((interaction, result)) => {
-      let request_response_interaction = interaction.as_v4_http().unwrap();
+    Some((index, interaction, result)) => {
       if result.all_matched() {
-        MatchResult::RequestMatch(request_response_interaction.request, request_response_interaction.response, req.clone())
+        (MatchResult::RequestMatch(interaction.request, interaction.response, req.clone()), Some(index))
       } else if result.method_or_path_mismatch() {
-        MatchResult::RequestNotFound(req.clone())
+        (MatchResult::RequestNotFound(req.clone()), None)
       } else {
-        MatchResult::RequestMismatch(request_response_interaction.request, req.clone(), result.mismatches())
+        (MatchResult::RequestMismatch(interaction.request, req.clone(), result.mismatches()), Some(index))
       }
     },
-    None => MatchResult::RequestNotFound(req.clone())
+    None => (MatchResult::RequestNotFound(req.clone()), None)
   }
 }
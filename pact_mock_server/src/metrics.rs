@@ -0,0 +1,263 @@
+//! Metrics collected by a mock server, and support for exposing them in the
+//! [OpenMetrics](https://openmetrics.io) text format (which can be scraped by Prometheus).
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::matching::MatchResult;
+
+/// Content type of the OpenMetrics text format
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Upper bounds (in milliseconds) of the buckets of the response latency histogram
+pub const LATENCY_BUCKETS_MS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Metrics for the mock server
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct MockServerMetrics {
+  /// Total requests
+  pub requests: usize,
+  /// Total requests by path
+  pub requests_by_path: HashMap<String, usize>,
+  /// Number of requests that matched, by interaction description
+  pub matches_by_interaction: HashMap<String, usize>,
+  /// Number of mismatches, by mismatch type (i.e. `BodyMismatch` or `RequestNotFound`)
+  pub mismatches_by_type: HashMap<String, usize>,
+  /// Histogram of the time taken to respond to requests
+  pub response_latency: LatencyHistogram,
+  /// Number of connections that are currently open
+  pub active_connections: usize,
+  /// Total connections that have been accepted
+  pub total_connections: usize,
+  /// Number of connections that failed
+  pub connection_failures: usize,
+  /// Number of connections where the TLS handshake failed
+  pub tls_handshake_failures: usize
+}
+
+impl MockServerMetrics {
+  pub(crate) fn add_path(&mut self, path: String) {
+    self.requests += 1;
+    *self.requests_by_path
+      .entry(path)
+      .or_insert(0)
+      += 1;
+  }
+
+  /// Records the result of matching a request. `interaction` is the description of the
+  /// interaction that matched.
+  pub(crate) fn add_match_result(&mut self, result: &MatchResult, interaction: Option<String>) {
+    match result {
+      MatchResult::RequestMatch(request, _, _) => {
+        let key = interaction.unwrap_or_else(|| format!("{} {}", request.method, request.path));
+        *self.matches_by_interaction.entry(key).or_insert(0) += 1;
+      }
+      MatchResult::RequestMismatch(_, _, mismatches) => for mismatch in mismatches {
+        *self.mismatches_by_type.entry(mismatch.mismatch_type().to_string()).or_insert(0) += 1;
+      },
+      MatchResult::RequestNotFound(_) => {
+        *self.mismatches_by_type.entry("RequestNotFound".to_string()).or_insert(0) += 1;
+      }
+      MatchResult::MissingRequest(_) => {
+        *self.mismatches_by_type.entry("MissingRequest".to_string()).or_insert(0) += 1;
+      }
+    }
+  }
+
+  pub(crate) fn connection_opened(&mut self) {
+    self.active_connections += 1;
+    self.total_connections += 1;
+  }
+
+  pub(crate) fn connection_closed(&mut self) {
+    self.active_connections = self.active_connections.saturating_sub(1);
+  }
+
+  /// Formats these metrics in the OpenMetrics text format, labelled with the mock server ID
+  pub fn to_openmetrics(&self, server_id: &str) -> String {
+    format_openmetrics([(server_id, self)])
+  }
+}
+
+/// Histogram of response latencies. The buckets are defined by `LATENCY_BUCKETS_MS`, with an
+/// additional overflow bucket for anything slower.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct LatencyHistogram {
+  /// Number of responses in each bucket (not cumulative)
+  pub buckets: Vec<usize>,
+  /// Total number of responses
+  pub count: usize,
+  /// Sum of all the latencies in microseconds
+  pub sum_micros: u64
+}
+
+impl LatencyHistogram {
+  /// Records a response latency
+  pub fn observe(&mut self, duration: Duration) {
+    let millis = duration.as_secs_f64() * 1000.0;
+    let index = LATENCY_BUCKETS_MS.iter()
+      .position(|bound| millis <= *bound as f64)
+      .unwrap_or(LATENCY_BUCKETS_MS.len());
+    if self.buckets.len() <= index {
+      self.buckets.resize(LATENCY_BUCKETS_MS.len() + 1, 0);
+    }
+    self.buckets[index] += 1;
+    self.count += 1;
+    self.sum_micros += duration.as_micros() as u64;
+  }
+
+  /// Returns the cumulative counts for each of the bucket upper bounds, ending with `+Inf`
+  pub fn cumulative_buckets(&self) -> Vec<(String, usize)> {
+    let mut total = 0;
+    LATENCY_BUCKETS_MS.iter()
+      .map(|bound| (*bound as f64 / 1000.0).to_string())
+      .chain(std::iter::once("+Inf".to_string()))
+      .enumerate()
+      .map(|(index, bound)| {
+        total += self.buckets.get(index).copied().unwrap_or_default();
+        (bound, total)
+      })
+      .collect()
+  }
+}
+
+impl Default for LatencyHistogram {
+  fn default() -> Self {
+    LatencyHistogram {
+      buckets: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+      count: 0,
+      sum_micros: 0
+    }
+  }
+}
+
+/// Formats the metrics of a number of mock servers in the OpenMetrics text format. Each sample is
+/// labelled with the ID of the mock server it is from.
+pub fn format_openmetrics<'a>(servers: impl IntoIterator<Item = (&'a str, &'a MockServerMetrics)>) -> String {
+  let servers = servers.into_iter().collect_vec();
+  let mut buffer = String::new();
+
+  write_family(&mut buffer, "pact_mock_server_requests", "counter", "Total requests received",
+    servers.iter().map(|(id, m)| (labels(id, &[]), "_total", m.requests.to_string())));
+  write_family(&mut buffer, "pact_mock_server_path_requests", "counter", "Requests received by path",
+    servers.iter().flat_map(|(id, m)| m.requests_by_path.iter()
+      .sorted()
+      .map(|(path, count)| (labels(id, &[("path", path)]), "_total", count.to_string()))));
+  write_family(&mut buffer, "pact_mock_server_interaction_matches", "counter", "Requests that matched, by interaction",
+    servers.iter().flat_map(|(id, m)| m.matches_by_interaction.iter()
+      .sorted()
+      .map(|(interaction, count)| (labels(id, &[("interaction", interaction)]), "_total", count.to_string()))));
+  write_family(&mut buffer, "pact_mock_server_mismatches", "counter", "Mismatches, by mismatch type",
+    servers.iter().flat_map(|(id, m)| m.mismatches_by_type.iter()
+      .sorted()
+      .map(|(mismatch_type, count)| (labels(id, &[("type", mismatch_type)]), "_total", count.to_string()))));
+  write_family(&mut buffer, "pact_mock_server_response_duration_seconds", "histogram", "Time taken to respond to requests",
+    servers.iter().flat_map(|(id, m)| {
+      let histogram = &m.response_latency;
+      histogram.cumulative_buckets().into_iter()
+        .map(|(bound, count)| (labels(id, &[("le", &bound)]), "_bucket", count.to_string()))
+        .chain([
+          (labels(id, &[]), "_count", histogram.count.to_string()),
+          (labels(id, &[]), "_sum", (histogram.sum_micros as f64 / 1_000_000.0).to_string())
+        ])
+        .collect_vec()
+    }));
+  write_family(&mut buffer, "pact_mock_server_active_connections", "gauge", "Connections that are currently open",
+    servers.iter().map(|(id, m)| (labels(id, &[]), "", m.active_connections.to_string())));
+  write_family(&mut buffer, "pact_mock_server_connections", "counter", "Total connections accepted",
+    servers.iter().map(|(id, m)| (labels(id, &[]), "_total", m.total_connections.to_string())));
+  write_family(&mut buffer, "pact_mock_server_connection_failures", "counter", "Connections that failed",
+    servers.iter().map(|(id, m)| (labels(id, &[]), "_total", m.connection_failures.to_string())));
+  write_family(&mut buffer, "pact_mock_server_tls_handshake_failures", "counter", "Connections where the TLS handshake failed",
+    servers.iter().map(|(id, m)| (labels(id, &[]), "_total", m.tls_handshake_failures.to_string())));
+
+  buffer.push_str("# EOF\n");
+  buffer
+}
+
+fn write_family(
+  buffer: &mut String,
+  name: &str,
+  metric_type: &str,
+  help: &str,
+  samples: impl Iterator<Item = (String, &'static str, String)>
+) {
+  let _ = writeln!(buffer, "# TYPE {} {}", name, metric_type);
+  let _ = writeln!(buffer, "# HELP {} {}", name, help);
+  for (labels, suffix, value) in samples {
+    let _ = writeln!(buffer, "{}{}{{{}}} {}", name, suffix, labels, value);
+  }
+}
+
+fn labels(server_id: &str, labels: &[(&str, &str)]) -> String {
+  std::iter::once(("mock_server", server_id))
+    .chain(labels.iter().copied())
+    .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+    .join(",")
+}
+
+fn escape_label_value(value: &str) -> String {
+  value.replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use maplit::hashmap;
+  use pretty_assertions::assert_eq;
+
+  use super::*;
+
+  #[test]
+  fn latency_histogram_test() {
+    let mut histogram = LatencyHistogram::default();
+    histogram.observe(Duration::from_micros(500));
+    histogram.observe(Duration::from_millis(30));
+    histogram.observe(Duration::from_secs(20));
+
+    expect!(histogram.count).to(be_equal_to(3));
+    expect!(histogram.sum_micros).to(be_equal_to(20_030_500));
+    let buckets = histogram.cumulative_buckets();
+    expect!(buckets.len()).to(be_equal_to(LATENCY_BUCKETS_MS.len() + 1));
+    expect!(buckets[0].clone()).to(be_equal_to(("0.001".to_string(), 1)));
+    expect!(buckets[3].clone()).to(be_equal_to(("0.025".to_string(), 1)));
+    expect!(buckets[4].clone()).to(be_equal_to(("0.05".to_string(), 2)));
+    expect!(buckets[12].clone()).to(be_equal_to(("+Inf".to_string(), 3)));
+  }
+
+  #[test]
+  fn format_openmetrics_test() {
+    let metrics = MockServerMetrics {
+      requests: 2,
+      requests_by_path: hashmap!{ "/a\"b".to_string() => 2 },
+      mismatches_by_type: hashmap!{ "BodyMismatch".to_string() => 1 },
+      active_connections: 1,
+      total_connections: 3,
+      .. MockServerMetrics::default()
+    };
+    let text = metrics.to_openmetrics("abc");
+
+    let lines = text.lines().collect_vec();
+    assert_eq!(&lines[0..5], &[
+      "# TYPE pact_mock_server_requests counter",
+      "# HELP pact_mock_server_requests Total requests received",
+      "pact_mock_server_requests_total{mock_server=\"abc\"} 2",
+      "# TYPE pact_mock_server_path_requests counter",
+      "# HELP pact_mock_server_path_requests Requests received by path"
+    ]);
+    expect!(lines.contains(&"pact_mock_server_path_requests_total{mock_server=\"abc\",path=\"/a\\\"b\"} 2")).to(be_true());
+    expect!(lines.contains(&"pact_mock_server_mismatches_total{mock_server=\"abc\",type=\"BodyMismatch\"} 1")).to(be_true());
+    expect!(lines.contains(&"pact_mock_server_response_duration_seconds_bucket{mock_server=\"abc\",le=\"+Inf\"} 0")).to(be_true());
+    expect!(lines.contains(&"pact_mock_server_active_connections{mock_server=\"abc\"} 1")).to(be_true());
+    expect!(lines.contains(&"pact_mock_server_connections_total{mock_server=\"abc\"} 3")).to(be_true());
+    expect!(lines.last()).to(be_some().value(&"# EOF"));
+  }
+}
@@ -6,28 +6,40 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::future::Future;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
 use std::sync::{Arc, mpsc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::anyhow;
+use bytes::Bytes;
+use futures::{Stream, StreamExt, stream};
 use pact_models::generators::generate_hexadecimal;
 use pact_models::json_utils::json_to_string;
-use pact_models::pact::{Pact, ReadWritePact, write_pact};
+use pact_models::pact::{Pact, ReadWritePact};
 use pact_models::PactSpecification;
 use pact_models::v4::http_parts::HttpRequest;
 use pact_models::v4::pact::V4Pact;
 #[cfg(feature = "plugins")] use pact_plugin_driver::catalogue_manager::CatalogueEntry;
 #[cfg(feature = "tls")] use rustls::ServerConfig;
-use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tokio::sync::mpsc::Receiver;
-use tracing::{debug, info, trace, warn};
+use tokio::sync::{broadcast, watch};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, Instrument, trace, warn};
 
+use crate::cors::CorsPolicy;
+use crate::downgrade::{DowngradeIssue, validate_downgrade};
 use crate::hyper_server::create_and_bind;
+use crate::logs::{LogBuffer, mock_server_span, register_log_buffer};
 #[cfg(feature = "tls")] use crate::hyper_server::create_and_bind_https;
 use crate::matching::MatchResult;
+use crate::pact_writer;
+use crate::pact_writer::{MergePolicy, MergeReport};
+pub use crate::metrics::MockServerMetrics;
 use crate::utils::json_to_bool;
 
 /// Mock server configuration
@@ -35,12 +47,17 @@ use crate::utils::json_to_bool;
 pub struct MockServerConfig {
   /// If CORS Pre-Flight requests should be responded to
   pub cors_preflight: bool,
+  /// CORS policy that controls the `Access-Control-*` headers added to responses
+  pub cors_policy: CorsPolicy,
   /// Pact specification to use
   pub pact_specification: PactSpecification,
   /// Configuration required for the transport used
   pub transport_config: HashMap<String, Value>,
   /// Address to bind to
   pub address: String,
+  /// Any additional addresses to bind to (i.e. to listen on both the IP4 and IP6 loopback
+  /// adapters). Addresses with a port of zero will use the same port as the main address.
+  pub additional_addresses: Vec<String>,
   /// Unique mock server ID to assign
   pub mockserver_id: Option<String>,
   /// TLS configuration
@@ -50,7 +67,41 @@ pub struct MockServerConfig {
   #[cfg(feature = "plugins")]
   pub transport_entry: Option<CatalogueEntry>,
   /// If connection keep alive should be enabled
-  pub keep_alive: bool
+  pub keep_alive: bool,
+  /// If generated responses should be compressed when the request `Accept-Encoding` header allows
+  /// it. Responses from interactions that have a `Content-Encoding` header are always compressed.
+  pub compress_responses: bool,
+  /// If the original case of header names should be preserved for HTTP/1 connections.
+  /// Request headers will be captured with the case they were received with (and recorded in the
+  /// order they were received), and response headers will be written with the case from the Pact
+  /// file (otherwise in Title-Case).
+  pub preserve_header_case: bool,
+  /// Reserved path that the metrics of the mock server will be served from in the OpenMetrics
+  /// text format (i.e. `/__pact/metrics`). Requests to this path are not matched against the
+  /// Pact interactions. Disabled if not set.
+  pub metrics_path: Option<String>,
+  /// Reserved path prefix for the admin API of the mock server (i.e. `/__pact`), which allows the
+  /// mock server to be inspected and controlled over HTTP. Requests under this prefix are not
+  /// matched against the Pact interactions. Disabled if not set.
+  pub admin_path: Option<String>,
+  /// Directory that the admin API writes Pact files to. The admin request can only give a
+  /// relative path under this directory. Defaults to the current working directory if not set.
+  pub admin_output_dir: Option<String>,
+  /// Delay to wait before sending each response, to simulate a slow provider
+  pub response_delay: Option<Duration>,
+  /// If writing the Pact file should fail when the Pact has features that can not be represented
+  /// in the specification version it is written as. Otherwise, they are logged as warnings.
+  pub strict_spec_validation: bool,
+  /// If only the interactions that received a matching request should be written to the Pact file.
+  /// Interactions that are not HTTP interactions are always written.
+  pub write_exercised_only: bool,
+  /// If the number of requests that matched each interaction should be added to the comments of
+  /// the interactions (as `matchCount`) when the Pact file is written. Only V4 Pact files support
+  /// comments.
+  pub annotate_match_counts: bool,
+  /// How conflicts with the interactions in an existing Pact file are resolved when the Pact is
+  /// merged with it
+  pub merge_policy: MergePolicy
 }
 
 impl MockServerConfig {
@@ -62,10 +113,45 @@ impl MockServerConfig {
       for (k, v) in map {
         if k == "corsPreflight" {
           config.cors_preflight = json_to_bool(v).unwrap_or_default();
+        } else if k == "additionalAddresses" {
+          config.additional_addresses = match v {
+            Value::Array(values) => values.iter().map(json_to_string).collect(),
+            _ => json_to_string(v).split(',')
+              .map(|address| address.trim().to_string())
+              .filter(|address| !address.is_empty())
+              .collect()
+          };
+        } else if k == "cors" {
+          config.cors_policy = CorsPolicy::from_json(v);
         } else if k == "pactSpecification" {
           config.pact_specification = PactSpecification::from(json_to_string(v));
         } else if k == "keepAlive" {
           config.keep_alive = json_to_bool(v).unwrap_or_default();
+        } else if k == "compressResponses" {
+          config.compress_responses = json_to_bool(v).unwrap_or_default();
+        } else if k == "preserveHeaderCase" {
+          config.preserve_header_case = json_to_bool(v).unwrap_or_default();
+        } else if k == "metricsPath" {
+          config.metrics_path = Some(json_to_string(v)).filter(|path| !path.is_empty());
+        } else if k == "adminPath" {
+          config.admin_path = Some(json_to_string(v)).filter(|path| !path.is_empty());
+        } else if k == "adminOutputDir" {
+          config.admin_output_dir = Some(json_to_string(v)).filter(|dir| !dir.is_empty());
+        } else if k == "responseDelay" {
+          config.response_delay = v.as_u64()
+            .or_else(|| json_to_string(v).parse().ok())
+            .map(Duration::from_millis);
+        } else if k == "strictSpecValidation" {
+          config.strict_spec_validation = json_to_bool(v).unwrap_or_default();
+        } else if k == "writeExercisedOnly" {
+          config.write_exercised_only = json_to_bool(v).unwrap_or_default();
+        } else if k == "annotateMatchCounts" {
+          config.annotate_match_counts = json_to_bool(v).unwrap_or_default();
+        } else if k == "mergePolicy" {
+          match json_to_string(v).parse() {
+            Ok(policy) => config.merge_policy = policy,
+            Err(err) => warn!("Ignoring the merge policy - {}", err)
+          }
         } else {
           config.transport_config.insert(k.clone(), v.clone());
         }
@@ -75,6 +161,24 @@ impl MockServerConfig {
     config
   }
 
+  /// Parses the addresses that the mock server should bind to. The main address is always first,
+  /// and defaults to the IP6 loopback adapter with a random port.
+  pub fn bind_addresses(&self) -> anyhow::Result<Vec<SocketAddr>> {
+    let address = if self.address.is_empty() {
+      SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)
+    } else {
+      self.address.parse()
+        .map_err(|err| anyhow!("'{}' is not a valid address to bind to - {}", self.address, err))?
+    };
+
+    let mut addresses = vec![ address ];
+    for additional in &self.additional_addresses {
+      addresses.push(additional.parse()
+        .map_err(|err| anyhow!("'{}' is not a valid address to bind to - {}", additional, err))?);
+    }
+    Ok(addresses)
+  }
+
   /// Return default config with keep alive enabled
   pub fn with_keep_alive(keep_alive: bool) -> Self {
     MockServerConfig {
@@ -89,13 +193,25 @@ impl Default for MockServerConfig {
   fn default() -> Self {
     MockServerConfig {
       cors_preflight: false,
+      cors_policy: Default::default(),
       pact_specification: Default::default(),
       transport_config: Default::default(),
       address: "".to_string(),
+      additional_addresses: vec![],
       mockserver_id: None,
       tls_config: None,
       transport_entry: None,
-      keep_alive: true
+      keep_alive: true,
+      compress_responses: false,
+      preserve_header_case: false,
+      metrics_path: None,
+      admin_path: None,
+      admin_output_dir: None,
+      response_delay: None,
+      strict_spec_validation: false,
+      write_exercised_only: false,
+      annotate_match_counts: false,
+      merge_policy: MergePolicy::default()
     }
   }
 
@@ -103,12 +219,24 @@ impl Default for MockServerConfig {
   fn default() -> Self {
     MockServerConfig {
       cors_preflight: false,
+      cors_policy: Default::default(),
       pact_specification: Default::default(),
       transport_config: Default::default(),
       address: "".to_string(),
+      additional_addresses: vec![],
       mockserver_id: None,
       tls_config: None,
-      keep_alive: true
+      keep_alive: true,
+      compress_responses: false,
+      preserve_header_case: false,
+      metrics_path: None,
+      admin_path: None,
+      admin_output_dir: None,
+      response_delay: None,
+      strict_spec_validation: false,
+      write_exercised_only: false,
+      annotate_match_counts: false,
+      merge_policy: MergePolicy::default()
     }
   }
 
@@ -116,12 +244,24 @@ impl Default for MockServerConfig {
   fn default() -> Self {
     MockServerConfig {
       cors_preflight: false,
+      cors_policy: Default::default(),
       pact_specification: Default::default(),
       transport_config: Default::default(),
       address: "".to_string(),
+      additional_addresses: vec![],
       mockserver_id: None,
       transport_entry: None,
-      keep_alive: true
+      keep_alive: true,
+      compress_responses: false,
+      preserve_header_case: false,
+      metrics_path: None,
+      admin_path: None,
+      admin_output_dir: None,
+      response_delay: None,
+      strict_spec_validation: false,
+      write_exercised_only: false,
+      annotate_match_counts: false,
+      merge_policy: MergePolicy::default()
     }
   }
 
@@ -129,11 +269,23 @@ impl Default for MockServerConfig {
   fn default() -> Self {
     MockServerConfig {
       cors_preflight: false,
+      cors_policy: Default::default(),
       pact_specification: Default::default(),
       transport_config: Default::default(),
       address: "".to_string(),
+      additional_addresses: vec![],
       mockserver_id: None,
-      keep_alive: true
+      keep_alive: true,
+      compress_responses: false,
+      preserve_header_case: false,
+      metrics_path: None,
+      admin_path: None,
+      admin_output_dir: None,
+      response_delay: None,
+      strict_spec_validation: false,
+      write_exercised_only: false,
+      annotate_match_counts: false,
+      merge_policy: MergePolicy::default()
     }
   }
 }
@@ -142,11 +294,23 @@ impl Default for MockServerConfig {
 impl PartialEq for MockServerConfig {
   fn eq(&self, other: &Self) -> bool {
     let ok = self.cors_preflight == other.cors_preflight
+      && self.cors_policy == other.cors_policy
       && self.pact_specification == other.pact_specification
       && self.transport_config == other.transport_config
       && self.address == other.address
+      && self.additional_addresses == other.additional_addresses
       && self.mockserver_id == other.mockserver_id
-      && self.keep_alive == other.keep_alive;
+      && self.keep_alive == other.keep_alive
+      && self.compress_responses == other.compress_responses
+      && self.preserve_header_case == other.preserve_header_case
+      && self.metrics_path == other.metrics_path
+      && self.admin_path == other.admin_path
+      && self.admin_output_dir == other.admin_output_dir
+      && self.response_delay == other.response_delay
+      && self.strict_spec_validation == other.strict_spec_validation
+      && self.write_exercised_only == other.write_exercised_only
+      && self.annotate_match_counts == other.annotate_match_counts
+      && self.merge_policy == other.merge_policy;
 
     #[cfg(feature = "plugins")]
     {
@@ -184,38 +348,108 @@ impl Display for MockServerScheme {
   }
 }
 
-/// Metrics for the mock server
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct MockServerMetrics {
-  /// Total requests
-  pub requests: usize,
-  /// Total requests by path
-  pub requests_by_path: HashMap<String, usize>
+/// Number of events that are buffered for each subscriber
+const EVENT_BUFFER_SIZE: usize = 256;
+
+/// Details of a request received by the mock server. The request ID can be used to correlate the
+/// recorded request and response prepared events for the same request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestDetails {
+  /// ID of the request, unique for the mock server
+  pub request_id: u64,
+  /// Address of the client that sent the request
+  pub remote_address: SocketAddr,
+  /// Address the request was received on
+  pub local_address: SocketAddr,
+  /// HTTP version of the request (i.e. `HTTP/1.1`)
+  pub http_version: String,
+  /// When the request was received
+  pub received_at: SystemTime
 }
 
-impl MockServerMetrics {
-  pub(crate) fn add_path(&mut self, path: String) {
-    self.requests += 1;
-    *self.requests_by_path
-      .entry(path)
-      .or_insert(0)
-      += 1;
-  }
+/// Details of the response sent for a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseDetails {
+  /// ID of the request this is the response for
+  pub request_id: u64,
+  /// HTTP status code of the response
+  pub status: u16,
+  /// Size of the response body in bytes
+  pub body_size: usize,
+  /// When the response was ready to be written
+  pub prepared_at: SystemTime,
+  /// Time taken from receiving the request to the response being ready to be written
+  pub duration: Duration
+}
+
+/// Request received by the mock server, with the result of matching it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+  /// Result of matching the request
+  pub result: MatchResult,
+  /// Index of the interaction in the Pact that the request was matched (or mismatched) against.
+  /// This is `None` if the request was not expected.
+  pub interaction: Option<usize>,
+  /// Details of the request
+  pub details: RequestDetails,
+  /// Body of the request as it was received. This is only set if the body was decoded using the
+  /// `Content-Encoding` header of the request, as the request in the match result will have the
+  /// decoded body.
+  pub raw_body: Option<Bytes>,
+  /// Headers of the request in the order and with the case they were received. This is only set
+  /// if preserving the case of header names is enabled, as the request in the match result has
+  /// the headers in a map.
+  pub raw_headers: Option<Vec<(String, String)>>
+}
+
+/// Number of requests received for an interaction of the Pact
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InteractionRequests {
+  /// Requests that matched the interaction
+  pub matched: usize,
+  /// Requests that were matched against the interaction, but had mismatches
+  pub mismatched: usize
 }
 
 /// Events sent from the mock server task to be consumed by the mock server event loop.
 #[derive(Debug, Clone, PartialEq)]
 pub enum MockServerEvent {
+  /// Connection accepted from the remote address
+  ConnectionOpened(SocketAddr),
+  /// Connection from the remote address has been closed
+  ConnectionClosed(SocketAddr),
   /// Connection failed with error
   ConnectionFailed(String),
+  /// TLS handshake failed with error
+  TlsHandshakeFailed(String),
   /// Request received with path
   RequestReceived(String),
+  /// Request that has been matched, with the details of the request. This is sent before the
+  /// `RequestMatch` event for the same request.
+  RequestRecorded(RecordedRequest),
   /// Result of matching a request
   RequestMatch(MatchResult),
+  /// Response for a request is ready to be written to the connection
+  ResponsePrepared(ResponseDetails),
+  /// The drain timeout expired while shutting down. Contains the number of connections that were
+  /// closed, and the requests (method and path) that were cut off.
+  DrainTimeout(usize, Vec<String>),
   /// Server is shutting down
   ServerShutdown
 }
 
+/// Report of what happened when a mock server was shut down
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ShutdownReport {
+  /// If all the in-flight requests completed before the drain timeout expired
+  pub drained: bool,
+  /// Number of connections that were still open when the drain timeout expired, and were closed
+  pub connections_closed: usize,
+  /// Requests (method and path) that were still being processed when the connections were closed.
+  /// These requests will not have been recorded as matches or mismatches.
+  pub requests_cut_off: Vec<String>
+}
+
 /// Struct to represent the "foreground" part of mock server. Note that while Clone has been
 /// implemented, clones of the mock server are detached from the background tasks, and so
 /// should only be used to extract data at a point in time and then discarded.
@@ -227,12 +461,14 @@ pub struct MockServer {
   pub scheme: MockServerScheme,
   /// Address the mock server is bound to
   pub address: SocketAddr,
+  /// Any additional addresses the mock server is bound to
+  pub additional_addresses: Vec<SocketAddr>,
   /// Pact that this mock server is based on
   pub pact: V4Pact,
-  /// Receiver of match results
-  matches: Arc<Mutex<Vec<MatchResult>>>,
+  /// Requests received by the mock server, with their match results
+  requests: Arc<Mutex<Vec<RecordedRequest>>>,
   /// Sender to signal main server to shutdown
-  shutdown_tx: RefCell<Option<tokio::sync::oneshot::Sender<()>>>,
+  shutdown_tx: RefCell<Option<tokio::sync::oneshot::Sender<Option<Duration>>>>,
   /// Mock server config
   pub config: MockServerConfig,
   /// Metrics collected by the mock server
@@ -240,7 +476,13 @@ pub struct MockServer {
   /// Pact spec version to use
   pub spec_version: PactSpecification,
   /// Event loop shutdown signal receiver. Message will be sent when the event loop has terminated.
-  pub event_loop_rx: Option<mpsc::Receiver<()>>
+  pub event_loop_rx: Option<mpsc::Receiver<()>>,
+  /// Receives the shutdown report when the event loop has terminated
+  shutdown_report_rx: Option<watch::Receiver<Option<ShutdownReport>>>,
+  /// Receiver for events published by the event loop, used to create new subscriptions
+  event_rx: broadcast::Receiver<MockServerEvent>,
+  /// Log entries captured for the mock server
+  logs: LogBuffer
 }
 
 impl Clone for MockServer {
@@ -249,13 +491,17 @@ impl Clone for MockServer {
       id: self.id.clone(),
       scheme: self.scheme.clone(),
       address: self.address.clone(),
+      additional_addresses: self.additional_addresses.clone(),
       pact: self.pact.clone(),
-      matches: self.matches.clone(),
+      requests: self.requests.clone(),
       shutdown_tx: RefCell::new(None),
       config: self.config.clone(),
       metrics: self.metrics.clone(),
       spec_version: self.spec_version.clone(),
-      event_loop_rx: None
+      event_loop_rx: None,
+      shutdown_report_rx: None,
+      event_rx: self.event_rx.resubscribe(),
+      logs: self.logs.clone()
     }
   }
 }
@@ -266,18 +512,46 @@ impl Default for MockServer {
       id: generate_hexadecimal(8),
       scheme: MockServerScheme::HTTP,
       address: SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0),
+      additional_addresses: vec![],
       pact: Default::default(),
-      matches: Arc::new(Mutex::new(vec![])),
+      requests: Arc::new(Mutex::new(vec![])),
       shutdown_tx: RefCell::new(None),
       config: Default::default(),
       metrics: Arc::new(Mutex::new(Default::default())),
       spec_version: Default::default(),
-      event_loop_rx: None
+      event_loop_rx: None,
+      shutdown_report_rx: None,
+      event_rx: broadcast::channel(1).1,
+      logs: Default::default()
     }
   }
 }
 
 impl MockServer {
+  /// Creates a detached mock server that shares the requests and metrics of a running one, so
+  /// that the running mock server can be inspected from the server tasks.
+  pub(crate) fn detached(
+    id: String,
+    scheme: MockServerScheme,
+    address: SocketAddr,
+    pact: V4Pact,
+    config: MockServerConfig,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    metrics: Arc<Mutex<MockServerMetrics>>
+  ) -> MockServer {
+    MockServer {
+      id,
+      scheme,
+      address,
+      pact,
+      requests,
+      spec_version: config.pact_specification,
+      config,
+      metrics,
+      .. MockServer::default()
+    }
+  }
+
   /// Create a new mock server, spawn its execution loop onto the tokio runtime and return the
   /// mock server instance.
   pub async fn create(
@@ -288,27 +562,30 @@ impl MockServer {
       .clone()
       .unwrap_or_else(|| generate_hexadecimal(8));
 
-    let address = if config.address.is_empty() {
-      SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)
-    } else {
-      config.address.parse()?
-    };
+    let addresses = config.bind_addresses()?;
 
-    trace!(%server_id, %address, "Starting mock server");
-    let (address, shutdown_send, event_recv, _task_handle) = create_and_bind(server_id.clone(), pact.clone(), address, config.clone()).await?;
-    trace!(%server_id, %address, "Mock server started");
+    trace!(%server_id, ?addresses, "Starting mock server");
+    let logs = register_log_buffer(&server_id);
+    let metrics = Arc::new(Mutex::new(MockServerMetrics::default()));
+    let requests = Arc::new(Mutex::new(vec![]));
+    let (addresses, shutdown_send, event_recv, _task_handle) = create_and_bind(server_id.clone(), pact.clone(), &addresses, config.clone(), metrics.clone(), requests.clone()).await?;
+    trace!(%server_id, ?addresses, "Mock server started");
 
     let mut mock_server = MockServer {
       id: server_id,
       scheme: Default::default(),
-      address,
+      address: addresses[0],
+      additional_addresses: addresses[1..].to_vec(),
       pact,
-      matches: Default::default(),
+      requests,
       shutdown_tx: RefCell::new(Some(shutdown_send)),
       config: config.clone(),
-      metrics: Default::default(),
+      metrics,
       spec_version: config.pact_specification,
-      event_loop_rx: None
+      event_loop_rx: None,
+      shutdown_report_rx: None,
+      event_rx: broadcast::channel(1).1,
+      logs
     };
 
     mock_server.start_event_loop(event_recv);
@@ -326,27 +603,30 @@ impl MockServer {
   ) -> anyhow::Result<MockServer> {
     let server_id = generate_hexadecimal(8);
 
-    let address = if config.address.is_empty() {
-      SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)
-    } else {
-      config.address.parse()?
-    };
+    let addresses = config.bind_addresses()?;
 
-    trace!(%server_id, %address, "Starting TLS mock server");
-    let (address, shutdown_send, event_recv, _task_handle) = create_and_bind_https(server_id.clone(), pact.clone(), address, config.clone()).await?;
-    trace!(%server_id, %address, "TLS mock server started");
+    trace!(%server_id, ?addresses, "Starting TLS mock server");
+    let logs = register_log_buffer(&server_id);
+    let metrics = Arc::new(Mutex::new(MockServerMetrics::default()));
+    let requests = Arc::new(Mutex::new(vec![]));
+    let (addresses, shutdown_send, event_recv, _task_handle) = create_and_bind_https(server_id.clone(), pact.clone(), &addresses, config.clone(), metrics.clone(), requests.clone()).await?;
+    trace!(%server_id, ?addresses, "TLS mock server started");
 
     let mut mock_server = MockServer {
       id: server_id,
       scheme: MockServerScheme::HTTPS,
-      address,
+      address: addresses[0],
+      additional_addresses: addresses[1..].to_vec(),
       pact,
-      matches: Default::default(),
+      requests,
       shutdown_tx: RefCell::new(Some(shutdown_send)),
       config: config.clone(),
-      metrics: Default::default(),
+      metrics,
       spec_version: Default::default(),
-      event_loop_rx: None
+      event_loop_rx: None,
+      shutdown_report_rx: None,
+      event_rx: broadcast::channel(1).1,
+      logs
     };
 
     mock_server.start_event_loop(event_recv);
@@ -359,7 +639,7 @@ impl MockServer {
     trace!(server_id = %self.id, address = %self.address, "Shutting mock server down");
     match self.shutdown_tx.take() {
       Some(sender) => {
-        match sender.send(()) {
+        match sender.send(None) {
           Ok(()) => {
             trace!(server_id = %self.id, address = %self.address, "Shutdown event sent, waiting for tasks to complete");
             if let Some(recv) = self.event_loop_rx.take() {
@@ -379,44 +659,186 @@ impl MockServer {
     }
   }
 
+  /// Send the shutdown signal to the server, and wait for any in-flight requests to complete.
+  /// Connections that are still open after the timeout will be closed. This will only return
+  /// once all the requests that completed have been recorded, and the returned report will
+  /// contain any requests that were cut off.
+  pub async fn shutdown_async(&mut self, timeout: Duration) -> anyhow::Result<ShutdownReport> {
+    trace!(server_id = %self.id, address = %self.address, ?timeout, "Shutting mock server down");
+    let sender = self.shutdown_tx.take()
+      .ok_or_else(|| anyhow!("Mock server already shut down"))?;
+    sender.send(Some(timeout))
+      .map_err(|_| anyhow!("Problem sending shutdown signal to mock server"))?;
+
+    trace!(server_id = %self.id, address = %self.address, "Shutdown event sent, waiting for tasks to complete");
+    self.event_loop_rx.take();
+    let mut report_rx = self.shutdown_report_rx.take()
+      .ok_or_else(|| anyhow!("Mock server event loop is not running"))?;
+    let report = report_rx.wait_for(|report| report.is_some()).await
+      .map_err(|_| anyhow!("Mock server event loop terminated without a shutdown report"))?
+      .clone()
+      .unwrap_or_default();
+
+    let metrics = {
+      let guard = self.metrics.lock().unwrap();
+      guard.clone()
+    };
+    debug!("Mock server {} shutdown - {:?}, {:?}", self.id, metrics, report);
+    Ok(report)
+  }
+
   /// Start the event loop for the mock server
   fn start_event_loop(&mut self, mut event_recv: Receiver<MockServerEvent>) {
     let server_id = self.id.clone();
     let metrics = self.metrics.clone();
-    let matches = self.matches.clone();
     let (sender, receiver) = mpsc::channel();
     self.event_loop_rx = Some(receiver);
+    let (report_tx, report_rx) = watch::channel(None);
+    self.shutdown_report_rx = Some(report_rx);
+    let (event_tx, event_rx) = broadcast::channel(EVENT_BUFFER_SIZE);
+    self.event_rx = event_rx;
 
     tokio::spawn(async move {
       trace!(%server_id, "Starting mock server event loop");
 
       let mut total_events = 0;
+      let mut report = ShutdownReport { drained: true, .. ShutdownReport::default() };
       let metrics = metrics.clone();
-      let matches = matches.clone();
       while let Some(event) = event_recv.recv().await {
         trace!(%server_id, ?event, "Received event");
         total_events += 1;
 
-        match event {
-          MockServerEvent::ConnectionFailed(_err) => {}
-          MockServerEvent::RequestReceived(path) => {
-            let mut guard = metrics.lock().unwrap();
-            guard.add_path(path);
+        let shutdown = event == MockServerEvent::ServerShutdown;
+        match &event {
+          MockServerEvent::ConnectionOpened(_) => {
+            metrics.lock().unwrap().connection_opened();
           }
-          MockServerEvent::RequestMatch(result) => {
-            let mut guard = matches.lock().unwrap();
-            guard.push(result.clone());
+          MockServerEvent::ConnectionClosed(_) => {
+            metrics.lock().unwrap().connection_closed();
+          }
+          MockServerEvent::ConnectionFailed(_err) => {
+            metrics.lock().unwrap().connection_failures += 1;
+          }
+          MockServerEvent::TlsHandshakeFailed(_err) => {
+            metrics.lock().unwrap().tls_handshake_failures += 1;
+          }
+          MockServerEvent::RequestReceived(_) | MockServerEvent::RequestRecorded(_) |
+            MockServerEvent::RequestMatch(_) => {}
+          MockServerEvent::ResponsePrepared(details) => {
+            metrics.lock().unwrap().response_latency.observe(details.duration);
+          }
+          MockServerEvent::DrainTimeout(connections, requests) => {
+            report = ShutdownReport {
+              drained: false,
+              connections_closed: *connections,
+              requests_cut_off: requests.clone()
+            };
           }
           MockServerEvent::ServerShutdown => {
             trace!(%server_id, total_events, "Exiting mock server event loop");
-            break;
           }
         }
+
+        // Events are only published once they have been recorded, so subscribers will always
+        // see them reflected in the matches and metrics. An error just means no subscribers.
+        let _ = event_tx.send(event);
+        if shutdown {
+          break;
+        }
       }
 
       trace!(%server_id, total_events, "Mock server event loop done");
+      let _ = report_tx.send(Some(report));
       let _ = sender.send(());
-    });
+    }.instrument(mock_server_span(&self.id)));
+  }
+
+  /// Subscribe to the events from the mock server. The returned stream will receive all the events
+  /// that occur after this call, and ends when the mock server has shut down. Events are only
+  /// published after they have been recorded, so any match results will already be included
+  /// in `matches()`. If the subscriber falls too far behind, the oldest events will be skipped.
+  pub fn subscribe(&self) -> impl Stream<Item = MockServerEvent> + Send + use<> {
+    let server_id = self.id.clone();
+    stream::unfold(self.event_rx.resubscribe(), move |mut receiver| {
+      let server_id = server_id.clone();
+      async move {
+        loop {
+          match receiver.recv().await {
+            Ok(event) => return Some((event, receiver)),
+            Err(RecvError::Lagged(skipped)) => {
+              warn!(%server_id, skipped, "Mock server event subscriber has fallen behind, skipping events");
+            }
+            Err(RecvError::Closed) => return None
+          }
+        }
+      }
+    })
+  }
+
+  /// Waits for a request that matches the predicate to be received by the mock server, returning
+  /// its match result. Requests that have already been received are also checked. Returns an error
+  /// if no such request is received before the timeout expires, or the mock server shuts down.
+  pub fn wait_for_request<F>(
+    &self,
+    predicate: F,
+    timeout: Duration
+  ) -> impl Future<Output = anyhow::Result<MatchResult>> + Send + use<F>
+    where F: Fn(&MatchResult) -> bool + Send + 'static {
+    // Subscribe before checking the existing matches, so no request can be missed
+    let events = self.subscribe();
+    let existing = self.matches().into_iter().find(|result| predicate(result));
+
+    async move {
+      if let Some(result) = existing {
+        return Ok(result);
+      }
+
+      let mut events = pin!(events);
+      let wait = async move {
+        while let Some(event) = events.next().await {
+          match event {
+            MockServerEvent::RequestMatch(result) if predicate(&result) => return Some(result),
+            _ => {}
+          }
+        }
+        None
+      };
+      tokio::time::timeout(timeout, wait).await
+        .map_err(|_| anyhow!("Timed out after {:?} waiting for a matching request", timeout))?
+        .ok_or_else(|| anyhow!("Mock server shut down before a matching request was received"))
+    }
+  }
+
+  /// Waits for the mock server to have received at least `count` requests, returning all the
+  /// match results. Returns an error if the requests are not received before the timeout expires,
+  /// or the mock server shuts down.
+  pub fn wait_for_requests(
+    &self,
+    count: usize,
+    timeout: Duration
+  ) -> impl Future<Output = anyhow::Result<Vec<MatchResult>>> + Send + use<> {
+    let events = self.subscribe();
+    let requests = self.requests.clone();
+
+    async move {
+      let current = || requests.lock().unwrap().iter()
+        .map(|request| request.result.clone())
+        .collect::<Vec<_>>();
+      let mut events = pin!(events);
+      let wait = async {
+        loop {
+          let results = current();
+          if results.len() >= count {
+            return Some(results);
+          }
+          events.next().await?;
+        }
+      };
+      tokio::time::timeout(timeout, wait).await
+        .map_err(|_| anyhow!("Timed out after {:?} waiting for {} requests, only received {}",
+          timeout, count, current().len()))?
+        .ok_or_else(|| anyhow!("Mock server shut down before {} requests were received", count))
+    }
   }
 
   /// Converts this mock server to a `Value` struct
@@ -429,6 +851,7 @@ impl MockServer {
       "id" : self.id.clone(),
       "port" : self.address.port(),
       "address" : self.address.to_string(),
+      "addresses" : self.addresses().iter().map(|address| address.to_string()).collect::<Vec<_>>(),
       "scheme" : self.scheme.to_string(),
       "provider" : self.pact.provider().name.clone(),
       "status" : if self.mismatches().is_empty() { "ok" } else { "error" },
@@ -438,16 +861,38 @@ impl MockServer {
 
   /// Returns all collected matches
   pub fn matches(&self) -> Vec<MatchResult> {
-    let guard = self.matches.lock().unwrap();
+    let guard = self.requests.lock().unwrap();
+    guard.iter().map(|request| request.result.clone()).collect()
+  }
+
+  /// Returns all the requests received by the mock server, with their match results and the
+  /// details of each request
+  pub fn requests(&self) -> Vec<RecordedRequest> {
+    let guard = self.requests.lock().unwrap();
     guard.clone()
   }
 
+  /// Clears all the collected matches, so that the mock server can be reused for another test.
+  /// The metrics of the mock server are not affected.
+  pub fn reset(&self) {
+    self.requests.lock().unwrap().clear();
+  }
+
+  /// Returns the log entries that have been captured for this mock server, oldest first. These
+  /// are only captured if a `logs::MockServerLogLayer` has been added to the tracing subscriber.
+  pub fn logs(&self) -> Vec<String> {
+    self.logs.lock().unwrap().iter().cloned().collect()
+  }
+
   /// If all requests to the mock server matched correctly
   pub fn all_matched(&self) -> bool {
     self.mismatches().is_empty()
   }
 
-    /// Returns all the mismatches that have occurred with this mock server
+    /// Returns all the mismatches that have occurred with this mock server, including any
+    /// interactions that did not receive a request. Interactions that are not HTTP interactions
+    /// (i.e. messages) are not handled by the mock server, so are skipped (see
+    /// `skipped_interactions`).
     pub fn mismatches(&self) -> Vec<MatchResult> {
       let matches = self.matches();
       let mismatches = matches.iter()
@@ -466,22 +911,24 @@ impl MockServer {
 
       let interactions = self.pact.interactions();
       let missing = interactions.iter()
-        .map(|i| i.as_v4_http().unwrap().request)
+        .filter_map(|i| i.as_v4_http())
+        .map(|i| i.request)
         .filter(|req| !requests.contains(req))
         .map(|req| MatchResult::MissingRequest(req.clone()));
       mismatches.chain(missing).collect()
     }
 
-  /// Mock server writes its pact out to the provided directory
+  /// Mock server writes its pact out to the provided directory. The file is locked while it is
+  /// being merged and written, so multiple processes can safely write to the same pact file.
   pub fn write_pact(&self, output_path: &Option<String>, overwrite: bool) -> anyhow::Result<()> {
-    trace!("write_pact: output_path = {:?}, overwrite = {}", output_path, overwrite);
-    let mut v4_pact = self.pact.clone();
-    v4_pact.add_md_version("mockserver", option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"));
-    for interaction in &mut v4_pact.interactions {
-      interaction.set_transport(Some("http".to_string()));
-    }
+    self.write_pact_with_report(output_path, overwrite).map(|_| ())
+  }
 
-    let pact_file_name = v4_pact.default_file_name();
+  /// Mock server writes its pact out to the provided directory, the same as `write_pact`, and
+  /// returns the report of merging the pact with any existing file.
+  pub fn write_pact_with_report(&self, output_path: &Option<String>, overwrite: bool) -> anyhow::Result<MergeReport> {
+    trace!("write_pact: output_path = {:?}, overwrite = {}", output_path, overwrite);
+    let pact_file_name = self.pact.default_file_name();
     let filename = match *output_path {
       Some(ref path) => {
         let mut path = PathBuf::from(path);
@@ -490,14 +937,16 @@ impl MockServer {
       },
       None => PathBuf::from(pact_file_name)
     };
+    self.write_pact_to_path(&filename, overwrite)
+  }
 
-    info!("Writing pact out to '{}'", filename.display());
-    let specification = match self.spec_version {
-      PactSpecification::Unknown => self.pact.specification_version(),
-      _ => self.spec_version
-    };
-    match write_pact(v4_pact.boxed(), filename.as_path(), specification, overwrite) {
-      Ok(_) => Ok(()),
+  /// Mock server writes its pact out to the given file path. If overwrite is false, the pact will
+  /// be merged with any existing file, and the report of the merge is returned.
+  pub fn write_pact_to_path(&self, path: &Path, overwrite: bool) -> anyhow::Result<MergeReport> {
+    let (pact, specification) = self.pact_for_output()?;
+    info!("Writing pact out to '{}'", path.display());
+    match pact_writer::write_pact(&pact, path, specification, overwrite, self.config.merge_policy) {
+      Ok(report) => Ok(report),
       Err(err) => {
         warn!("Failed to write pact to file - {}", err);
         Err(err)
@@ -505,16 +954,166 @@ impl MockServer {
     }
   }
 
+  /// Mock server writes its pact as JSON to the writer (i.e. stdout). The pact is not merged with
+  /// any existing pact file.
+  pub fn write_pact_to<W: Write>(&self, writer: &mut W) -> anyhow::Result<()> {
+    let json = self.pact_json()?;
+    serde_json::to_writer_pretty(&mut *writer, &json)?;
+    writer.flush()?;
+    Ok(())
+  }
+
+  /// Returns the pact of the mock server as JSON, in the form it would be written to a pact file
+  pub fn pact_json(&self) -> anyhow::Result<Value> {
+    let (pact, specification) = self.pact_for_output()?;
+    pact.to_json(specification)
+  }
+
+  /// Publishes the pact of the mock server to a Pact Broker, in the form it would be written to a
+  /// pact file
+  #[cfg(feature = "broker")]
+  pub async fn publish_pact(&self, options: &crate::broker::PublishOptions) -> anyhow::Result<()> {
+    crate::broker::publish_pact(&self.pact_json()?, options).await
+  }
+
+  /// Returns the pact as it should be output, along with the specification version to output it as
+  fn pact_for_output(&self) -> anyhow::Result<(V4Pact, PactSpecification)> {
+    let specification = self.write_specification();
+    let mut v4_pact = self.pact_to_write(specification);
+    v4_pact.add_md_version("mockserver", option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"));
+    for interaction in &mut v4_pact.interactions {
+      interaction.set_transport(Some("http".to_string()));
+    }
+    self.validate_spec_downgrade(&v4_pact, specification)?;
+    Ok((v4_pact, specification))
+  }
+
+  /// Returns the interactions of the Pact that are not verified by the mock server, as they are not
+  /// HTTP interactions (i.e. asynchronous or synchronous messages). Each one is returned as its type
+  /// and description.
+  pub fn skipped_interactions(&self) -> Vec<String> {
+    self.pact.interactions.iter()
+      .filter(|interaction| interaction.as_v4_http().is_none())
+      .map(|interaction| format!("{} '{}'", interaction.type_of(), interaction.description()))
+      .collect()
+  }
+
+  /// Returns the number of requests that matched and mismatched each interaction of the Pact, in
+  /// the same order as the interactions. Requests are counted against the interaction they were
+  /// matched against when they were received.
+  pub fn interaction_requests(&self) -> Vec<InteractionRequests> {
+    let mut counts = vec![InteractionRequests::default(); self.pact.interactions.len()];
+    for request in self.requests.lock().unwrap().iter() {
+      if let Some(count) = request.interaction.and_then(|index| counts.get_mut(index)) {
+        match request.result {
+          MatchResult::RequestMatch(..) => count.matched += 1,
+          MatchResult::RequestMismatch(..) => count.mismatched += 1,
+          _ => {}
+        }
+      }
+    }
+    counts
+  }
+
+  /// Returns the number of requests that matched each interaction of the Pact, in the same order
+  /// as the interactions
+  pub fn interaction_match_counts(&self) -> Vec<usize> {
+    self.interaction_requests().iter()
+      .map(|count| count.matched)
+      .collect()
+  }
+
+  /// Returns the Pact to write, with only the exercised interactions or with the match counts
+  /// added to the interactions if the mock server has been configured to do so
+  fn pact_to_write(&self, specification: PactSpecification) -> V4Pact {
+    let mut pact = self.pact.clone();
+    if !self.config.write_exercised_only && !self.config.annotate_match_counts {
+      return pact;
+    }
+
+    let counts = self.interaction_match_counts();
+    let annotate = self.config.annotate_match_counts && specification >= PactSpecification::V4;
+    if self.config.annotate_match_counts && !annotate {
+      warn!("Match counts can only be written to V4 Pact files, not adding them to the {} Pact file", specification);
+    }
+    pact.interactions = pact.interactions.into_iter()
+      .zip(counts)
+      .filter(|(interaction, count)| {
+        let exercised = *count > 0 || !self.config.write_exercised_only || interaction.as_v4_http().is_none();
+        if !exercised {
+          debug!("Not writing interaction '{}' as it did not receive any matching requests", interaction.description());
+        }
+        exercised
+      })
+      .map(|(mut interaction, count)| {
+        if annotate && interaction.as_v4_http().is_some() {
+          interaction.comments_mut().insert("matchCount".to_string(), json!(count));
+        }
+        interaction
+      })
+      .collect();
+    if pact.interactions.is_empty() {
+      warn!("None of the interactions received a matching request, the Pact file will have no interactions");
+    }
+    pact
+  }
+
+  /// Returns the features of the Pact that can not be represented in the Pact specification
+  /// version the Pact file will be written as
+  pub fn spec_downgrade_issues(&self) -> Vec<DowngradeIssue> {
+    validate_downgrade(&self.pact, self.write_specification())
+  }
+
+  fn write_specification(&self) -> PactSpecification {
+    match self.spec_version {
+      PactSpecification::Unknown => self.pact.specification_version(),
+      _ => self.spec_version
+    }
+  }
+
+  /// Logs a warning for each feature of the Pact that will be lost when it is written as the
+  /// specification version, or returns an error if strict validation is enabled
+  fn validate_spec_downgrade(&self, pact: &V4Pact, specification: PactSpecification) -> anyhow::Result<()> {
+    let issues = validate_downgrade(pact, specification);
+    if issues.is_empty() {
+      Ok(())
+    } else if self.config.strict_spec_validation {
+      Err(anyhow!("Pact can not be written as a {} Pact file without losing information: {}",
+        specification, issues.iter().map(|issue| issue.to_string()).collect::<Vec<_>>().join("; ")))
+    } else {
+      for issue in &issues {
+        warn!("Writing pact as a {} Pact file will lose information - {}", specification, issue);
+      }
+      Ok(())
+    }
+  }
+
   /// Returns the URL of the mock server
   pub fn url(&self) -> String {
-    if self.address.ip().is_unspecified() {
-      if self.address.is_ipv4() {
-        format!("{}://{}:{}", self.scheme, Ipv4Addr::LOCALHOST, self.address.port())
+    self.url_for(&self.address)
+  }
+
+  /// Returns all the addresses the mock server is bound to. The main address is always first.
+  pub fn addresses(&self) -> Vec<SocketAddr> {
+    let mut addresses = vec![ self.address ];
+    addresses.extend_from_slice(&self.additional_addresses);
+    addresses
+  }
+
+  /// Returns the URLs for all the addresses the mock server is bound to
+  pub fn urls(&self) -> Vec<String> {
+    self.addresses().iter().map(|address| self.url_for(address)).collect()
+  }
+
+  fn url_for(&self, address: &SocketAddr) -> String {
+    if address.ip().is_unspecified() {
+      if address.is_ipv4() {
+        format!("{}://{}:{}", self.scheme, Ipv4Addr::LOCALHOST, address.port())
       } else {
-        format!("{}://[{}]:{}", self.scheme, Ipv6Addr::LOCALHOST, self.address.port())
+        format!("{}://[{}]:{}", self.scheme, Ipv6Addr::LOCALHOST, address.port())
       }
     } else {
-      format!("{}://{}", self.scheme, self.address)
+      format!("{}://{}", self.scheme, address)
     }
   }
 
@@ -532,9 +1131,17 @@ mod tests {
   use expectest::prelude::*;
   use maplit::hashmap;
   use pact_models::PactSpecification;
+  use pact_models::prelude::{Consumer, Provider};
+  use pact_models::v4::async_message::AsynchronousMessage;
+  use pact_models::v4::http_parts::HttpRequest;
+  use pact_models::v4::interaction::V4Interaction;
+  use pact_models::v4::pact::V4Pact;
+  use pact_models::v4::synch_http::SynchronousHttp;
   use serde_json::{json, Value};
 
+  use crate::cors::CorsPolicy;
   use crate::mock_server::{MockServer, MockServerConfig};
+  use crate::pact_writer::MergePolicy;
 
   #[test]
   fn test_mock_server_config_from_json() {
@@ -569,6 +1176,105 @@ mod tests {
     expect!(MockServerConfig::from_json(&json!({
       "keepAlive": true
     }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      compress_responses: true,
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "compressResponses": true
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      preserve_header_case: true,
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "preserveHeaderCase": true
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      metrics_path: Some("/__pact/metrics".to_string()),
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "metricsPath": "/__pact/metrics"
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      admin_path: Some("/__pact".to_string()),
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "adminPath": "/__pact"
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      admin_output_dir: Some("target/pacts".to_string()),
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "adminOutputDir": "target/pacts"
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      response_delay: Some(std::time::Duration::from_millis(250)),
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "responseDelay": 250
+    }))).to(be_equal_to(config.clone()));
+    expect!(MockServerConfig::from_json(&json!({
+      "responseDelay": "250"
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      strict_spec_validation: true,
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "strictSpecValidation": true
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      write_exercised_only: true,
+      annotate_match_counts: true,
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "writeExercisedOnly": true,
+      "annotateMatchCounts": true
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      merge_policy: MergePolicy::PreferExisting,
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "mergePolicy": "prefer-existing"
+    }))).to(be_equal_to(config));
+    expect!(MockServerConfig::from_json(&json!({
+      "mergePolicy": "other"
+    }))).to(be_equal_to(MockServerConfig::default()));
+
+    let config = MockServerConfig {
+      additional_addresses: vec!["[::1]:0".to_string(), "127.0.0.2:0".to_string()],
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "additionalAddresses": ["[::1]:0", "127.0.0.2:0"]
+    }))).to(be_equal_to(config.clone()));
+    expect!(MockServerConfig::from_json(&json!({
+      "additionalAddresses": "[::1]:0, 127.0.0.2:0"
+    }))).to(be_equal_to(config));
+
+    let config = MockServerConfig {
+      cors_policy: CorsPolicy::disabled(),
+      .. MockServerConfig::default()
+    };
+    expect!(MockServerConfig::from_json(&json!({
+      "cors": false
+    }))).to(be_equal_to(config));
   }
 
   #[test]
@@ -615,4 +1321,94 @@ mod tests {
     expect!(ms.url()).to(be_equal_to("http://10.0.0.1:1025"));
     expect!(ms.port()).to(be_equal_to(1025));
   }
+
+  #[test]
+  fn write_pact_fails_with_strict_spec_validation_if_information_would_be_lost() {
+    let pact = V4Pact {
+      interactions: vec![
+        SynchronousHttp { description: "get orders".to_string(), .. SynchronousHttp::default() }.boxed_v4(),
+        AsynchronousMessage { description: "an order event".to_string(), .. AsynchronousMessage::default() }.boxed_v4()
+      ],
+      .. V4Pact::default()
+    };
+    let ms = MockServer {
+      pact,
+      spec_version: PactSpecification::V3,
+      config: MockServerConfig { strict_spec_validation: true, .. MockServerConfig::default() },
+      .. MockServer::default()
+    };
+
+    expect!(ms.spec_downgrade_issues().len()).to(be_equal_to(1));
+    let result = ms.write_pact(&Some("/nonexistent".to_string()), false);
+    expect!(result.as_ref()).to(be_err());
+    expect!(result.unwrap_err().to_string()).to(be_equal_to(
+      "Pact can not be written as a V3 Pact file without losing information: Interaction 'an order event': \
+      V4 Asynchronous/Messages interactions can not be written to a V3 HTTP Pact file"));
+  }
+
+  #[test]
+  fn pact_output_test() {
+    let pact = V4Pact {
+      consumer: Consumer { name: "output-consumer".to_string() },
+      provider: Provider { name: "output-provider".to_string() },
+      interactions: vec![
+        SynchronousHttp { description: "get orders".to_string(), .. SynchronousHttp::default() }.boxed_v4()
+      ],
+      .. V4Pact::default()
+    };
+    let ms = MockServer {
+      pact,
+      spec_version: PactSpecification::V3,
+      .. MockServer::default()
+    };
+
+    let json = ms.pact_json().unwrap();
+    expect!(json["metadata"]["pactSpecification"]["version"].clone()).to(be_equal_to(json!("3.0.0")));
+    expect!(json["interactions"][0]["description"].clone()).to(be_equal_to(json!("get orders")));
+
+    let mut buffer = vec![];
+    ms.write_pact_to(&mut buffer).unwrap();
+    expect!(serde_json::from_slice::<Value>(&buffer).unwrap()).to(be_equal_to(json.clone()));
+
+    let path = std::env::temp_dir()
+      .join(format!("pact_mock_server_output_{}", std::process::id()))
+      .join("custom-name.json");
+    ms.write_pact_to_path(&path, true).unwrap();
+    let written: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    expect!(written).to(be_equal_to(json));
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+  }
+
+  #[test]
+  fn write_pact_to_path_returns_the_merge_report() {
+    let pact_with_path = |path: &str| V4Pact {
+      consumer: Consumer { name: "merge-consumer".to_string() },
+      provider: Provider { name: "merge-provider".to_string() },
+      interactions: vec![
+        SynchronousHttp {
+          description: "get orders".to_string(),
+          request: HttpRequest { path: path.to_string(), .. HttpRequest::default() },
+          .. SynchronousHttp::default()
+        }.boxed_v4()
+      ],
+      .. V4Pact::default()
+    };
+    let path = std::env::temp_dir()
+      .join(format!("pact_mock_server_merge_report_{}", std::process::id()))
+      .join("merge.json");
+
+    let ms = MockServer { pact: pact_with_path("/orders"), .. MockServer::default() };
+    let report = ms.write_pact_to_path(&path, true).unwrap();
+    expect!(report.merged).to(be_false());
+
+    let ms = MockServer { pact: pact_with_path("/orders/1"), .. MockServer::default() };
+    let report = ms.write_pact_to_path(&path, false).unwrap();
+    let written: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+
+    expect!(report.merged).to(be_true());
+    expect!(report.conflicts.len()).to(be_equal_to(1));
+    expect!(report.policy).to(be_equal_to(MergePolicy::PreferNew));
+    expect!(written["interactions"][0]["request"]["path"].clone()).to(be_equal_to(json!("/orders/1")));
+  }
 }
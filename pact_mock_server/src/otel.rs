@@ -0,0 +1,57 @@
+//! Support for OpenTelemetry tracing. The trace context of received requests (i.e. the W3C
+//! `traceparent` and `tracestate` headers) is used as the parent of the spans for the request, so
+//! that the mock server shows up in distributed traces when the spans are exported with an
+//! OpenTelemetry tracing layer.
+//!
+//! The trace context is extracted with the global text map propagator, so this must be configured
+//! by the application (for example, with `global::set_text_map_propagator(TraceContextPropagator::new())`).
+
+use hyper::HeaderMap;
+use opentelemetry::global;
+use opentelemetry::propagation::Extractor;
+use tracing::{Span, trace};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Extracts the trace context values from the request headers
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+  fn get(&self, key: &str) -> Option<&str> {
+    self.0.get(key).and_then(|value| value.to_str().ok())
+  }
+
+  fn keys(&self) -> Vec<&str> {
+    self.0.keys().map(|key| key.as_str()).collect()
+  }
+}
+
+/// Sets the parent of the span from the trace context headers of the request
+pub(crate) fn set_parent_context(span: &Span, headers: &HeaderMap) {
+  let context = global::get_text_map_propagator(|propagator| {
+    propagator.extract(&HeaderExtractor(headers))
+  });
+  if let Err(err) = span.set_parent(context) {
+    trace!("Could not set the parent trace context of the request span: {}", err);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use hyper::header::HeaderValue;
+
+  use super::*;
+
+  #[test]
+  fn header_extractor_test() {
+    let mut headers = HeaderMap::new();
+    headers.insert("traceparent", HeaderValue::from_static("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"));
+    headers.insert("x-invalid", HeaderValue::from_bytes(b"\xFF").unwrap());
+    let extractor = HeaderExtractor(&headers);
+
+    expect!(extractor.get("traceparent")).to(be_some().value("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"));
+    expect!(extractor.get("tracestate")).to(be_none());
+    expect!(extractor.get("x-invalid")).to(be_none());
+    expect!(extractor.keys()).to(be_equal_to(vec!["traceparent", "x-invalid"]));
+  }
+}
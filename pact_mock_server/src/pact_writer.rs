@@ -0,0 +1,548 @@
+//! Writing of Pact files that is safe when multiple processes write to the same file. The pact
+//! file is updated while holding an exclusive advisory lock on a lock file next to it (i.e.
+//! `.consumer-provider.json.lock`), and is written to a temporary file which is then renamed over
+//! the pact file, so readers never see a partially written file.
+//!
+//! When merging with an existing pact file, interactions with the same type, description and
+//! provider states that have different contents are conflicts, and are resolved with the
+//! [`MergePolicy`].
+
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use pact_models::pact::{Pact, ReadWritePact, read_pact};
+use pact_models::PactSpecification;
+use pact_models::v4::interaction::V4Interaction;
+use pact_models::v4::pact::V4Pact;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, trace, warn};
+use uuid::Uuid;
+
+/// How long to keep trying to get the lock on a pact file before giving up
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Initial delay between attempts to get the lock. This doubles after each attempt.
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Maximum delay between attempts to get the lock
+const MAX_LOCK_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// How to resolve conflicts between the interactions being written and the interactions in an
+/// existing pact file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergePolicy {
+  /// Fail to write the pact file, with a report of the conflicts
+  Fail,
+  /// Replace the conflicting interactions in the pact file with the new ones. This is the default,
+  /// and is how pact files have always been merged.
+  #[default]
+  PreferNew,
+  /// Keep the conflicting interactions in the pact file
+  PreferExisting
+}
+
+impl FromStr for MergePolicy {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().replace('_', "-").as_str() {
+      "fail" => Ok(MergePolicy::Fail),
+      "prefer-new" => Ok(MergePolicy::PreferNew),
+      "prefer-existing" => Ok(MergePolicy::PreferExisting),
+      _ => Err(anyhow!("'{}' is not a valid merge policy (expected fail, prefer-new or prefer-existing)", s))
+    }
+  }
+}
+
+impl Display for MergePolicy {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      MergePolicy::Fail => write!(f, "fail"),
+      MergePolicy::PreferNew => write!(f, "prefer-new"),
+      MergePolicy::PreferExisting => write!(f, "prefer-existing")
+    }
+  }
+}
+
+/// Interaction that is in both the pact being written and the existing pact file, with different
+/// contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+  /// Type of the interaction
+  pub interaction_type: String,
+  /// Description of the interaction
+  pub description: String,
+  /// Provider states of the interaction
+  pub provider_states: Vec<String>,
+  /// Differences between the existing and new interaction, one for each JSON path that differs
+  pub differences: Vec<String>
+}
+
+impl Display for MergeConflict {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{} '{}'", self.interaction_type, self.description)?;
+    if !self.provider_states.is_empty() {
+      write!(f, " (provider states: {})", self.provider_states.join(", "))?;
+    }
+    for difference in &self.differences {
+      write!(f, "\n    {}", difference)?;
+    }
+    Ok(())
+  }
+}
+
+/// Report of merging a pact with an existing pact file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergeReport {
+  /// If the pact was merged with an existing pact file
+  pub merged: bool,
+  /// Conflicting interactions
+  pub conflicts: Vec<MergeConflict>,
+  /// Policy used to resolve the conflicts
+  pub policy: MergePolicy
+}
+
+impl Display for MergeReport {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{} conflicting interaction(s) with the existing pact file", self.conflicts.len())?;
+    for (index, conflict) in self.conflicts.iter().enumerate() {
+      write!(f, "\n  {}) {}", index + 1, conflict)?;
+    }
+    Ok(())
+  }
+}
+
+/// Error returned when the pact conflicts with an existing pact file and the merge policy is
+/// [`MergePolicy::Fail`]. Can be retrieved from the returned error with `downcast_ref`.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Could not merge the pact with the existing pact file '{}': {}", path.display(), report)]
+pub struct PactMergeError {
+  /// Path of the pact file
+  pub path: PathBuf,
+  /// Report of the conflicts
+  pub report: MergeReport
+}
+
+/// Writes the Pact to the file as the given specification version. If overwrite is false and the
+/// file exists, the Pact is merged with the existing file, with any conflicts resolved using the
+/// merge policy. Other processes using this function to write the same file will wait for the
+/// lock, so no interactions will be lost.
+pub fn write_pact(
+  pact: &V4Pact,
+  path: &Path,
+  specification: PactSpecification,
+  overwrite: bool,
+  merge_policy: MergePolicy
+) -> anyhow::Result<MergeReport> {
+  if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+    fs::create_dir_all(dir)
+      .with_context(|| format!("Could not create the directory '{}'", dir.display()))?;
+  }
+
+  let lock_file = lock_pact_file(path, LOCK_TIMEOUT)?;
+  let result = merge_and_write(pact, path, specification, overwrite, merge_policy);
+  if let Err(err) = lock_file.unlock() {
+    warn!("Failed to release the lock on the pact file '{}' - {}", path.display(), err);
+  }
+  result
+}
+
+fn merge_and_write(
+  pact: &V4Pact,
+  path: &Path,
+  specification: PactSpecification,
+  overwrite: bool,
+  merge_policy: MergePolicy
+) -> anyhow::Result<MergeReport> {
+  let mut report = MergeReport { policy: merge_policy, .. MergeReport::default() };
+  let json = if !overwrite && path.exists() {
+    debug!("Merging pact with file '{}'", path.display());
+    let existing = read_pact(path)
+      .with_context(|| format!("Could not read the existing pact file '{}'", path.display()))?;
+    if existing.specification_version() < pact.specification_version() {
+      warn!("Note: Existing pact is an older specification version ({}), and will be upgraded",
+        existing.specification_version());
+    }
+    let mut existing = existing.as_v4_pact()
+      .with_context(|| format!("Could not read the existing pact file '{}'", path.display()))?;
+    let mut pact = pact.clone();
+
+    report.merged = true;
+    report.conflicts = find_conflicts(&pact, &existing, specification)
+      .with_context(|| format!("Could not compare the pact with the existing pact file '{}'", path.display()))?;
+    if !report.conflicts.is_empty() {
+      match merge_policy {
+        MergePolicy::Fail => return Err(PactMergeError { path: path.to_path_buf(), report }.into()),
+        MergePolicy::PreferNew => {
+          warn!("Replacing the interactions in the pact file '{}' - {}", path.display(), report);
+          existing.interactions.retain(|interaction| !is_conflict(interaction.as_ref(), &report.conflicts));
+        }
+        MergePolicy::PreferExisting => {
+          warn!("Keeping the interactions in the pact file '{}' - {}", path.display(), report);
+          pact.interactions.retain(|interaction| !is_conflict(interaction.as_ref(), &report.conflicts));
+        }
+      }
+    }
+
+    let merged = pact.merge(&existing)
+      .with_context(|| format!("Could not merge the pact with the existing pact file '{}'", path.display()))?;
+    merged.to_json(specification)?
+  } else {
+    debug!("Writing new pact file '{}'", path.display());
+    pact.to_json(specification)?
+  };
+
+  write_atomically(path, serde_json::to_string_pretty(&json)?.as_bytes())?;
+  Ok(report)
+}
+
+/// Interactions are the same if they have the same type, description and provider states
+fn interaction_id(interaction: &dyn V4Interaction) -> (String, String, Vec<String>) {
+  (
+    interaction.type_of(),
+    interaction.description(),
+    interaction.provider_states().iter().map(|state| state.name.clone()).collect()
+  )
+}
+
+fn is_conflict(interaction: &dyn V4Interaction, conflicts: &[MergeConflict]) -> bool {
+  let (interaction_type, description, provider_states) = interaction_id(interaction);
+  conflicts.iter().any(|conflict| conflict.interaction_type == interaction_type
+    && conflict.description == description && conflict.provider_states == provider_states)
+}
+
+/// Returns the interactions that are in both pacts with different contents. The interactions are
+/// compared in the form they will be written in, ignoring the keys and comments.
+fn find_conflicts(
+  pact: &V4Pact,
+  existing: &V4Pact,
+  specification: PactSpecification
+) -> anyhow::Result<Vec<MergeConflict>> {
+  let mut conflicts = vec![];
+  for interaction in &pact.interactions {
+    let id = interaction_id(interaction.as_ref());
+    let existing_interaction = existing.interactions.iter()
+      .find(|existing| interaction_id(existing.as_ref()) == id);
+    if let Some(existing_interaction) = existing_interaction {
+      let new_json = interaction_json(pact, interaction.as_ref(), specification)?;
+      let existing_json = interaction_json(existing, existing_interaction.as_ref(), specification)?;
+      let mut differences = vec![];
+      json_differences("$", &existing_json, &new_json, &mut differences);
+      if !differences.is_empty() {
+        let (interaction_type, description, provider_states) = id;
+        conflicts.push(MergeConflict { interaction_type, description, provider_states, differences });
+      }
+    }
+  }
+  Ok(conflicts)
+}
+
+/// Returns the JSON of the interaction, as it will be written in the pact for the specification
+/// version. Each interaction is written in a pact of its own, as the interactions are sorted when
+/// the pact is written, and older specification versions can only have some interaction types.
+fn interaction_json(
+  pact: &V4Pact,
+  interaction: &dyn V4Interaction,
+  specification: PactSpecification
+) -> anyhow::Result<Value> {
+  let single = V4Pact {
+    consumer: pact.consumer.clone(),
+    provider: pact.provider.clone(),
+    interactions: vec![interaction.boxed_v4()],
+    metadata: pact.metadata.clone(),
+    plugin_data: pact.plugin_data.clone()
+  };
+  let json = single.to_json(specification)?;
+  let mut interaction_json = json.get("interactions")
+    .or_else(|| json.get("messages"))
+    .and_then(|interactions| interactions.as_array())
+    .and_then(|interactions| interactions.first())
+    .cloned()
+    .ok_or_else(|| anyhow!("Interaction '{}' can not be written as a {} pact", interaction.description(), specification))?;
+  if let Some(map) = interaction_json.as_object_mut() {
+    map.remove("key");
+    map.remove("_id");
+    map.remove("comments");
+  }
+  Ok(interaction_json)
+}
+
+/// Adds a description of each difference between the existing and new JSON values
+fn json_differences(path: &str, existing: &Value, new: &Value, differences: &mut Vec<String>) {
+  match (existing, new) {
+    (Value::Object(existing_map), Value::Object(new_map)) => {
+      let mut keys = existing_map.keys().chain(new_map.keys()).collect::<Vec<_>>();
+      keys.sort();
+      keys.dedup();
+      for key in keys {
+        let child_path = format!("{}.{}", path, key);
+        match (existing_map.get(key), new_map.get(key)) {
+          (Some(existing), Some(new)) => json_differences(&child_path, existing, new, differences),
+          (Some(existing), None) => differences.push(format!("{}: {} (existing) was removed", child_path, existing)),
+          (None, Some(new)) => differences.push(format!("{}: {} (new) was added", child_path, new)),
+          (None, None) => {}
+        }
+      }
+    }
+    (Value::Array(existing_items), Value::Array(new_items)) if existing_items.len() == new_items.len() => {
+      for (index, (existing, new)) in existing_items.iter().zip(new_items).enumerate() {
+        json_differences(&format!("{}[{}]", path, index), existing, new, differences);
+      }
+    }
+    _ => if existing != new {
+      differences.push(format!("{}: {} (existing) != {} (new)", path, existing, new));
+    }
+  }
+}
+
+/// Writes the contents to a temporary file in the same directory, and then renames it to the
+/// path. The rename replaces the file in one step, so the file is never partially written.
+fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+  let temp_path = sibling_path(path, &format!("{}.tmp", Uuid::new_v4().simple()));
+  trace!("Writing pact to temporary file '{}'", temp_path.display());
+  let result = File::create(&temp_path)
+    .and_then(|mut file| {
+      file.write_all(contents)?;
+      file.sync_all()
+    })
+    .and_then(|_| fs::rename(&temp_path, path));
+  if let Err(err) = result {
+    let _ = fs::remove_file(&temp_path);
+    return Err(anyhow!("Could not write the pact file '{}' - {}", path.display(), err));
+  }
+  Ok(())
+}
+
+/// Gets an exclusive lock on the lock file for the pact file, retrying until the timeout expires.
+/// The lock file is not removed afterwards, as another process may be waiting on it. This blocks
+/// the current thread while waiting, so async code should call it on a blocking thread.
+fn lock_pact_file(path: &Path, timeout: Duration) -> anyhow::Result<File> {
+  let lock_path = sibling_path(path, "lock");
+  let file = OpenOptions::new()
+    .create(true)
+    .truncate(false)
+    .write(true)
+    .open(&lock_path)
+    .with_context(|| format!("Could not open the lock file '{}'", lock_path.display()))?;
+
+  let start = Instant::now();
+  let mut delay = LOCK_RETRY_DELAY;
+  let mut attempt = 1;
+  loop {
+    trace!("Attempt {} to get an exclusive lock on '{}'", attempt, lock_path.display());
+    match file.try_lock() {
+      Ok(_) => return Ok(file),
+      Err(TryLockError::Error(err)) => {
+        return Err(anyhow!("Could not lock the lock file '{}' - {}", lock_path.display(), err));
+      }
+      Err(TryLockError::WouldBlock) if start.elapsed() >= timeout => {
+        return Err(anyhow!("Could not acquire the lock on the pact file '{}' after {} attempts over {:?} \
+          (another process may be writing it, or the lock file '{}' is held)",
+          path.display(), attempt, timeout, lock_path.display()));
+      }
+      Err(TryLockError::WouldBlock) => {
+        trace!("The lock is held, retrying in {:?}", delay);
+        thread::sleep(delay);
+        delay = (delay * 2).min(MAX_LOCK_RETRY_DELAY);
+        attempt += 1;
+      }
+    }
+  }
+}
+
+/// Returns the path of a hidden file in the same directory as the pact file
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+  let file_name = path.file_name()
+    .map(|name| name.to_string_lossy().to_string())
+    .unwrap_or_default();
+  path.with_file_name(format!(".{}.{}", file_name, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Barrier};
+
+  use expectest::prelude::*;
+  use pact_models::prelude::Consumer;
+  use pact_models::prelude::Provider;
+  use pact_models::v4::interaction::V4Interaction;
+  use pact_models::v4::synch_http::SynchronousHttp;
+
+  use super::*;
+
+  fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pact_mock_server_writer_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+  }
+
+  fn pact_with_interaction(description: &str) -> V4Pact {
+    V4Pact {
+      consumer: Consumer { name: "consumer".to_string() },
+      provider: Provider { name: "provider".to_string() },
+      interactions: vec![
+        SynchronousHttp { description: description.to_string(), .. SynchronousHttp::default() }.boxed_v4()
+      ],
+      .. V4Pact::default()
+    }
+  }
+
+  fn pact_with_request_path(description: &str, request_path: &str, comment: Option<&str>) -> V4Pact {
+    let mut interaction = SynchronousHttp { description: description.to_string(), .. SynchronousHttp::default() };
+    interaction.request.path = request_path.to_string();
+    if let Some(comment) = comment {
+      interaction.comments.insert("text".to_string(), serde_json::json!([comment]));
+    }
+    V4Pact {
+      consumer: Consumer { name: "consumer".to_string() },
+      provider: Provider { name: "provider".to_string() },
+      interactions: vec![interaction.boxed_v4()],
+      .. V4Pact::default()
+    }
+  }
+
+  fn request_paths(path: &Path) -> Vec<String> {
+    let pact = read_pact(path).unwrap().as_v4_pact().unwrap();
+    pact.interactions.iter()
+      .map(|interaction| interaction.as_v4_http().unwrap().request.path)
+      .collect()
+  }
+
+  fn interactions(path: &Path) -> Vec<String> {
+    let pact = read_pact(path).unwrap();
+    let mut descriptions = pact.interactions().iter()
+      .map(|interaction| interaction.description())
+      .collect::<Vec<_>>();
+    descriptions.sort();
+    descriptions
+  }
+
+  #[test]
+  fn write_pact_merges_with_the_existing_file() {
+    let path = test_dir("merge").join("consumer-provider.json");
+    write_pact(&pact_with_interaction("a"), &path, PactSpecification::V4, false, MergePolicy::Fail).unwrap();
+    write_pact(&pact_with_interaction("b"), &path, PactSpecification::V4, false, MergePolicy::Fail).unwrap();
+    expect!(interactions(&path)).to(be_equal_to(vec!["a".to_string(), "b".to_string()]));
+
+    write_pact(&pact_with_interaction("c"), &path, PactSpecification::V4, true, MergePolicy::Fail).unwrap();
+    expect!(interactions(&path)).to(be_equal_to(vec!["c".to_string()]));
+
+    let temp_files = fs::read_dir(path.parent().unwrap()).unwrap()
+      .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".tmp"))
+      .count();
+    expect!(temp_files).to(be_equal_to(0));
+  }
+
+  #[test]
+  fn write_pact_fails_with_a_report_of_conflicting_interactions() {
+    let path = test_dir("conflict_fail").join("consumer-provider.json");
+    write_pact(&pact_with_request_path("a", "/a", None), &path, PactSpecification::V4, false, MergePolicy::Fail).unwrap();
+
+    let result = write_pact(&pact_with_request_path("a", "/b", None), &path, PactSpecification::V4, false, MergePolicy::Fail);
+    let err = result.unwrap_err();
+    let merge_error = err.downcast_ref::<PactMergeError>().unwrap();
+    expect!(merge_error.report.conflicts.clone()).to(be_equal_to(vec![
+      MergeConflict {
+        interaction_type: "V4 Synchronous/HTTP".to_string(),
+        description: "a".to_string(),
+        provider_states: vec![],
+        differences: vec![r#"$.request.path: "/a" (existing) != "/b" (new)"#.to_string()]
+      }
+    ]));
+    expect!(err.to_string()).to(be_equal_to(format!(
+      "Could not merge the pact with the existing pact file '{}': 1 conflicting interaction(s) with the existing pact file\n  \
+      1) V4 Synchronous/HTTP 'a'\n    $.request.path: \"/a\" (existing) != \"/b\" (new)", path.display())));
+    expect!(request_paths(&path)).to(be_equal_to(vec!["/a".to_string()]));
+  }
+
+  #[test]
+  fn write_pact_resolves_conflicts_with_the_merge_policy() {
+    let path = test_dir("conflict_policy").join("consumer-provider.json");
+    write_pact(&pact_with_request_path("a", "/a", None), &path, PactSpecification::V4, false, MergePolicy::Fail).unwrap();
+
+    let report = write_pact(&pact_with_request_path("a", "/b", None), &path, PactSpecification::V4, false, MergePolicy::PreferExisting).unwrap();
+    expect!(report.conflicts.len()).to(be_equal_to(1));
+    expect!(request_paths(&path)).to(be_equal_to(vec!["/a".to_string()]));
+
+    let report = write_pact(&pact_with_request_path("a", "/b", None), &path, PactSpecification::V4, false, MergePolicy::PreferNew).unwrap();
+    expect!(report.conflicts.len()).to(be_equal_to(1));
+    expect!(request_paths(&path)).to(be_equal_to(vec!["/b".to_string()]));
+  }
+
+  #[test]
+  fn write_pact_ignores_differences_in_comments() {
+    let path = test_dir("conflict_comments").join("consumer-provider.json");
+    write_pact(&pact_with_request_path("a", "/a", Some("first")), &path, PactSpecification::V4, false, MergePolicy::Fail).unwrap();
+
+    let report = write_pact(&pact_with_request_path("a", "/a", Some("second")), &path, PactSpecification::V4, false, MergePolicy::Fail).unwrap();
+    expect!(report.merged).to(be_true());
+    expect!(report.conflicts.is_empty()).to(be_true());
+    expect!(request_paths(&path)).to(be_equal_to(vec!["/a".to_string()]));
+  }
+
+  #[test]
+  fn write_pact_compares_each_interaction_with_the_matching_existing_one() {
+    let path = test_dir("conflict_order").join("consumer-provider.json");
+    let mut existing = pact_with_request_path("a", "/a", None);
+    existing.interactions.extend(pact_with_request_path("b", "/b", None).interactions);
+    write_pact(&existing, &path, PactSpecification::V4, false, MergePolicy::Fail).unwrap();
+
+    // The interactions are not in the order they are written in (sorted by description)
+    let mut pact = pact_with_request_path("b", "/b", None);
+    pact.interactions.extend(pact_with_request_path("a", "/a2", None).interactions);
+    let result = write_pact(&pact, &path, PactSpecification::V4, false, MergePolicy::Fail);
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+
+    let error = result.unwrap_err();
+    let conflicts = &error.downcast_ref::<PactMergeError>().unwrap().report.conflicts;
+    expect!(conflicts.iter().map(|conflict| conflict.description.clone()).collect::<Vec<_>>())
+      .to(be_equal_to(vec!["a".to_string()]));
+  }
+
+  #[test]
+  fn merge_policy_from_str() {
+    expect!("fail".parse::<MergePolicy>().unwrap()).to(be_equal_to(MergePolicy::Fail));
+    expect!("prefer-new".parse::<MergePolicy>().unwrap()).to(be_equal_to(MergePolicy::PreferNew));
+    expect!("Prefer_Existing".parse::<MergePolicy>().unwrap()).to(be_equal_to(MergePolicy::PreferExisting));
+    expect!("other".parse::<MergePolicy>()).to(be_err());
+    expect!(MergePolicy::default()).to(be_equal_to(MergePolicy::PreferNew));
+  }
+
+  #[test]
+  fn concurrent_writes_do_not_lose_interactions() {
+    let path = test_dir("concurrent").join("consumer-provider.json");
+    let barrier = Arc::new(Barrier::new(8));
+    let handles = (0..8).map(|i| {
+      let path = path.clone();
+      let barrier = barrier.clone();
+      thread::spawn(move || {
+        barrier.wait();
+        write_pact(&pact_with_interaction(&format!("interaction {}", i)), &path, PactSpecification::V4, false, MergePolicy::Fail)
+      })
+    }).collect::<Vec<_>>();
+    for handle in handles {
+      handle.join().unwrap().unwrap();
+    }
+
+    expect!(interactions(&path)).to(be_equal_to((0..8).map(|i| format!("interaction {}", i)).collect::<Vec<_>>()));
+  }
+
+  #[test]
+  fn lock_pact_file_fails_if_the_lock_is_held() {
+    let path = test_dir("locked").join("consumer-provider.json");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let _lock = lock_pact_file(&path, Duration::ZERO).unwrap();
+
+    let result = lock_pact_file(&path, Duration::from_millis(50));
+    expect!(result.as_ref()).to(be_err());
+    expect!(result.unwrap_err().to_string().starts_with("Could not acquire the lock on the pact file")).to(be_true());
+  }
+}
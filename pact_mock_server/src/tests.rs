@@ -5,13 +5,17 @@ use pact_matching::Mismatch;
 use pact_models::bodies::OptionalBody;
 use pact_models::matchingrules;
 use pact_models::matchingrules::MatchingRule;
+use pact_models::prelude::{Consumer, Provider};
 use pact_models::prelude::v4::{SynchronousHttp, V4Pact};
 use pact_models::v4::http_parts::{HttpRequest, HttpResponse};
 use pact_models::v4::interaction::V4Interaction;
 use reqwest::header::ACCEPT;
+use tracing_subscriber::layer::SubscriberExt;
 
 use crate::builder::MockServerBuilder;
+use crate::logs::MockServerLogLayer;
 use crate::matching::{match_request, MatchResult};
+use crate::mock_server::{InteractionRequests, MockServerConfig, MockServerEvent};
 
 use super::*;
 
@@ -315,3 +319,686 @@ fn basic_mock_server_test() -> anyhow::Result<()> {
 
   Ok(())
 }
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn shutdown_async_records_all_completed_requests() {
+  let pact = V4Pact {
+    interactions: vec![
+      SynchronousHttp {
+        request: HttpRequest { path: "/one".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4(),
+      SynchronousHttp {
+        request: HttpRequest { path: "/two".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let client = reqwest::Client::new();
+  let response1 = client.get(format!("{}/one", mock_server.url())).send().await.unwrap();
+  let response2 = client.get(format!("{}/two", mock_server.url())).send().await.unwrap();
+
+  let report = mock_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+
+  expect!(response1.status()).to(be_equal_to(200));
+  expect!(response2.status()).to(be_equal_to(200));
+  expect!(report).to(be_equal_to(crate::mock_server::ShutdownReport {
+    drained: true,
+    .. crate::mock_server::ShutdownReport::default()
+  }));
+  expect!(mock_server.matches().len()).to(be_equal_to(2));
+  expect!(mock_server.all_matched()).to(be_true());
+  expect!(mock_server.shutdown_async(std::time::Duration::from_secs(1)).await).to(be_err());
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn shutdown_async_reports_requests_that_were_cut_off() {
+  use tokio::io::AsyncWriteExt;
+
+  let pact = V4Pact {
+    interactions: vec![
+      SynchronousHttp {
+        request: HttpRequest { method: "POST".to_string(), path: "/upload".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  // Only send part of the body, so the request is still being processed at shutdown
+  let mut stream = tokio::net::TcpStream::connect(mock_server.address).await.unwrap();
+  stream.write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\npartial").await.unwrap();
+  tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+  let report = mock_server.shutdown_async(std::time::Duration::from_millis(200)).await.unwrap();
+
+  expect!(report).to(be_equal_to(crate::mock_server::ShutdownReport {
+    drained: false,
+    connections_closed: 1,
+    requests_cut_off: vec!["POST /upload".to_string()]
+  }));
+  expect!(mock_server.matches().is_empty()).to(be_true());
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn subscribe_to_mock_server_events() {
+  let pact = V4Pact {
+    interactions: vec![
+      SynchronousHttp {
+        request: HttpRequest { path: "/one".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let events = mock_server.subscribe();
+  let client = reqwest::Client::new();
+  client.get(format!("{}/one", mock_server.url())).send().await.unwrap();
+  mock_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+
+  let events: Vec<MockServerEvent> = futures::StreamExt::collect(events).await;
+  let events = events.iter()
+    .filter(|event| !matches!(event, MockServerEvent::ConnectionOpened(_) |
+      MockServerEvent::ConnectionClosed(_) | MockServerEvent::ConnectionFailed(_)))
+    .cloned()
+    .collect::<Vec<_>>();
+  expect!(events.len()).to(be_equal_to(5));
+  expect!(events[0].clone()).to(be_equal_to(MockServerEvent::RequestReceived("/one".to_string())));
+  let request_details = match &events[1] {
+    MockServerEvent::RequestRecorded(request) => {
+      expect!(request.result.clone()).to(be_equal_to(mock_server.matches()[0].clone()));
+      request.details.clone()
+    }
+    event => panic!("Expected a request recorded event, got {:?}", event)
+  };
+  expect!(events[2].clone()).to(be_equal_to(MockServerEvent::RequestMatch(mock_server.matches()[0].clone())));
+  match &events[3] {
+    MockServerEvent::ResponsePrepared(details) => {
+      expect!(details.request_id).to(be_equal_to(request_details.request_id));
+      expect!(details.status).to(be_equal_to(200));
+    }
+    event => panic!("Expected a response prepared event, got {:?}", event)
+  }
+  expect!(events[4].clone()).to(be_equal_to(MockServerEvent::ServerShutdown));
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn events_contain_the_request_and_response_details() {
+  let pact = V4Pact {
+    interactions: vec![
+      SynchronousHttp {
+        request: HttpRequest { path: "/one".to_string(), .. HttpRequest::default() },
+        response: HttpResponse {
+          status: 201,
+          body: OptionalBody::from("created"),
+          .. HttpResponse::default()
+        },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let events = mock_server.subscribe();
+  let client = reqwest::Client::new();
+  client.get(format!("{}/one", mock_server.url())).send().await.unwrap();
+  client.get(format!("{}/two", mock_server.url())).send().await.unwrap();
+  mock_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+
+  let events: Vec<MockServerEvent> = futures::StreamExt::collect(events).await;
+  let received = events.iter()
+    .filter_map(|event| match event {
+      MockServerEvent::RequestRecorded(request) => Some(request.details.clone()),
+      _ => None
+    })
+    .collect::<Vec<_>>();
+  let responses = events.iter()
+    .filter_map(|event| match event {
+      MockServerEvent::ResponsePrepared(details) => Some(details.clone()),
+      _ => None
+    })
+    .collect::<Vec<_>>();
+  expect!(received.len()).to(be_equal_to(2));
+  expect!(responses.len()).to(be_equal_to(2));
+  expect!(received[0].request_id).to_not(be_equal_to(received[1].request_id));
+
+  for details in &received {
+    expect!(details.local_address).to(be_equal_to(mock_server.address));
+    expect!(details.remote_address.ip().is_loopback()).to(be_true());
+    expect!(details.http_version.as_str()).to(be_equal_to("HTTP/1.1"));
+  }
+
+  let first = responses.iter().find(|r| r.request_id == received[0].request_id).unwrap();
+  expect!(first.status).to(be_equal_to(201));
+  expect!(first.body_size).to(be_equal_to(7));
+  expect!(first.prepared_at >= received[0].received_at).to(be_true());
+  let second = responses.iter().find(|r| r.request_id == received[1].request_id).unwrap();
+  expect!(second.status).to(be_equal_to(500));
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn mock_server_metrics_test() {
+  let pact = V4Pact {
+    interactions: vec![
+      SynchronousHttp {
+        description: "get one".to_string(),
+        request: HttpRequest { path: "/one".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .with_config(MockServerConfig {
+      metrics_path: Some("/__pact/metrics".to_string()),
+      .. MockServerConfig::default()
+    })
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let client = reqwest::Client::new();
+  client.get(format!("{}/one", mock_server.url())).send().await.unwrap();
+  client.get(format!("{}/one?a=b", mock_server.url())).send().await.unwrap();
+  client.get(format!("{}/two", mock_server.url())).send().await.unwrap();
+  mock_server.wait_for_requests(3, std::time::Duration::from_secs(5)).await.unwrap();
+
+  let response = client.get(format!("{}/__pact/metrics", mock_server.url())).send().await.unwrap();
+  expect!(response.status()).to(be_equal_to(200));
+  expect!(response.headers().get("content-type").unwrap().to_str().unwrap())
+    .to(be_equal_to(crate::metrics::OPENMETRICS_CONTENT_TYPE));
+  let text = response.text().await.unwrap();
+  let server_label = format!("mock_server=\"{}\"", mock_server.id);
+  expect!(text.contains(&format!("pact_mock_server_requests_total{{{}}} 3", server_label))).to(be_true());
+  expect!(text.contains(&format!("pact_mock_server_interaction_matches_total{{{},interaction=\"get one\"}} 1", server_label))).to(be_true());
+  expect!(text.contains(&format!("pact_mock_server_mismatches_total{{{},type=\"QueryMismatch\"}} 1", server_label))).to(be_true());
+  expect!(text.contains(&format!("pact_mock_server_mismatches_total{{{},type=\"RequestNotFound\"}} 1", server_label))).to(be_true());
+  expect!(text.ends_with("# EOF\n")).to(be_true());
+
+  mock_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+  let metrics = mock_server.metrics.lock().unwrap().clone();
+  expect!(metrics.requests).to(be_equal_to(3));
+  expect!(metrics.response_latency.count).to(be_equal_to(3));
+  expect!(metrics.total_connections).to(be_greater_or_equal_to(1));
+  expect!(metrics.active_connections).to(be_equal_to(0));
+  // The metrics request is not matched against the Pact
+  expect!(mock_server.matches().len()).to(be_equal_to(3));
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn mock_server_admin_api_test() {
+  let pact = V4Pact {
+    consumer: Consumer { name: "admin-consumer".to_string() },
+    provider: Provider { name: "admin-provider".to_string() },
+    interactions: vec![
+      SynchronousHttp {
+        description: "get one".to_string(),
+        request: HttpRequest { path: "/one".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let output_dir = std::env::temp_dir().join(format!("pact-admin-{}", std::process::id()));
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .with_admin_path("/__pact")
+    .with_admin_output_dir(output_dir.to_string_lossy())
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+  let admin_url = format!("{}/__pact", mock_server.url());
+  let client = reqwest::Client::new();
+
+  let status: serde_json::Value = client.get(format!("{}/status", admin_url)).send().await.unwrap()
+    .json().await.unwrap();
+  expect!(status["id"].as_str()).to(be_some().value(mock_server.id.as_str()));
+  expect!(status["status"].as_str()).to(be_some().value("error"));
+
+  let interactions: serde_json::Value = client.get(format!("{}/interactions", admin_url)).send().await.unwrap()
+    .json().await.unwrap();
+  expect!(interactions["interactions"][0]["description"].as_str()).to(be_some().value("get one"));
+
+  client.get(format!("{}/two", mock_server.url())).send().await.unwrap();
+  mock_server.wait_for_requests(1, std::time::Duration::from_secs(5)).await.unwrap();
+  let mismatches: serde_json::Value = client.get(format!("{}/mismatches", admin_url)).send().await.unwrap()
+    .json().await.unwrap();
+  expect!(mismatches["mismatches"].as_array().unwrap().len()).to(be_equal_to(2));
+
+  let response = client.post(format!("{}/reset", admin_url)).send().await.unwrap();
+  expect!(response.status().as_u16()).to(be_equal_to(204));
+  expect!(mock_server.matches().len()).to(be_equal_to(0));
+
+  client.get(format!("{}/one", mock_server.url())).send().await.unwrap();
+  mock_server.wait_for_requests(1, std::time::Duration::from_secs(5)).await.unwrap();
+  let matches: serde_json::Value = client.get(format!("{}/matches", admin_url)).send().await.unwrap()
+    .json().await.unwrap();
+  expect!(matches["matches"].as_array().unwrap().len()).to(be_equal_to(1));
+  expect!(matches["matches"][0]["type"].as_str()).to(be_some().value("request-match"));
+
+  let response = client.post(format!("{}/write-pact", admin_url))
+    .query(&[("outputPath", "consumer")])
+    .send().await.unwrap();
+  expect!(response.status().as_u16()).to(be_equal_to(204));
+  expect!(output_dir.join("consumer").join("admin-consumer-admin-provider.json").exists()).to(be_true());
+  let response = client.post(format!("{}/write-pact", admin_url))
+    .query(&[("outputPath", "../other")])
+    .send().await.unwrap();
+  expect!(response.status().as_u16()).to(be_equal_to(400));
+  let response = client.post(format!("{}/write-pact", admin_url))
+    .query(&[("outputPath", std::env::temp_dir().to_string_lossy().to_string())])
+    .send().await.unwrap();
+  expect!(response.status().as_u16()).to(be_equal_to(400));
+  let _ = std::fs::remove_dir_all(output_dir);
+
+  let response = client.delete(format!("{}/reset", admin_url)).send().await.unwrap();
+  expect!(response.status().as_u16()).to(be_equal_to(405));
+  let response = client.get(format!("{}/unknown", admin_url)).send().await.unwrap();
+  expect!(response.status().as_u16()).to(be_equal_to(404));
+
+  mock_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+  // Admin requests are not matched against the Pact
+  expect!(mock_server.matches().len()).to(be_equal_to(1));
+}
+
+#[tokio::test]
+async fn mock_server_logs_test() {
+  // Current thread runtime, so all the mock server tasks use the thread local subscriber
+  let subscriber = tracing_subscriber::registry().with(MockServerLogLayer::new());
+  let _guard = tracing::subscriber::set_default(subscriber);
+
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(V4Pact::default())
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+  let mut other_server = MockServerBuilder::new()
+    .with_v4_pact(V4Pact::default())
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let client = reqwest::Client::new();
+  client.get(format!("{}/logs-test", mock_server.url())).send().await.unwrap();
+  mock_server.wait_for_requests(1, std::time::Duration::from_secs(5)).await.unwrap();
+  mock_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+  other_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+
+  let logs = mock_server.logs();
+  expect!(logs.iter().any(|line| line.contains("INFO pact_mock_server::hyper_server: Received request GET /logs-test"))).to(be_true());
+  expect!(other_server.logs().iter().any(|line| line.contains("/logs-test"))).to(be_false());
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn wait_for_request_test() {
+  let pact = V4Pact {
+    interactions: vec![
+      SynchronousHttp {
+        request: HttpRequest { path: "/one".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4(),
+      SynchronousHttp {
+        request: HttpRequest { path: "/two".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let url = mock_server.url();
+  let wait_for_two = mock_server.wait_for_request(|result| match result {
+    MatchResult::RequestMatch(request, _, _) => request.path == "/two",
+    _ => false
+  }, std::time::Duration::from_secs(5));
+  tokio::spawn(async move {
+    let client = reqwest::Client::new();
+    client.get(format!("{}/one", url)).send().await.unwrap();
+    client.get(format!("{}/two", url)).send().await.unwrap();
+  });
+
+  let result = wait_for_two.await.unwrap();
+  expect!(result.matched()).to(be_true());
+  let results = mock_server.wait_for_requests(2, std::time::Duration::from_secs(5)).await.unwrap();
+  expect!(results.len()).to(be_equal_to(2));
+
+  // Requests that have already been received are also checked
+  expect!(mock_server.wait_for_request(|result| result.matched(), std::time::Duration::from_millis(10)).await).to(be_ok());
+  expect!(mock_server.wait_for_requests(3, std::time::Duration::from_millis(100)).await).to(be_err());
+
+  mock_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+}
+
+#[test_log::test]
+fn wait_for_requests_from_a_blocking_test() -> anyhow::Result<()> {
+  let pact = V4Pact {
+    interactions: vec![
+      SynchronousHttp {
+        request: HttpRequest {
+          headers: Some(hashmap! {
+            "accept".to_string() => vec!["application/json".to_string()]
+          }),
+          .. HttpRequest::default()
+        },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut manager = ServerManager::new();
+  let id = "wait_for_requests_from_a_blocking_test".to_string();
+  let mock_server_builder = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .with_id(id.clone())
+    .bind_to("127.0.0.1:0");
+  let port = manager.spawn_mock_server(mock_server_builder)?.port();
+
+  let client = reqwest::blocking::Client::new();
+  let response = client.get(format!("http://127.0.0.1:{}", port).as_str())
+    .header(ACCEPT, "application/json").send();
+  let requests = manager.find_mock_server_by_id(&id, &|_, ms| {
+    ms.unwrap_left().wait_for_requests(1, std::time::Duration::from_secs(5))
+  }).unwrap();
+  let results = tokio::runtime::Runtime::new()?.block_on(requests)?;
+  let all_matched = manager.find_mock_server_by_id(&id, &|_, ms| {
+    ms.unwrap_left().all_matched()
+  }).unwrap();
+  manager.shutdown_mock_server_by_port(port);
+
+  expect!(response.unwrap().status().is_success()).to(be_true());
+  expect!(results.len()).to(be_equal_to(1));
+  expect!(results[0].matched()).to(be_true());
+  expect!(all_matched).to(be_true());
+
+  Ok(())
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn mock_server_response_delay_test() {
+  let pact = V4Pact {
+    interactions: vec![
+      SynchronousHttp {
+        request: HttpRequest { path: "/slow".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .with_response_delay(std::time::Duration::from_millis(200))
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let start = std::time::Instant::now();
+  let response = reqwest::get(format!("{}/slow", mock_server.url())).await.unwrap();
+  expect!(response.status().as_u16()).to(be_equal_to(200));
+  expect!(start.elapsed() >= std::time::Duration::from_millis(200)).to(be_true());
+
+  mock_server.shutdown().unwrap();
+  expect!(mock_server.all_matched()).to(be_true());
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn mock_server_write_exercised_interactions_only_test() {
+  let pact = V4Pact {
+    consumer: Consumer { name: "exercised-consumer".to_string() },
+    provider: Provider { name: "exercised-provider".to_string() },
+    interactions: vec![
+      SynchronousHttp {
+        description: "get users".to_string(),
+        request: HttpRequest { path: "/users".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4(),
+      SynchronousHttp {
+        description: "get orders".to_string(),
+        request: HttpRequest { path: "/orders".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .with_exercised_interactions_only(true)
+    .with_match_count_annotations(true)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  for _ in 0..2 {
+    let response = reqwest::get(format!("{}/users", mock_server.url())).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(200));
+  }
+  mock_server.wait_for_requests(2, std::time::Duration::from_secs(5)).await.unwrap();
+  mock_server.shutdown().unwrap();
+  expect!(mock_server.interaction_match_counts()).to(be_equal_to(vec![2, 0]));
+  // Only exercised interactions are written, but the missing request is still a mismatch
+  expect!(mock_server.mismatches()).to(be_equal_to(vec![
+    MatchResult::MissingRequest(HttpRequest { path: "/orders".to_string(), .. HttpRequest::default() })
+  ]));
+
+  let dir = std::env::temp_dir().join(format!("pact_mock_server_exercised_{}", std::process::id()));
+  mock_server.write_pact(&Some(dir.to_string_lossy().to_string()), true).unwrap();
+  let pact = pact_models::pact::read_pact(&dir.join("exercised-consumer-exercised-provider.json")).unwrap().as_v4_pact().unwrap();
+  expect!(pact.interactions.len()).to(be_equal_to(1));
+  expect!(pact.interactions[0].description()).to(be_equal_to("get users"));
+  expect!(pact.interactions[0].comments().get("matchCount").cloned()).to(be_some().value(serde_json::json!(2)));
+  let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn interaction_requests_are_counted_against_the_interaction_that_was_matched() {
+  use pact_models::provider_states::ProviderState;
+
+  let interaction = |state: &str, path: &str| SynchronousHttp {
+    description: "get users".to_string(),
+    provider_states: vec![ProviderState::default(state)],
+    request: HttpRequest { path: path.to_string(), .. HttpRequest::default() },
+    .. SynchronousHttp::default()
+  }.boxed_v4();
+  let pact = V4Pact {
+    interactions: vec![
+      interaction("there are users", "/users"),
+      interaction("there are no users", "/users"),
+      interaction("there are orders", "/orders")
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let client = reqwest::Client::new();
+  client.get(format!("{}/users", mock_server.url())).send().await.unwrap();
+  client.get(format!("{}/orders?a=b", mock_server.url())).send().await.unwrap();
+  mock_server.wait_for_requests(2, std::time::Duration::from_secs(5)).await.unwrap();
+  mock_server.shutdown_async(std::time::Duration::from_secs(5)).await.unwrap();
+
+  // The interactions with the same request are only counted once, against the one that matched
+  expect!(mock_server.interaction_requests()).to(be_equal_to(vec![
+    InteractionRequests { matched: 1, mismatched: 0 },
+    InteractionRequests { matched: 0, mismatched: 0 },
+    InteractionRequests { matched: 0, mismatched: 1 }
+  ]));
+  expect!(mock_server.interaction_match_counts()).to(be_equal_to(vec![1, 0, 0]));
+  expect!(mock_server.requests().iter().map(|request| request.interaction).collect::<Vec<_>>())
+    .to(be_equal_to(vec![Some(0), Some(2)]));
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn mock_server_with_mixed_interaction_types_test() {
+  use pact_models::v4::async_message::AsynchronousMessage;
+  use pact_models::v4::sync_message::SynchronousMessage;
+
+  let pact = V4Pact {
+    consumer: Consumer { name: "mixed-consumer".to_string() },
+    provider: Provider { name: "mixed-provider".to_string() },
+    interactions: vec![
+      AsynchronousMessage {
+        description: "an order event".to_string(),
+        .. AsynchronousMessage::default()
+      }.boxed_v4(),
+      SynchronousHttp {
+        description: "get users".to_string(),
+        request: HttpRequest { path: "/users".to_string(), .. HttpRequest::default() },
+        .. SynchronousHttp::default()
+      }.boxed_v4(),
+      SynchronousMessage {
+        description: "a user request".to_string(),
+        .. SynchronousMessage::default()
+      }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .with_match_count_annotations(true)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  expect!(mock_server.skipped_interactions()).to(be_equal_to(vec![
+    "V4 Asynchronous/Messages 'an order event'".to_string(),
+    "V4 Synchronous/Messages 'a user request'".to_string()
+  ]));
+  let mismatches = mock_server.mismatches();
+  expect!(mismatches.len()).to(be_equal_to(1));
+  expect!(matches!(&mismatches[0], MatchResult::MissingRequest(request) if request.path == "/users")).to(be_true());
+
+  let response = reqwest::get(format!("{}/users", mock_server.url())).await.unwrap();
+  expect!(response.status().as_u16()).to(be_equal_to(200));
+  mock_server.wait_for_requests(1, std::time::Duration::from_secs(5)).await.unwrap();
+  mock_server.shutdown().unwrap();
+  expect!(mock_server.all_matched()).to(be_true());
+  expect!(mock_server.interaction_match_counts()).to(be_equal_to(vec![0, 1, 0]));
+
+  let dir = std::env::temp_dir().join(format!("pact_mock_server_mixed_{}", std::process::id()));
+  mock_server.write_pact(&Some(dir.to_string_lossy().to_string()), true).unwrap();
+  let pact = pact_models::pact::read_pact(&dir.join("mixed-consumer-mixed-provider.json")).unwrap().as_v4_pact().unwrap();
+  expect!(pact.interactions.len()).to(be_equal_to(3));
+  let match_count = |description: &str| pact.interactions.iter()
+    .find(|interaction| interaction.description() == description)
+    .and_then(|interaction| interaction.comments().get("matchCount").cloned());
+  expect!(match_count("get users")).to(be_some().value(serde_json::json!(1)));
+  expect!(match_count("an order event")).to(be_none());
+  expect!(match_count("a user request")).to(be_none());
+  let _ = std::fs::remove_dir_all(dir);
+}
+
+#[cfg(feature = "broker")]
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn publish_pact_test() {
+  use crate::broker::{BrokerAuth, PublishOptions};
+
+  let pact = V4Pact {
+    consumer: Consumer { name: "web app".to_string() },
+    provider: Provider { name: "orders".to_string() },
+    interactions: vec![
+      SynchronousHttp { description: "get orders".to_string(), .. SynchronousHttp::default() }.boxed_v4()
+    ],
+    .. V4Pact::default()
+  };
+  let mut mock_server = MockServerBuilder::new()
+    .with_v4_pact(pact)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  // Use a mock server as a stand-in for the Pact Broker
+  let broker_request = |description: &str, path: &str| SynchronousHttp {
+    description: description.to_string(),
+    request: HttpRequest {
+      method: "PUT".to_string(),
+      path: path.to_string(),
+      headers: Some(hashmap! { "authorization".to_string() => vec!["Bearer 1234".to_string()] }),
+      .. HttpRequest::default()
+    },
+    response: HttpResponse { status: 201, .. HttpResponse::default() },
+    .. SynchronousHttp::default()
+  }.boxed_v4();
+  let broker_pact = V4Pact {
+    interactions: vec![
+      broker_request("branch", "/pacticipants/web%20app/branches/main/versions/1.0.0"),
+      broker_request("tag", "/pacticipants/web%20app/versions/1.0.0/tags/prod"),
+      broker_request("pact", "/pacts/provider/orders/consumer/web%20app/version/1.0.0")
+    ],
+    .. V4Pact::default()
+  };
+  let mut broker = MockServerBuilder::new()
+    .with_v4_pact(broker_pact)
+    .bind_to("127.0.0.1:0")
+    .start()
+    .await
+    .unwrap();
+
+  let options = PublishOptions {
+    broker_url: broker.url(),
+    consumer_version: "1.0.0".to_string(),
+    branch: Some("main".to_string()),
+    tags: vec!["prod".to_string()],
+    auth: Some(BrokerAuth::Token("1234".to_string()))
+  };
+  let result = mock_server.publish_pact(&options).await;
+  broker.wait_for_requests(3, std::time::Duration::from_secs(5)).await.unwrap();
+  broker.shutdown().unwrap();
+  mock_server.shutdown().unwrap();
+
+  expect!(result).to(be_ok());
+  expect!(broker.all_matched()).to(be_true());
+
+  let published = broker.matches().iter()
+    .filter_map(|result| match result {
+      MatchResult::RequestMatch(_, _, actual) if actual.path.starts_with("/pacts") => Some(actual.clone()),
+      _ => None
+    })
+    .next()
+    .unwrap();
+  let json: serde_json::Value = serde_json::from_slice(&published.body.value().unwrap()).unwrap();
+  expect!(json["interactions"][0]["description"].clone()).to(be_equal_to(serde_json::json!("get orders")));
+}
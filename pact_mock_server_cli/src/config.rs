@@ -0,0 +1,388 @@
+//! Config file for the master server, describing the mock servers to start when it boots. The
+//! file can be in TOML or YAML format, based on the file extension. Relative paths in the file
+//! are resolved from the directory the config file is in.
+//!
+//! ```toml
+//! [[mock_servers]]
+//! id = "orders"
+//! pact = "pacts/web-app-orders.json"
+//! port = 9001
+//! response_delay_ms = 100
+//! provider_states = ["an order with ID 100 exists"]
+//! tls = { cert = "certs/server.pem", key = "certs/server.key" }
+//!
+//! [mock_servers.cors]
+//! allowed_origins = ["http://localhost:3000"]
+//! allow_credentials = true
+//! ```
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use pact_models::pact::{read_pact, Pact};
+use pact_models::PactSpecification;
+use serde::Deserialize;
+use tracing::debug;
+
+use pact_mock_server::builder::MockServerBuilder;
+use pact_mock_server::cors::CorsPolicy;
+use pact_mock_server::mock_server::MockServerConfig;
+use pact_mock_server::pact_writer::MergePolicy;
+
+/// Config file with the mock servers to start
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+  /// Mock servers to start
+  #[serde(default)]
+  pub mock_servers: Vec<MockServerDefinition>,
+  /// Directory that relative paths are resolved from
+  #[serde(skip)]
+  pub base_dir: PathBuf
+}
+
+/// Definition of a mock server in the config file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MockServerDefinition {
+  /// ID to assign to the mock server
+  pub id: Option<String>,
+  /// Pact file to start the mock server with
+  pub pact: PathBuf,
+  /// Port for the mock server. If not set, it will be allocated from the base port of the master
+  /// server, or by the OS.
+  pub port: Option<u16>,
+  /// IP addresses to bind to (defaults to 127.0.0.1)
+  #[serde(default)]
+  pub bind: Vec<IpAddr>,
+  /// Pact specification version to use (defaults to the version of the pact file)
+  pub specification: Option<String>,
+  /// If writing the pact file should fail if the pact can not be represented in the specification
+  /// version
+  #[serde(default)]
+  pub strict_spec_validation: bool,
+  /// If only the interactions that received a matching request should be written to the pact file
+  #[serde(default)]
+  pub exercised_only: bool,
+  /// If the number of matching requests should be added to each interaction in the pact file
+  #[serde(default)]
+  pub match_counts: bool,
+  /// How conflicts with the interactions in an existing pact file are resolved when merging
+  #[serde(default)]
+  pub merge_policy: MergePolicy,
+  /// TLS settings
+  pub tls: Option<TlsDefinition>,
+  /// If CORS pre-flight requests should be responded to
+  #[serde(default)]
+  pub cors_preflight: bool,
+  /// CORS policy for the responses
+  pub cors: Option<CorsDefinition>,
+  /// Delay in milliseconds before each response is sent
+  pub response_delay_ms: Option<u64>,
+  /// If set, only interactions without provider states, or where all the provider states are in
+  /// this list, will be served by the mock server
+  #[serde(default)]
+  pub provider_states: Vec<String>,
+  /// Reserved path to serve the metrics of the mock server from
+  pub metrics_path: Option<String>,
+  /// Reserved path prefix to serve the admin API of the mock server from
+  pub admin_path: Option<String>
+}
+
+/// TLS settings for a mock server. Either `true` to use a self-signed certificate, or the paths to
+/// the certificate and private key PEM files.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum TlsDefinition {
+  /// Enable or disable TLS with a self-signed certificate
+  SelfSigned(bool),
+  /// Use the certificates and private key from the PEM files
+  Certificates {
+    /// Certificate chain PEM file
+    cert: PathBuf,
+    /// Private key PEM file
+    key: PathBuf
+  }
+}
+
+/// CORS policy for a mock server. Any values not set will use the default policy.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CorsDefinition {
+  pub enabled: Option<bool>,
+  pub allowed_origins: Option<Vec<String>>,
+  pub allowed_methods: Option<Vec<String>>,
+  pub allowed_headers: Option<Vec<String>>,
+  pub exposed_headers: Option<Vec<String>>,
+  pub allow_credentials: Option<bool>,
+  pub max_age: Option<u64>
+}
+
+impl ConfigFile {
+  /// Loads the config file. Files with a `.yaml` or `.yml` extension are parsed as YAML, all
+  /// others as TOML.
+  pub fn load(path: &Path) -> anyhow::Result<ConfigFile> {
+    let contents = fs::read_to_string(path)
+      .with_context(|| format!("Could not read the config file '{}'", path.display()))?;
+    let mut config = match path.extension().and_then(|ext| ext.to_str()) {
+      Some("yaml" | "yml") => serde_yaml::from_str::<ConfigFile>(&contents)
+        .with_context(|| format!("Could not parse the YAML config file '{}'", path.display()))?,
+      _ => toml::from_str::<ConfigFile>(&contents)
+        .with_context(|| format!("Could not parse the TOML config file '{}'", path.display()))?
+    };
+    config.base_dir = path.parent().map(|dir| dir.to_path_buf()).unwrap_or_default();
+    Ok(config)
+  }
+
+  /// Resolves a path from the config file against the directory of the config file
+  pub fn resolve(&self, path: &Path) -> PathBuf {
+    self.base_dir.join(path)
+  }
+}
+
+impl MockServerDefinition {
+  /// Creates the mock server builder for this definition, using the given port if no port has
+  /// been set.
+  pub fn builder(&self, config_file: &ConfigFile, port: u16) -> anyhow::Result<MockServerBuilder> {
+    let pact_file = config_file.resolve(&self.pact);
+    let pact = read_pact(&pact_file)
+      .with_context(|| format!("Could not load the pact file '{}'", pact_file.display()))?;
+    let specification = self.specification.as_ref()
+      .map(|spec| PactSpecification::from(spec.as_str()))
+      .unwrap_or_else(|| pact.specification_version());
+    let pact = self.filter_interactions(pact.as_ref())?;
+    debug!("Mock server pact = {:?}", pact);
+
+    let builder = MockServerBuilder::new()
+      .with_pact(pact)
+      .with_config(self.mock_server_config(port, specification));
+    let builder = match &self.id {
+      Some(id) => builder.with_id(id),
+      None => builder
+    };
+    self.with_tls(builder, config_file)
+  }
+
+  /// Returns the pact with only the interactions for the configured provider states
+  fn filter_interactions(&self, pact: &dyn Pact) -> anyhow::Result<Box<dyn Pact + Send + Sync>> {
+    let mut pact = pact.as_v4_pact()?;
+    if !self.provider_states.is_empty() {
+      pact.interactions.retain(|interaction| interaction.provider_states().iter()
+        .all(|state| self.provider_states.contains(&state.name)));
+      if pact.interactions.is_empty() {
+        return Err(anyhow!("There are no interactions in the pact file '{}' for the provider states {:?}",
+          self.pact.display(), self.provider_states));
+      }
+    }
+    Ok(Box::new(pact))
+  }
+
+  fn mock_server_config(&self, port: u16, specification: PactSpecification) -> MockServerConfig {
+    let address = self.bind.first().cloned().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    MockServerConfig {
+      cors_preflight: self.cors_preflight,
+      cors_policy: self.cors.as_ref().map(CorsDefinition::policy).unwrap_or_default(),
+      pact_specification: specification,
+      address: SocketAddr::new(address, self.port.unwrap_or(port)).to_string(),
+      additional_addresses: self.bind.iter().skip(1)
+        .map(|address| SocketAddr::new(*address, 0).to_string())
+        .collect(),
+      metrics_path: self.metrics_path.clone(),
+      admin_path: self.admin_path.clone(),
+      response_delay: self.response_delay_ms.map(Duration::from_millis),
+      strict_spec_validation: self.strict_spec_validation,
+      write_exercised_only: self.exercised_only,
+      annotate_match_counts: self.match_counts,
+      merge_policy: self.merge_policy,
+      .. MockServerConfig::default()
+    }
+  }
+
+  #[cfg(feature = "tls")]
+  fn with_tls(&self, builder: MockServerBuilder, config_file: &ConfigFile) -> anyhow::Result<MockServerBuilder> {
+    use rustls::crypto::ring::default_provider;
+    use rustls::crypto::CryptoProvider;
+
+    match &self.tls {
+      Some(TlsDefinition::SelfSigned(true)) => {
+        if CryptoProvider::get_default().is_none() && CryptoProvider::install_default(default_provider()).is_err() {
+          return Err(anyhow!("Failed to install the default FIPS cryptographic provider"));
+        }
+        builder.with_self_signed_tls()
+      }
+      Some(TlsDefinition::Certificates { cert, key }) => {
+        let cert_file = config_file.resolve(cert);
+        let certificates = fs::read_to_string(&cert_file)
+          .with_context(|| format!("Could not read the certificate file '{}'", cert_file.display()))?;
+        let key_file = config_file.resolve(key);
+        let private_key = fs::read_to_string(&key_file)
+          .with_context(|| format!("Could not read the private key file '{}'", key_file.display()))?;
+        builder.with_tls_certs(&certificates, &private_key)
+      }
+      _ => Ok(builder)
+    }
+  }
+
+  #[cfg(not(feature = "tls"))]
+  fn with_tls(&self, builder: MockServerBuilder, _config_file: &ConfigFile) -> anyhow::Result<MockServerBuilder> {
+    match &self.tls {
+      None | Some(TlsDefinition::SelfSigned(false)) => Ok(builder),
+      _ => Err(anyhow!("TLS is not supported, as the tls feature is not enabled"))
+    }
+  }
+}
+
+impl CorsDefinition {
+  /// Converts this definition into a CORS policy, using the defaults for any values not set
+  pub fn policy(&self) -> CorsPolicy {
+    let default = CorsPolicy::default();
+    CorsPolicy {
+      enabled: self.enabled.unwrap_or(default.enabled),
+      allowed_origins: self.allowed_origins.clone().unwrap_or(default.allowed_origins),
+      allowed_methods: self.allowed_methods.clone().unwrap_or(default.allowed_methods),
+      allowed_headers: self.allowed_headers.clone().unwrap_or(default.allowed_headers),
+      exposed_headers: self.exposed_headers.clone().unwrap_or(default.exposed_headers),
+      allow_credentials: self.allow_credentials.unwrap_or(default.allow_credentials),
+      max_age: self.max_age.or(default.max_age)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use expectest::prelude::*;
+
+  use super::*;
+
+  const TOML_CONFIG: &str = r#"
+    [[mock_servers]]
+    id = "orders"
+    pact = "pacts/orders.json"
+    port = 9001
+    bind = ["127.0.0.1", "::1"]
+    response_delay_ms = 100
+    strict_spec_validation = true
+    match_counts = true
+    merge_policy = "prefer-new"
+    provider_states = ["an order exists"]
+    tls = true
+
+    [mock_servers.cors]
+    allowed_origins = ["http://localhost:3000"]
+    allow_credentials = true
+
+    [[mock_servers]]
+    pact = "pacts/users.json"
+    tls = { cert = "server.pem", key = "server.key" }
+  "#;
+
+  const YAML_CONFIG: &str = r#"
+mock_servers:
+  - id: orders
+    pact: pacts/orders.json
+    port: 9001
+    bind: [127.0.0.1, "::1"]
+    response_delay_ms: 100
+    strict_spec_validation: true
+    match_counts: true
+    merge_policy: prefer-new
+    provider_states:
+      - an order exists
+    tls: true
+    cors:
+      allowed_origins: [http://localhost:3000]
+      allow_credentials: true
+  - pact: pacts/users.json
+    tls:
+      cert: server.pem
+      key: server.key
+"#;
+
+  fn expected_config(base_dir: &Path) -> ConfigFile {
+    ConfigFile {
+      mock_servers: vec![
+        MockServerDefinition {
+          id: Some("orders".to_string()),
+          pact: PathBuf::from("pacts/orders.json"),
+          port: Some(9001),
+          bind: vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+          response_delay_ms: Some(100),
+          strict_spec_validation: true,
+          match_counts: true,
+          merge_policy: MergePolicy::PreferNew,
+          provider_states: vec!["an order exists".to_string()],
+          tls: Some(TlsDefinition::SelfSigned(true)),
+          cors: Some(CorsDefinition {
+            allowed_origins: Some(vec!["http://localhost:3000".to_string()]),
+            allow_credentials: Some(true),
+            .. CorsDefinition::default()
+          }),
+          .. MockServerDefinition::default()
+        },
+        MockServerDefinition {
+          pact: PathBuf::from("pacts/users.json"),
+          tls: Some(TlsDefinition::Certificates {
+            cert: PathBuf::from("server.pem"),
+            key: PathBuf::from("server.key")
+          }),
+          .. MockServerDefinition::default()
+        }
+      ],
+      base_dir: base_dir.to_path_buf()
+    }
+  }
+
+  fn write_config(name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pact_mock_server_cli_config_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let mut file = fs::File::create(&path).unwrap();
+    file.write_all(contents.as_bytes()).unwrap();
+    path
+  }
+
+  #[test]
+  fn load_toml_config_file() {
+    let path = write_config("config.toml", TOML_CONFIG);
+    let config = ConfigFile::load(&path).unwrap();
+    expect!(config).to(be_equal_to(expected_config(path.parent().unwrap())));
+  }
+
+  #[test]
+  fn load_yaml_config_file() {
+    let path = write_config("config.yaml", YAML_CONFIG);
+    let config = ConfigFile::load(&path).unwrap();
+    expect!(config).to(be_equal_to(expected_config(path.parent().unwrap())));
+  }
+
+  #[test]
+  fn load_config_file_with_unknown_fields() {
+    let path = write_config("invalid.toml", "[[mock_servers]]\npact = \"pact.json\"\nunknown = 1\n");
+    expect!(ConfigFile::load(&path)).to(be_err());
+  }
+
+  #[test]
+  fn mock_server_config_test() {
+    let definition = &expected_config(Path::new(".")).mock_servers[0];
+    let config = definition.mock_server_config(0, PactSpecification::V4);
+    expect!(config.address).to(be_equal_to("127.0.0.1:9001"));
+    expect!(config.additional_addresses).to(be_equal_to(vec!["[::1]:0".to_string()]));
+    expect!(config.response_delay).to(be_some().value(Duration::from_millis(100)));
+    expect!(config.strict_spec_validation).to(be_true());
+    expect!(config.annotate_match_counts).to(be_true());
+    expect!(config.write_exercised_only).to(be_false());
+    expect!(config.merge_policy).to(be_equal_to(MergePolicy::PreferNew));
+    expect!(config.cors_policy.allow_credentials).to(be_true());
+    expect!(config.cors_policy.allowed_methods).to(be_equal_to(CorsPolicy::default().allowed_methods));
+
+    let definition = &expected_config(Path::new(".")).mock_servers[1];
+    let config = definition.mock_server_config(9100, PactSpecification::V3);
+    expect!(config.address).to(be_equal_to("127.0.0.1:9100"));
+    expect!(config.cors_policy).to(be_equal_to(CorsPolicy::default()));
+  }
+}
@@ -1,87 +1,402 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::panic::RefUnwindSafe;
+use std::path::{Path, PathBuf};
 
+use anyhow::{anyhow, Context};
 use clap::ArgMatches;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::Table;
 use itertools::Itertools;
-use pact_models::pact::{Pact, ReadWritePact};
+use pact_models::pact::{read_pact, Pact, ReadWritePact};
 use pact_models::sync_pact::RequestResponsePact;
-use serde_json::Value;
-use tracing::{debug, error, info};
+use serde_json::{json, Value};
+use tracing::{debug, error, info, warn};
 
-use crate::handle_error;
+/// Details of a mock server started by the master server
+#[derive(Debug, Clone, PartialEq)]
+struct CreatedMockServer {
+  id: String,
+  port: u64,
+  url: String,
+  provider: String,
+  files: Vec<String>
+}
+
+impl CreatedMockServer {
+  fn to_json(&self) -> Value {
+    json!({
+      "id": self.id,
+      "port": self.port,
+      "url": self.url,
+      "provider": self.provider,
+      "files": self.files
+    })
+  }
+}
+
+/// Pact to start a mock server with, along with the files it was loaded from
+#[derive(Debug)]
+pub(crate) struct PactSource {
+  pub files: Vec<PathBuf>,
+  pub pact: Box<dyn Pact + Send + Sync + RefUnwindSafe>
+}
 
 pub async fn create_mock_server(host: &str, port: u16, matches: &ArgMatches, usage: &str) -> Result<(), i32> {
-  let file = matches.get_one::<String>("file").unwrap();
+  let args = mock_server_args(matches);
+  match matches.get_one::<String>("file") {
+    Some(file) => create_mock_server_from_file(host, port, file, args, matches, usage).await,
+    None => create_mock_servers_from_files(host, port, args, matches, usage).await
+  }
+}
+
+async fn create_mock_server_from_file(
+  host: &str,
+  port: u16,
+  file: &str,
+  mut args: Vec<String>,
+  matches: &ArgMatches,
+  usage: &str
+) -> Result<(), i32> {
   info!("Creating mock server from file {}", file);
 
   match RequestResponsePact::read_pact(Path::new(file)) {
     Ok(ref pact) => {
-      let mut args = Vec::<String>::new();
-      if matches.get_flag("cors") {
-        info!("Setting mock server to handle CORS pre-flight requests");
-        args.push("cors=true".to_string());
-      }
-      if let Some(specification) = matches.get_one::<String>("specification") {
-        info!("Setting mock server to use pact specification {}", specification);
-        let spec_arg = format!("specification={}", specification);
-        args.push(spec_arg);
-      }
-      if matches.get_flag("tls") {
-        info!("Setting mock server to use TLS");
-        args.push("tls=true".to_string());
+      if let Some(base_port) = matches.get_one::<u16>("base-port") {
+        args.push(format!("port={}", base_port));
       }
-      let url = if args.is_empty() {
-        format!("http://{}:{}/", host, port)
+      let mock_server = send_pact(host, port, pact, vec![file.to_string()], &args).await
+        .unwrap_or_else(|err| crate::display_error(err.message, usage, err.code));
+      if matches.get_flag("json") {
+        println!("{}", mock_servers_json(&[mock_server]));
       } else {
-        format!("http://{}:{}/?{}", host, port, args.iter().join("&"))
-      };
-      let client = reqwest::Client::new();
-      let json = match pact.to_json(pact.specification_version()) {
-        Ok(json) => json,
-        Err(err) => {
-          crate::display_error(format!("Failed to send pact as JSON '{}': {}", file, err), usage, 21);
-        }
-      };
-      let resp = client.post(url.as_str())
-        .json(&json)
-        .send().await;
-      match resp {
-        Ok(response) => {
-          let status_code = response.status();
-          let content_length = response.content_length();
-          if status_code.is_success() {
-            match response.json::<Value>().await {
-              Ok(json) => {
-                debug!("Got response from master server: {:?}", json);
-                let mock_server = json.get("mockServer")
-                  .ok_or_else(|| handle_error("Invalid JSON received from master server - no mockServer attribute"))?;
-                let id = mock_server.get("id")
-                  .ok_or_else(|| handle_error("Invalid JSON received from master server - mockServer has no id attribute"))?
-                  .as_str().ok_or_else(|| handle_error("Invalid JSON received from master server - mockServer id attribute is not a string"))?;
-                let port = mock_server.get("port")
-                  .ok_or_else(|| handle_error("Invalid JSON received from master server - mockServer has no port attribute"))?
-                  .as_u64().ok_or_else(|| handle_error("Invalid JSON received from master server - mockServer port attribute is not a number"))?;
-                println!("Mock server {} started on port {}", id, port);
-                Ok(())
-              },
-              Err(err) => {
-                error!("Failed to parse JSON: {}", err);
-                error!("Response:    {}", status_code);
-                error!("Body length: {:?}", content_length);
-                crate::display_error(format!("Failed to parse JSON: {}", err), usage, 20);
-              }
-            }
-          } else {
-            crate::display_error(format!("Master mock server returned an error: {}\n{}",
-                                         status_code, response.text().await.unwrap_or_default()), usage, 20);
-          }
-        }
-        Err(err) => {
-            crate::display_error(format!("Failed to connect to the master mock server '{}': {}", url, err), usage, 20);
-        }
+        println!("Mock server {} started on port {}", mock_server.id, mock_server.port);
       }
+      Ok(())
     },
     Err(err) => {
       crate::display_error(format!("Failed to load pact file '{}': {}", file, err), usage, 20);
     }
   }
 }
+
+/// Starts a mock server for each of the pact files from the `dir` or `glob` arguments. If
+/// `merge` is set, the pacts for the same provider will be merged and served by one mock server.
+async fn create_mock_servers_from_files(
+  host: &str,
+  port: u16,
+  args: Vec<String>,
+  matches: &ArgMatches,
+  usage: &str
+) -> Result<(), i32> {
+  let files = match pact_files(matches) {
+    Ok(files) if files.is_empty() => crate::display_error("No pact files were found".to_string(), usage, 20),
+    Ok(files) => files,
+    Err(err) => crate::display_error(format!("Failed to find the pact files: {}", err), usage, 20)
+  };
+  debug!("Found pact files {:?}", files);
+
+  let mut sources = vec![];
+  for file in files {
+    info!("Loading pact from file {}", file.display());
+    match read_pact(&file) {
+      Ok(pact) => sources.push(PactSource { files: vec![file], pact }),
+      Err(err) => crate::display_error(format!("Failed to load pact file '{}': {}", file.display(), err), usage, 20)
+    }
+  }
+  if matches.get_flag("merge") {
+    sources = match merge_by_provider(sources) {
+      Ok(sources) => sources,
+      Err(err) => crate::display_error(format!("Failed to merge the pact files: {}", err), usage, 20)
+    };
+  }
+
+  let base_port = matches.get_one::<u16>("base-port").cloned();
+  let mut mock_servers = vec![];
+  for (index, source) in sources.iter().enumerate() {
+    let result = async {
+      let mut args = args.clone();
+      if let Some(base_port) = base_port {
+        let port = mock_server_port(base_port, index)
+          .ok_or_else(|| CreateError::new(format!("Ran out of ports to assign from the base port {}", base_port), 20))?;
+        args.push(format!("port={}", port));
+      }
+      let files = source.files.iter().map(|file| file.display().to_string()).collect_vec();
+      info!("Creating mock server for provider '{}' from {}", source.pact.provider().name, files.join(", "));
+      send_pact(host, port, source.pact.as_ref(), files, &args).await
+    }.await;
+    match result {
+      Ok(mock_server) => mock_servers.push(mock_server),
+      Err(err) => {
+        shutdown_created_mock_servers(host, port, &mock_servers).await;
+        crate::display_error(err.message, usage, err.code);
+      }
+    }
+  }
+
+  if matches.get_flag("json") {
+    println!("{}", mock_servers_json(&mock_servers));
+  } else {
+    println!("{}", mock_servers_table(&mock_servers));
+  }
+  Ok(())
+}
+
+/// Query parameters to send to the master server to configure the mock servers
+fn mock_server_args(matches: &ArgMatches) -> Vec<String> {
+  let mut args = Vec::<String>::new();
+  if matches.get_flag("cors") {
+    info!("Setting mock server to handle CORS pre-flight requests");
+    args.push("cors=true".to_string());
+  }
+  if let Some(specification) = matches.get_one::<String>("specification") {
+    info!("Setting mock server to use pact specification {}", specification);
+    let spec_arg = format!("specification={}", specification);
+    args.push(spec_arg);
+  }
+  if matches.get_flag("strict-spec-validation") {
+    info!("Setting mock server to fail writing the pact file if it can not be represented in the specification version");
+    args.push("strictSpecValidation=true".to_string());
+  }
+  if matches.get_flag("exercised-only") {
+    info!("Setting mock server to only write the interactions that received a matching request");
+    args.push("writeExercisedOnly=true".to_string());
+  }
+  if matches.get_flag("match-counts") {
+    info!("Setting mock server to add the match counts to the interactions in the pact file");
+    args.push("annotateMatchCounts=true".to_string());
+  }
+  if let Some(merge_policy) = matches.get_one::<String>("merge-policy") {
+    info!("Setting mock server to use the merge policy {} for existing pact files", merge_policy);
+    args.push(format!("mergePolicy={}", merge_policy));
+  }
+  if let Some(addresses) = matches.get_many::<IpAddr>("bind") {
+    for address in addresses {
+      info!("Setting mock server to bind to {}", address);
+      args.push(format!("bind={}", address));
+    }
+  }
+  if let Some(metrics_path) = matches.get_one::<String>("metrics-path") {
+    info!("Setting mock server to serve metrics from {}", metrics_path);
+    args.push(format!("metricsPath={}", metrics_path));
+  }
+  if let Some(admin_path) = matches.get_one::<String>("admin-path") {
+    info!("Setting mock server to serve the admin API from {}", admin_path);
+    args.push(format!("adminPath={}", admin_path));
+  }
+  if matches.get_flag("tls") {
+    info!("Setting mock server to use TLS");
+    args.push("tls=true".to_string());
+  }
+  args
+}
+
+/// Error from the master server when creating a mock server, with the exit code to use
+#[derive(Debug, Clone, PartialEq)]
+struct CreateError {
+  message: String,
+  code: i32
+}
+
+impl CreateError {
+  fn new<S: Into<String>>(message: S, code: i32) -> Self {
+    CreateError { message: message.into(), code }
+  }
+}
+
+/// Sends the pact to the master server to start a mock server with
+async fn send_pact(
+  host: &str,
+  port: u16,
+  pact: &dyn Pact,
+  files: Vec<String>,
+  args: &[String]
+) -> Result<CreatedMockServer, CreateError> {
+  let url = if args.is_empty() {
+    format!("http://{}:{}/", host, port)
+  } else {
+    format!("http://{}:{}/?{}", host, port, args.iter().join("&"))
+  };
+  let client = reqwest::Client::new();
+  let json = pact.to_json(pact.specification_version())
+    .map_err(|err| CreateError::new(format!("Failed to send pact as JSON '{}': {}", files.join(", "), err), 21))?;
+  let resp = client.post(url.as_str())
+    .json(&json)
+    .send().await;
+  match resp {
+    Ok(response) => {
+      let status_code = response.status();
+      let content_length = response.content_length();
+      if status_code.is_success() {
+        match response.json::<Value>().await {
+          Ok(json) => {
+            debug!("Got response from master server: {:?}", json);
+            let invalid_json = |message: &str| CreateError::new(format!("Invalid JSON received from master server - {}", message), -100);
+            let mock_server = json.get("mockServer")
+              .ok_or_else(|| invalid_json("no mockServer attribute"))?;
+            let id = mock_server.get("id")
+              .ok_or_else(|| invalid_json("mockServer has no id attribute"))?
+              .as_str().ok_or_else(|| invalid_json("mockServer id attribute is not a string"))?;
+            let port = mock_server.get("port")
+              .ok_or_else(|| invalid_json("mockServer has no port attribute"))?
+              .as_u64().ok_or_else(|| invalid_json("mockServer port attribute is not a number"))?;
+            let url = mock_server.get("url")
+              .and_then(|url| url.as_str())
+              .map(|url| url.to_string())
+              .unwrap_or_else(|| format!("http://{}:{}", host, port));
+            Ok(CreatedMockServer {
+              id: id.to_string(),
+              port,
+              url,
+              provider: pact.provider().name,
+              files
+            })
+          },
+          Err(err) => {
+            error!("Failed to parse JSON: {}", err);
+            error!("Response:    {}", status_code);
+            error!("Body length: {:?}", content_length);
+            Err(CreateError::new(format!("Failed to parse JSON: {}", err), 20))
+          }
+        }
+      } else {
+        Err(CreateError::new(format!("Master mock server returned an error: {}\n{}",
+          status_code, response.text().await.unwrap_or_default()), 20))
+      }
+    }
+    Err(err) => Err(CreateError::new(format!("Failed to connect to the master mock server '{}': {}", url, err), 20))
+  }
+}
+
+/// Shuts down mock servers that have already been created with the master server, when creating
+/// a later one has failed
+async fn shutdown_created_mock_servers(host: &str, port: u16, mock_servers: &[CreatedMockServer]) {
+  let client = reqwest::Client::new();
+  for mock_server in mock_servers {
+    let url = format!("http://{}:{}/mockserver/{}", host, port, mock_server.id);
+    match client.delete(&url).send().await {
+      Ok(response) if response.status().is_success() => info!("Shut down mock server {}", mock_server.id),
+      Ok(response) => warn!("Failed to shut down mock server {}: {}", mock_server.id, response.status()),
+      Err(err) => warn!("Failed to shut down mock server {}: {}", mock_server.id, err)
+    }
+  }
+}
+
+/// Returns the pact files from either the `dir` or `glob` arguments, sorted by path. For a
+/// directory, all the JSON files in it are used.
+pub(crate) fn pact_files(matches: &ArgMatches) -> anyhow::Result<Vec<PathBuf>> {
+  let mut files = if let Some(dir) = matches.get_one::<String>("dir") {
+    fs::read_dir(dir)
+      .with_context(|| format!("Could not read the directory '{}'", dir))?
+      .map_ok(|entry| entry.path())
+      .filter_ok(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
+      .collect::<Result<Vec<_>, _>>()?
+  } else if let Some(pattern) = matches.get_one::<String>("glob") {
+    glob::glob(pattern)
+      .with_context(|| format!("'{}' is not a valid glob pattern", pattern))?
+      .filter_ok(|path| path.is_file())
+      .collect::<Result<Vec<_>, _>>()?
+  } else {
+    return Err(anyhow!("Either a directory or glob pattern must be provided"));
+  };
+  files.sort();
+  Ok(files)
+}
+
+/// Merges the pacts for the same provider, returning them sorted by provider name
+pub(crate) fn merge_by_provider(sources: Vec<PactSource>) -> anyhow::Result<Vec<PactSource>> {
+  let mut merged: BTreeMap<String, PactSource> = BTreeMap::new();
+  for source in sources {
+    let provider = source.pact.provider().name;
+    match merged.remove(&provider) {
+      Some(mut existing) => {
+        existing.pact = existing.pact.merge(source.pact.as_ref())
+          .with_context(|| format!("Could not merge the pacts for provider '{}'", provider))?;
+        existing.files.extend(source.files);
+        merged.insert(provider, existing);
+      }
+      None => {
+        merged.insert(provider, source);
+      }
+    }
+  }
+  Ok(merged.into_values().collect())
+}
+
+/// Port for the mock server at the given index, counting up from the base port
+pub(crate) fn mock_server_port(base_port: u16, index: usize) -> Option<u16> {
+  u16::try_from(index).ok().and_then(|index| base_port.checked_add(index))
+}
+
+fn mock_servers_json(mock_servers: &[CreatedMockServer]) -> String {
+  let json = json!({
+    "mockServers": mock_servers.iter().map(|mock_server| mock_server.to_json()).collect::<Vec<_>>()
+  });
+  serde_json::to_string_pretty(&json).unwrap_or_default()
+}
+
+fn mock_servers_table(mock_servers: &[CreatedMockServer]) -> Table {
+  let mut table = Table::new();
+  table
+    .load_preset(UTF8_FULL)
+    .set_header(vec!["Provider", "Mock Server", "URL", "Pact Files"]);
+  for mock_server in mock_servers {
+    table.add_row(vec![
+      mock_server.provider.clone(),
+      mock_server.id.clone(),
+      mock_server.url.clone(),
+      mock_server.files.join("\n")
+    ]);
+  }
+  table
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use pact_models::prelude::{Consumer, Provider};
+
+  use super::*;
+
+  fn pact_source(file: &str, consumer: &str, provider: &str) -> PactSource {
+    PactSource {
+      files: vec![PathBuf::from(file)],
+      pact: RequestResponsePact {
+        consumer: Consumer { name: consumer.to_string() },
+        provider: Provider { name: provider.to_string() },
+        .. RequestResponsePact::default()
+      }.boxed()
+    }
+  }
+
+  #[test]
+  fn merge_by_provider_test() {
+    let sources = vec![
+      pact_source("c-b.json", "c", "b"),
+      pact_source("c-a.json", "c", "a"),
+      pact_source("c-b-2.json", "c", "b")
+    ];
+    let merged = merge_by_provider(sources).unwrap();
+
+    expect!(merged.iter().map(|source| source.pact.provider().name).collect::<Vec<_>>())
+      .to(be_equal_to(vec!["a".to_string(), "b".to_string()]));
+    expect!(merged[1].files.clone()).to(be_equal_to(vec![PathBuf::from("c-b.json"), PathBuf::from("c-b-2.json")]));
+  }
+
+  #[test]
+  fn merge_by_provider_fails_for_different_consumers() {
+    let sources = vec![
+      pact_source("c-b.json", "c", "b"),
+      pact_source("d-b.json", "d", "b")
+    ];
+    expect!(merge_by_provider(sources)).to(be_err());
+  }
+
+  #[test]
+  fn mock_server_port_test() {
+    expect!(mock_server_port(9000, 0)).to(be_some().value(9000));
+    expect!(mock_server_port(9000, 2)).to(be_some().value(9002));
+    expect!(mock_server_port(65535, 1)).to(be_none());
+  }
+}
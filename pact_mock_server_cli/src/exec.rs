@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use clap::ArgMatches;
+use itertools::Itertools;
+use pact_models::pact::read_pact;
+use serde_json::json;
+use tokio::process::{Child, Command};
+use tracing::{debug, info, warn};
+
+use pact_mock_server::builder::MockServerBuilder;
+use pact_mock_server::mock_server::MockServer;
+
+use crate::create_mock::{merge_by_provider, mock_server_port, pact_files, PactSource};
+use crate::display_error;
+use crate::serve::{mock_server_config, start_mock_server, SHUTDOWN_TIMEOUT};
+use crate::verify;
+
+/// Mock server started for a provider, along with the environment variable its URL is exported as
+struct ProviderMockServer {
+  provider: String,
+  env_var: String,
+  mock_server: MockServer
+}
+
+/// Starts a mock server for each of the pacts, and then runs the command with the URLs of the mock
+/// servers exported as `PACT_MOCK_<PROVIDER>_URL` environment variables. Once the command exits,
+/// the mock servers are verified and shut down, and the pact files written if the command was
+/// successful and all the requests matched.
+pub async fn exec_command(matches: &ArgMatches, usage: &str) -> Result<(), i32> {
+  let command = matches.get_many::<String>("command")
+    .map(|values| values.cloned().collect_vec())
+    .unwrap_or_default();
+  let sources = match load_pacts(matches) {
+    Ok(sources) if sources.is_empty() => display_error("No pact files were found".to_string(), usage, 80),
+    Ok(sources) => sources,
+    Err(err) => display_error(format!("{:#}", err), usage, 80)
+  };
+
+  let mut mock_servers = match start_mock_servers(sources, matches).await {
+    Ok(mock_servers) => mock_servers,
+    Err(err) => display_error(format!("Failed to start the mock servers: {:#}", err), usage, 80)
+  };
+  for mock_server in &mock_servers {
+    println!("Mock server {} for provider '{}' started on {} ({})", mock_server.mock_server.id,
+      mock_server.provider, mock_server.mock_server.url(), mock_server.env_var);
+  }
+
+  let status = run_command(&command, &mock_servers).await;
+  let command_result = match status {
+    Ok(status) if status.success() => Ok(()),
+    Ok(status) => {
+      eprintln!("ERROR: Command '{}' failed with {}", command.join(" "), status);
+      Err(status.code().unwrap_or(1))
+    }
+    Err(err) => {
+      eprintln!("ERROR: Failed to run the command '{}': {}", command.join(" "), err);
+      Err(80)
+    }
+  };
+
+  let output_path = matches.get_one::<String>("output").cloned();
+  let mut result = Ok(());
+  for provider_mock_server in &mut mock_servers {
+    let mock_server = &mut provider_mock_server.mock_server;
+    if let Err(err) = mock_server.shutdown_async(SHUTDOWN_TIMEOUT).await {
+      warn!("Failed to shutdown mock server {} - {}", mock_server.id, err);
+    }
+
+    verify::display_skipped_interactions(mock_server);
+    let mismatches = mock_server.mismatches();
+    if !mismatches.is_empty() {
+      let json = json!({
+        "mismatches": mismatches.iter().map(|mismatch| mismatch.to_json()).collect::<Vec<_>>()
+      });
+      verify::display_verification_errors(&mock_server.id, mock_server.port() as u64, &json);
+      result = Err(2);
+    } else if command_result.is_ok() {
+      // Writing the pact file can block while waiting for the lock on the file
+      if let Err(err) = tokio::task::block_in_place(|| mock_server.write_pact(&output_path, false)) {
+        eprintln!("ERROR: Failed to write the pact file for provider '{}': {}", provider_mock_server.provider, err);
+        result = result.and(Err(80));
+      }
+    }
+  }
+
+  command_result.and(result)
+}
+
+/// Loads the pacts from the file, directory and glob arguments, merging the pacts for the same
+/// provider if required
+fn load_pacts(matches: &ArgMatches) -> anyhow::Result<Vec<PactSource>> {
+  let mut files = matches.get_many::<String>("file")
+    .map(|files| files.map(PathBuf::from).collect_vec())
+    .unwrap_or_default();
+  if matches.contains_id("dir") || matches.contains_id("glob") {
+    files.extend(pact_files(matches)?);
+  }
+  debug!("Pact files = {:?}", files);
+
+  let mut sources = vec![];
+  for file in files {
+    info!("Loading pact from file {}", file.display());
+    let pact = read_pact(&file)
+      .map_err(|err| anyhow::anyhow!("Failed to load pact file '{}': {}", file.display(), err))?;
+    sources.push(PactSource { files: vec![file], pact });
+  }
+
+  if matches.get_flag("merge") {
+    merge_by_provider(sources)
+  } else {
+    Ok(sources)
+  }
+}
+
+/// Starts the mock servers for the pacts. If one fails to start, the mock servers already started
+/// are shut down.
+async fn start_mock_servers(sources: Vec<PactSource>, matches: &ArgMatches) -> anyhow::Result<Vec<ProviderMockServer>> {
+  let base_port = matches.get_one::<u16>("base-port").cloned();
+  let mut env_vars = HashSet::new();
+  let mut mock_servers: Vec<ProviderMockServer> = vec![];
+  for (index, source) in sources.into_iter().enumerate() {
+    let result = match base_port.map(|base_port| mock_server_port(base_port, index)) {
+      Some(None) => Err(anyhow::anyhow!("Ran out of ports to assign from the base port")),
+      Some(Some(port)) => start_provider_mock_server(source, port, matches).await,
+      None => start_provider_mock_server(source, 0, matches).await
+    };
+    match result {
+      Ok(mock_server) => {
+        let provider = mock_server.pact.provider.name.clone();
+        let env_var = unique_env_var(&provider, &mut env_vars);
+        mock_servers.push(ProviderMockServer { provider, env_var, mock_server });
+      }
+      Err(err) => {
+        for mut started in mock_servers {
+          let _ = started.mock_server.shutdown();
+        }
+        return Err(err);
+      }
+    }
+  }
+  Ok(mock_servers)
+}
+
+async fn start_provider_mock_server(source: PactSource, port: u16, matches: &ArgMatches) -> anyhow::Result<MockServer> {
+  let config = mock_server_config(port, source.pact.as_ref(), matches);
+  debug!("Mock server config = {:?}", config);
+  let builder = MockServerBuilder::new()
+    .with_pact(source.pact)
+    .with_config(config);
+  start_mock_server(builder, matches).await
+}
+
+/// Runs the command with the URLs of the mock servers set in the environment, and waits for it to
+/// exit.
+async fn run_command(command: &[String], mock_servers: &[ProviderMockServer]) -> std::io::Result<ExitStatus> {
+  let (program, args) = command.split_first()
+    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "No command was provided"))?;
+  info!("Running command '{}'", command.join(" "));
+  let mut child = Command::new(program)
+    .args(args)
+    .envs(mock_servers.iter().map(|ms| (ms.env_var.clone(), ms.mock_server.url())))
+    .spawn()?;
+  wait_for_command(&mut child).await
+}
+
+/// Waits for the command to exit. Interrupt signals are passed on to the command by the terminal,
+/// so they are ignored here. Terminate signals (i.e. from `docker stop`) are only sent to this
+/// process, so they are forwarded to the command. In both cases, the mock servers are still
+/// verified once the command exits.
+#[cfg(unix)]
+async fn wait_for_command(child: &mut Child) -> std::io::Result<ExitStatus> {
+  use tokio::signal::unix::{signal, SignalKind};
+
+  let mut terminate = match signal(SignalKind::terminate()) {
+    Ok(terminate) => Some(terminate),
+    Err(err) => {
+      warn!("Could not listen for the SIGTERM signal - {}", err);
+      None
+    }
+  };
+  loop {
+    tokio::select! {
+      status = child.wait() => return status,
+      _ = tokio::signal::ctrl_c() => debug!("Received SIGINT, waiting for the command to exit"),
+      Some(_) = async { terminate.as_mut()?.recv().await } => {
+        debug!("Received SIGTERM, passing it on to the command and waiting for it to exit");
+        if let Some(pid) = child.id() {
+          // Safety: this only sends a signal to the child process, which has not been reaped yet
+          if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+            warn!("Failed to send SIGTERM to the command - {}", std::io::Error::last_os_error());
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_command(child: &mut Child) -> std::io::Result<ExitStatus> {
+  loop {
+    tokio::select! {
+      status = child.wait() => return status,
+      _ = tokio::signal::ctrl_c() => debug!("Received SIGINT, waiting for the command to exit")
+    }
+  }
+}
+
+/// Returns the environment variable name for the URL of the mock server for the provider. If the
+/// name has already been used (i.e. there is more than one pact for the provider), a number is
+/// added to make it unique.
+fn unique_env_var(provider: &str, used: &mut HashSet<String>) -> String {
+  let name = provider.split(|ch: char| !ch.is_ascii_alphanumeric())
+    .filter(|part| !part.is_empty())
+    .map(|part| part.to_ascii_uppercase())
+    .join("_");
+  let mut env_var = format!("PACT_MOCK_{}_URL", name);
+  let mut count = 1;
+  while used.contains(&env_var) {
+    count += 1;
+    env_var = format!("PACT_MOCK_{}_{}_URL", name, count);
+  }
+  if count > 1 {
+    warn!("There is more than one pact for provider '{}', using {} for the mock server URL (use --merge to merge them)",
+      provider, env_var);
+  }
+  used.insert(env_var.clone());
+  env_var
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+
+  use super::*;
+
+  #[test]
+  fn unique_env_var_test() {
+    let mut used = HashSet::new();
+    expect!(unique_env_var("Order Service", &mut used)).to(be_equal_to("PACT_MOCK_ORDER_SERVICE_URL"));
+    expect!(unique_env_var("order-service", &mut used)).to(be_equal_to("PACT_MOCK_ORDER_SERVICE_2_URL"));
+    expect!(unique_env_var("users", &mut used)).to(be_equal_to("PACT_MOCK_USERS_URL"));
+    expect!(unique_env_var("  Café API v2 ", &mut used)).to(be_equal_to("PACT_MOCK_CAF_API_V2_URL"));
+  }
+
+  #[test]
+  fn exec_command_args() {
+    let matches = crate::setup_args().get_matches_from([
+      "pact_mock_server_cli", "exec", "-f", "a.json", "-f", "b.json", "--", "npm", "test", "--", "-v"
+    ]);
+    let (_, exec_matches) = matches.subcommand().unwrap();
+    expect!(exec_matches.get_many::<String>("file").unwrap().cloned().collect_vec())
+      .to(be_equal_to(vec!["a.json".to_string(), "b.json".to_string()]));
+    expect!(exec_matches.get_many::<String>("command").unwrap().cloned().collect_vec())
+      .to(be_equal_to(vec!["npm".to_string(), "test".to_string(), "--".to_string(), "-v".to_string()]));
+  }
+}
@@ -0,0 +1,107 @@
+use clap::ArgMatches;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::Table;
+use http::StatusCode;
+use pact_models::json_utils::json_to_string;
+use serde_json::Value;
+use tracing::error;
+
+use crate::{display_error, handle_error};
+
+pub async fn list_interactions(host: &str, port: u16, matches: &ArgMatches, usage: &str) -> Result<(), i32> {
+  let mock_server_id = matches.get_one::<String>("mock-server-id");
+  let mock_server_port = matches.get_one::<u16>("mock-server-port");
+  let (id, id_type) = match (mock_server_id, mock_server_port) {
+    (Some(id), _) => (id.clone(), "id"),
+    (_, Some(port)) => (port.to_string(), "port"),
+    _ => display_error("Either an ID or port must be provided".to_string(), usage, 50)
+  };
+
+  let client = reqwest::Client::new();
+  let url = format!("http://{}:{}/mockserver/{}/interactions", host, port, id);
+  match client.get(&url).send().await {
+    Ok(result) => {
+      let status = result.status();
+      if status.is_success() {
+        match result.json::<Value>().await {
+          Ok(json) => {
+            let interactions = json.get("interactions")
+              .and_then(|interactions| interactions.as_array())
+              .ok_or_else(|| handle_error("Invalid JSON received from master server - interactions is not an array"))?;
+            println!("{}", interactions_table(interactions));
+            Ok(())
+          },
+          Err(err) => {
+            error!("Failed to parse JSON: {}\n", err);
+            display_error(format!("Failed to parse JSON: {}", err), usage, 50);
+          }
+        }
+      } else if status == StatusCode::NOT_FOUND {
+        println!("No mock server found with {} '{}', use the 'list' command to get a list of available mock servers.", id_type, id);
+        Err(3)
+      } else {
+        let body = result.text().await.unwrap_or_default();
+        display_error(format!("Master mock server returned an error: {}\n{}", status, body), usage, 50);
+      }
+    },
+    Err(err) => {
+      display_error(format!("Failed to connect to the master mock server '{}': {}", url, err), usage, 50);
+    }
+  }
+}
+
+fn interactions_table(interactions: &[Value]) -> Table {
+  let mut table = Table::new();
+  table
+    .load_preset(UTF8_FULL)
+    .set_header(vec!["Description", "Provider States", "Key", "Status", "Calls", "Mismatches"]);
+  for interaction in interactions {
+    let provider_states = interaction.get("providerStates")
+      .and_then(|states| states.as_array())
+      .map(|states| states.iter().map(json_to_string).collect::<Vec<_>>().join("\n"))
+      .unwrap_or_default();
+    table.add_row(vec![
+      field(interaction, "description"),
+      provider_states,
+      field(interaction, "key"),
+      field(interaction, "status"),
+      field(interaction, "calls"),
+      field(interaction, "mismatches")
+    ]);
+  }
+  table
+}
+
+fn field(json: &Value, name: &str) -> String {
+  json.get(name).map(json_to_string).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn interactions_table_test() {
+    let interactions = vec![
+      json!({
+        "description": "a request for an order",
+        "providerStates": ["an order exists", "the user is logged in"],
+        "key": "1234abcd",
+        "status": "matched",
+        "calls": 2,
+        "mismatches": 0
+      }),
+      json!({ "description": "a message", "status": "skipped" })
+    ];
+    let table = interactions_table(&interactions);
+
+    expect!(table.row_count()).to(be_equal_to(2));
+    let rendered = table.to_string();
+    expect!(rendered.contains("a request for an order")).to(be_true());
+    expect!(rendered.contains("the user is logged in")).to(be_true());
+    expect!(rendered.contains("skipped")).to(be_true());
+  }
+}
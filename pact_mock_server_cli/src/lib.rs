@@ -7,6 +7,8 @@
 
 use std::env;
 use std::io;
+use std::net::IpAddr;
+use std::path::Path;
 use std::process::ExitCode;
 use std::str::FromStr;
 use std::sync::Mutex;
@@ -23,8 +25,13 @@ use regex::Regex;
 use tracing_core::LevelFilter;
 use tracing_subscriber::FmtSubscriber;
 
+use pact_mock_server::logs::MockServerLogLayer;
 use pact_mock_server::server_manager::ServerManager;
 use tracing_subscriber::layer::SubscriberExt;
+#[cfg(not(feature = "otel"))] use tracing_subscriber::layer::Identity;
+#[cfg(feature = "otel")] use tracing_core::Subscriber;
+#[cfg(feature = "otel")] use tracing_subscriber::Layer;
+#[cfg(feature = "otel")] use tracing_subscriber::registry::LookupSpan;
 
 pub(crate) fn display_error(error: String, _usage: &str, code: i32) -> ! {
   eprintln!("ERROR: {}\nExiting with status {}", error, code);
@@ -38,10 +45,16 @@ pub(crate) fn handle_error(error: &str) -> i32 {
 }
 
 mod server;
+mod config;
 mod create_mock;
 mod list;
+mod interactions;
+mod watch;
+mod serve;
+mod exec;
 mod verify;
 mod shutdown;
+#[cfg(feature = "otel")] mod otel;
 
 pub fn print_version() {
     println!("pact mock server version  : v{}", clap::crate_version!());
@@ -53,7 +66,8 @@ fn setup_loggers(
   command: &str,
   output: Option<&str>,
   no_file_log: bool,
-  no_term_log: bool
+  no_term_log: bool,
+  matches: &ArgMatches
 ) -> anyhow::Result<()> {
   let log_level = match level {
     "none" => LevelFilter::OFF,
@@ -74,7 +88,9 @@ fn setup_loggers(
 
     let subscriber = 
     FmtSubscriber::builder().with_max_level(log_level).with_ansi(!no_term_log).finish()
-      .with(file_layer);
+      .with(file_layer)
+      .with(MockServerLogLayer::new());
+    let subscriber = subscriber.with(otel_layer(matches)?);
 
     tracing::subscriber::set_global_default(subscriber)
   } else {
@@ -82,11 +98,33 @@ fn setup_loggers(
       .with_max_level(log_level)
       .with_thread_names(true)
       .with_ansi(!no_term_log)
-      .finish();
+      .finish()
+      .with(MockServerLogLayer::new())
+      .with(otel_layer(matches)?);
     tracing::subscriber::set_global_default(subscriber)
   }.map_err(|err| anyhow!(err))
 }
 
+#[cfg(feature = "otel")]
+fn otel_layer<S>(matches: &ArgMatches) -> anyhow::Result<Option<impl Layer<S>>>
+  where S: Subscriber + for<'span> LookupSpan<'span> {
+  otlp_endpoint(matches).as_ref().map(otel::tracing_layer).transpose()
+}
+
+#[cfg(not(feature = "otel"))]
+fn otel_layer(_matches: &ArgMatches) -> anyhow::Result<Option<Identity>> {
+  Ok(None)
+}
+
+/// Returns the OTLP endpoint to export spans to when the master server is started
+#[cfg(feature = "otel")]
+fn otlp_endpoint(matches: &ArgMatches) -> Option<otel::OtlpEndpoint> {
+  match matches.subcommand() {
+    Some(("start", args)) => otel::otlp_endpoint(args.get_one::<String>("otlp-endpoint").map(|e| e.as_str())),
+    _ => None
+  }
+}
+
 fn integer_value(v: &str) -> Result<u16, String> {
   v.parse::<u16>().map_err(|e| format!("'{}' is not a valid port value: {}", v, e) )
 }
@@ -108,7 +146,8 @@ fn mock_server_id(v: &str) -> Result<String, String> {
 pub(crate) struct ServerOpts {
   pub output_path: Option<String>,
   pub base_port: Option<u16>,
-  pub server_key: String
+  pub server_key: String,
+  pub config: Option<config::ConfigFile>
 }
 
 lazy_static!{
@@ -167,7 +206,8 @@ async fn handle_matches(matches: &ArgMatches) -> Result<(), i32> {
           }
         }).flatten(),
         no_file_log,
-        no_term_log
+        no_term_log,
+        matches
       ) {
         eprintln!("WARN: Could not setup loggers: {}", err);
         eprintln!();
@@ -185,16 +225,28 @@ async fn handle_matches(matches: &ArgMatches) -> Result<(), i32> {
           let base_port = sub_matches.get_one::<u16>("base-port").cloned();
           let server_key = sub_matches.get_one::<String>("server-key").map(|s| s.to_owned())
             .unwrap_or_else(|| rand::thread_rng().sample_iter(Alphanumeric).take(16).map(char::from).collect::<String>());
+          let config = match sub_matches.get_one::<String>("config") {
+            Some(config_file) => match config::ConfigFile::load(Path::new(config_file)) {
+              Ok(config) => Some(config),
+              Err(err) => display_error(format!("{:#}", err), usage.as_str(), 5)
+            },
+            None => None
+          };
           let options = ServerOpts {
             output_path,
             base_port,
             server_key,
+            config
           };
           server::start_server(port, options).await
         },
         Some(("list", _)) => list::list_mock_servers(host, port, usage.as_str()).await,
         Some(("create", sub_matches)) => create_mock::create_mock_server(host, port, sub_matches, usage.as_str()).await,
         Some(("verify", sub_matches)) => verify::verify_mock_server(host, port, sub_matches, usage.as_str()).await,
+        Some(("interactions", sub_matches)) => interactions::list_interactions(host, port, sub_matches, usage.as_str()).await,
+        Some(("watch", sub_matches)) => watch::watch_mock_server(host, port, sub_matches, usage.as_str()).await,
+        Some(("serve", sub_matches)) => serve::serve_mock_server(matches.get_one::<u16>("port").cloned(), sub_matches, usage.as_str()).await,
+        Some(("exec", sub_matches)) => exec::exec_command(sub_matches, usage.as_str()).await,
         Some(("shutdown", sub_matches)) => shutdown::shutdown_mock_server(host, port, sub_matches, usage.as_str()).await,
         Some(("shutdown-master", sub_matches)) => shutdown::shutdown_master_server(host, port, sub_matches, usage.as_str()).await,
         _ => Err(3)
@@ -209,8 +261,33 @@ pub fn setup_args() -> Command {
       .short('f')
       .long("file")
       .action(ArgAction::Set)
-      .required(true)
+      .required_unless_present_any(["dir", "glob"])
+      .conflicts_with_all(["dir", "glob"])
       .help("the pact file to define the mock server"))
+    .arg(Arg::new("dir")
+      .short('d')
+      .long("dir")
+      .action(ArgAction::Set)
+      .conflicts_with("glob")
+      .help("a directory of pact files (*.json) to start a mock server for each one"))
+    .arg(Arg::new("glob")
+      .short('g')
+      .long("glob")
+      .action(ArgAction::Set)
+      .help("a glob pattern for pact files to start a mock server for each one (e.g. 'pacts/**/*.json')"))
+    .arg(Arg::new("merge")
+      .long("merge")
+      .action(ArgAction::SetTrue)
+      .help("Merge the pact files for the same provider, so there is one mock server per provider"))
+    .arg(Arg::new("base-port")
+      .long("base-port")
+      .action(ArgAction::Set)
+      .value_parser(integer_value)
+      .help("the port number to assign the mock server ports from, in order of the pact files (or provider names when merging). If not specified, the master server will assign the ports"))
+    .arg(Arg::new("json")
+      .long("json")
+      .action(ArgAction::SetTrue)
+      .help("Print the details of the mock servers that were started as JSON"))
     .arg(Arg::new("cors")
       .short('c')
       .long("cors-preflight")
@@ -220,7 +297,39 @@ pub fn setup_args() -> Command {
       .long("specification")
       .action(ArgAction::Set)
       .num_args(1)
-      .help("The Pact specification version to use (defaults to V4)"));
+      .help("The Pact specification version to use (defaults to V4)"))
+    .arg(Arg::new("strict-spec-validation")
+      .long("strict-spec-validation")
+      .action(ArgAction::SetTrue)
+      .help("Fail to write the pact file if it has features that can not be represented in the Pact specification version"))
+    .arg(Arg::new("exercised-only")
+      .long("exercised-only")
+      .action(ArgAction::SetTrue)
+      .help("Only write the interactions that received a matching request to the pact file"))
+    .arg(Arg::new("match-counts")
+      .long("match-counts")
+      .action(ArgAction::SetTrue)
+      .help("Add the number of matching requests to each interaction in the pact file (V4 only)"))
+    .arg(Arg::new("merge-policy")
+      .long("merge-policy")
+      .action(ArgAction::Set)
+      .value_parser(["fail", "prefer-new", "prefer-existing"])
+      .help("How to resolve conflicts with the interactions in an existing pact file (defaults to prefer-new)"))
+    .arg(Arg::new("bind")
+      .long("bind")
+      .action(ArgAction::Append)
+      .value_parser(clap::value_parser!(IpAddr))
+      .help("IP address for the mock server to bind to (defaults to 127.0.0.1). Can be repeated to bind to multiple addresses, which will all use the same port"))
+    .arg(Arg::new("metrics-path")
+      .long("metrics-path")
+      .action(ArgAction::Set)
+      .num_args(1)
+      .help("Reserved path on the mock server to serve its metrics from in OpenMetrics format (e.g. /__pact/metrics)"))
+    .arg(Arg::new("admin-path")
+      .long("admin-path")
+      .action(ArgAction::Set)
+      .num_args(1)
+      .help("Reserved path prefix on the mock server to serve its admin API from (e.g. /__pact)"));
 
   #[cfg(feature = "tls")]
   {
@@ -230,6 +339,196 @@ pub fn setup_args() -> Command {
      .help("Enable TLS with the mock server (will use a self-signed certificate)"));
   }
 
+  #[allow(unused_mut)]
+  let mut serve_command = Command::new("serve")
+    .about("Starts a single mock server from a pact file in the foreground, without a master server")
+    .version(clap::crate_version!())
+    .arg(Arg::new("file")
+      .short('f')
+      .long("file")
+      .action(ArgAction::Set)
+      .required(true)
+      .help("the pact file to define the mock server"))
+    .arg(Arg::new("output")
+      .short('o')
+      .long("output")
+      .action(ArgAction::Set)
+      .help("the directory where to write the pact file to (defaults to current directory)"))
+    .arg(Arg::new("cors")
+      .short('c')
+      .long("cors-preflight")
+      .action(ArgAction::SetTrue)
+      .help("Handle CORS pre-flight requests"))
+    .arg(Arg::new("specification")
+      .long("specification")
+      .action(ArgAction::Set)
+      .num_args(1)
+      .help("The Pact specification version to use (defaults to the version of the pact file)"))
+    .arg(Arg::new("strict-spec-validation")
+      .long("strict-spec-validation")
+      .action(ArgAction::SetTrue)
+      .help("Fail to write the pact file if it has features that can not be represented in the Pact specification version"))
+    .arg(Arg::new("exercised-only")
+      .long("exercised-only")
+      .action(ArgAction::SetTrue)
+      .help("Only write the interactions that received a matching request to the pact file"))
+    .arg(Arg::new("match-counts")
+      .long("match-counts")
+      .action(ArgAction::SetTrue)
+      .help("Add the number of matching requests to each interaction in the pact file (V4 only)"))
+    .arg(Arg::new("merge-policy")
+      .long("merge-policy")
+      .action(ArgAction::Set)
+      .value_parser(["fail", "prefer-new", "prefer-existing"])
+      .help("How to resolve conflicts with the interactions in an existing pact file (defaults to prefer-new)"))
+    .arg(Arg::new("bind")
+      .long("bind")
+      .action(ArgAction::Append)
+      .value_parser(clap::value_parser!(IpAddr))
+      .help("IP address for the mock server to bind to (defaults to 127.0.0.1). Can be repeated to bind to multiple addresses, which will all use the same port"))
+    .arg(Arg::new("metrics-path")
+      .long("metrics-path")
+      .action(ArgAction::Set)
+      .num_args(1)
+      .help("Reserved path on the mock server to serve its metrics from in OpenMetrics format (e.g. /__pact/metrics)"))
+    .arg(Arg::new("admin-path")
+      .long("admin-path")
+      .action(ArgAction::Set)
+      .num_args(1)
+      .help("Reserved path prefix on the mock server to serve its admin API from (e.g. /__pact)"));
+
+  #[cfg(feature = "tls")]
+  {
+    serve_command = serve_command.arg(Arg::new("tls")
+     .long("tls")
+     .action(ArgAction::SetTrue)
+     .help("Enable TLS with the mock server (will use a self-signed certificate)"));
+  }
+
+  #[allow(unused_mut)]
+  let mut exec_command = Command::new("exec")
+    .about("Runs a command with a mock server started for each pact file, and then verifies the mock servers and writes the pact files")
+    .version(clap::crate_version!())
+    .arg(Arg::new("file")
+      .short('f')
+      .long("file")
+      .action(ArgAction::Append)
+      .required_unless_present_any(["dir", "glob"])
+      .help("a pact file to start a mock server for. Can be repeated"))
+    .arg(Arg::new("dir")
+      .short('d')
+      .long("dir")
+      .action(ArgAction::Set)
+      .conflicts_with("glob")
+      .help("a directory of pact files (*.json) to start a mock server for each one"))
+    .arg(Arg::new("glob")
+      .short('g')
+      .long("glob")
+      .action(ArgAction::Set)
+      .help("a glob pattern for pact files to start a mock server for each one (e.g. 'pacts/**/*.json')"))
+    .arg(Arg::new("merge")
+      .long("merge")
+      .action(ArgAction::SetTrue)
+      .help("Merge the pact files for the same provider, so there is one mock server per provider"))
+    .arg(Arg::new("base-port")
+      .long("base-port")
+      .action(ArgAction::Set)
+      .value_parser(integer_value)
+      .help("the port number to assign the mock server ports from, in order of the pact files (or provider names when merging). If not specified, the ports will be assigned by the OS"))
+    .arg(Arg::new("output")
+      .short('o')
+      .long("output")
+      .action(ArgAction::Set)
+      .help("the directory where to write the pact files to (defaults to current directory)"))
+    .arg(Arg::new("cors")
+      .short('c')
+      .long("cors-preflight")
+      .action(ArgAction::SetTrue)
+      .help("Handle CORS pre-flight requests"))
+    .arg(Arg::new("specification")
+      .long("specification")
+      .action(ArgAction::Set)
+      .num_args(1)
+      .help("The Pact specification version to use (defaults to the version of the pact files)"))
+    .arg(Arg::new("strict-spec-validation")
+      .long("strict-spec-validation")
+      .action(ArgAction::SetTrue)
+      .help("Fail to write the pact files if they have features that can not be represented in the Pact specification version"))
+    .arg(Arg::new("exercised-only")
+      .long("exercised-only")
+      .action(ArgAction::SetTrue)
+      .help("Only write the interactions that received a matching request to the pact files"))
+    .arg(Arg::new("match-counts")
+      .long("match-counts")
+      .action(ArgAction::SetTrue)
+      .help("Add the number of matching requests to each interaction in the pact files (V4 only)"))
+    .arg(Arg::new("merge-policy")
+      .long("merge-policy")
+      .action(ArgAction::Set)
+      .value_parser(["fail", "prefer-new", "prefer-existing"])
+      .help("How to resolve conflicts with the interactions in existing pact files (defaults to prefer-new)"))
+    .arg(Arg::new("bind")
+      .long("bind")
+      .action(ArgAction::Append)
+      .value_parser(clap::value_parser!(IpAddr))
+      .help("IP address for the mock servers to bind to (defaults to 127.0.0.1). Can be repeated to bind to multiple addresses, which will all use the same port"))
+    .arg(Arg::new("metrics-path")
+      .long("metrics-path")
+      .action(ArgAction::Set)
+      .num_args(1)
+      .help("Reserved path on the mock servers to serve their metrics from in OpenMetrics format (e.g. /__pact/metrics)"))
+    .arg(Arg::new("admin-path")
+      .long("admin-path")
+      .action(ArgAction::Set)
+      .num_args(1)
+      .help("Reserved path prefix on the mock servers to serve their admin API from (e.g. /__pact)"))
+    .arg(Arg::new("command")
+      .action(ArgAction::Set)
+      .num_args(1..)
+      .last(true)
+      .required(true)
+      .help("the command to run, and its arguments"));
+
+  #[cfg(feature = "tls")]
+  {
+    exec_command = exec_command.arg(Arg::new("tls")
+     .long("tls")
+     .action(ArgAction::SetTrue)
+     .help("Enable TLS with the mock servers (will use a self-signed certificate)"));
+  }
+
+  #[allow(unused_mut)]
+  let mut start_command = Command::new("start")
+    .about("Starts the master mock server")
+    .version(clap::crate_version!())
+    .arg(Arg::new("output")
+      .short('o')
+      .long("output")
+      .action(ArgAction::Set)
+      .help("the directory where to write files to (defaults to current directory)"))
+    .arg(Arg::new("base-port")
+      .long("base-port")
+      .action(ArgAction::Set)
+      .help("the base port number that mock server ports will be allocated from. If not specified, ports will be randomly assigned by the OS.")
+      .value_parser(integer_value))
+    .arg(Arg::new("server-key")
+      .long("server-key")
+      .action(ArgAction::Set)
+      .help("the server key to use to authenticate shutdown requests (defaults to a random generated one)"))
+    .arg(Arg::new("config")
+      .short('c')
+      .long("config")
+      .action(ArgAction::Set)
+      .help("a config file (TOML or YAML) with the mock servers to start when the master server starts"));
+
+  #[cfg(feature = "otel")]
+  {
+    start_command = start_command.arg(Arg::new("otlp-endpoint")
+      .long("otlp-endpoint")
+      .action(ArgAction::Set)
+      .help("OTLP/HTTP endpoint of an OpenTelemetry collector to export the mock server request spans to (i.e. http://localhost:4318). Defaults to the OTEL_EXPORTER_OTLP_ENDPOINT environment variable, if set"));
+  }
+
   command!()
     .about("Standalone Pact mock server")
     .disable_help_flag(true)
@@ -276,28 +575,13 @@ pub fn setup_args() -> Command {
       .global(true)
       .action(ArgAction::SetTrue)
       .help("Do not log to an output file"))
-    .subcommand(Command::new("start")
-      .about("Starts the master mock server")
-      .version(clap::crate_version!())
-      .arg(Arg::new("output")
-        .short('o')
-        .long("output")
-        .action(ArgAction::Set)
-        .help("the directory where to write files to (defaults to current directory)"))
-      .arg(Arg::new("base-port")
-        .long("base-port")
-        .action(ArgAction::Set)
-        .help("the base port number that mock server ports will be allocated from. If not specified, ports will be randomly assigned by the OS.")
-        .value_parser(integer_value))
-      .arg(Arg::new("server-key")
-        .long("server-key")
-        .action(ArgAction::Set)
-        .help("the server key to use to authenticate shutdown requests (defaults to a random generated one)"))
-      )
+    .subcommand(start_command)
     .subcommand(Command::new("list")
       .about("Lists all the running mock servers")
       .version(clap::crate_version!()))
     .subcommand(create_command)
+    .subcommand(serve_command)
+    .subcommand(exec_command)
     .subcommand(Command::new("verify")
       .about("Verify the mock server by id or port number, and generate a pact file if all ok")
       .version(clap::crate_version!())
@@ -316,6 +600,88 @@ pub fn setup_args() -> Command {
         .required_unless_present("mock-server-id")
         .help("the port number of the mock server")
         .value_parser(integer_value))
+      .arg(Arg::new("broker-url")
+        .long("broker-url")
+        .action(ArgAction::Set)
+        .env("PACT_BROKER_BASE_URL")
+        .hide_env_values(true)
+        .help("URL of a Pact Broker to publish the pact to once the mock server has been verified. The pact is only published if a consumer version is provided"))
+      .arg(Arg::new("consumer-version")
+        .long("consumer-version")
+        .action(ArgAction::Set)
+        .requires("broker-url")
+        .help("the consumer version to publish the pact for. The pact is published to the Pact Broker if this is provided"))
+      .arg(Arg::new("branch")
+        .long("branch")
+        .action(ArgAction::Set)
+        .requires("consumer-version")
+        .help("the branch of the consumer version to publish the pact for"))
+      .arg(Arg::new("tag")
+        .long("tag")
+        .action(ArgAction::Append)
+        .requires("consumer-version")
+        .help("tag to apply to the consumer version. Can be repeated"))
+      .arg(Arg::new("broker-token")
+        .long("broker-token")
+        .action(ArgAction::Set)
+        .env("PACT_BROKER_TOKEN")
+        .hide_env_values(true)
+        .help("bearer token to use with the Pact Broker. Takes precedence over the username and password"))
+      .arg(Arg::new("broker-username")
+        .long("broker-username")
+        .action(ArgAction::Set)
+        .env("PACT_BROKER_USERNAME")
+        .hide_env_values(true)
+        .help("username to use with the Pact Broker"))
+      .arg(Arg::new("broker-password")
+        .long("broker-password")
+        .action(ArgAction::Set)
+        .env("PACT_BROKER_PASSWORD")
+        .hide_env_values(true)
+        .help("password to use with the Pact Broker"))
+      )
+    .subcommand(Command::new("interactions")
+      .about("Lists the interactions of the mock server by id or port number, with the match status of each one")
+      .version(clap::crate_version!())
+      .arg(Arg::new("mock-server-id")
+        .short('i')
+        .long("mock-server-id")
+        .action(ArgAction::Set)
+        .required_unless_present("mock-server-port")
+        .conflicts_with("mock-server-port")
+        .help("the ID of the mock server")
+        .value_parser(mock_server_id))
+      .arg(Arg::new("mock-server-port")
+        .short('m')
+        .long("mock-server-port")
+        .action(ArgAction::Set)
+        .required_unless_present("mock-server-id")
+        .help("the port number of the mock server")
+        .value_parser(integer_value))
+      )
+    .subcommand(Command::new("watch")
+      .about("Streams the requests received by the mock server by id or port number, with their match results")
+      .version(clap::crate_version!())
+      .arg(Arg::new("mock-server-id")
+        .short('i')
+        .long("mock-server-id")
+        .action(ArgAction::Set)
+        .required_unless_present("mock-server-port")
+        .conflicts_with("mock-server-port")
+        .help("the ID of the mock server")
+        .value_parser(mock_server_id))
+      .arg(Arg::new("mock-server-port")
+        .short('m')
+        .long("mock-server-port")
+        .action(ArgAction::Set)
+        .required_unless_present("mock-server-id")
+        .help("the port number of the mock server")
+        .value_parser(integer_value))
+      .arg(Arg::new("mismatches")
+        .short('d')
+        .long("mismatches")
+        .action(ArgAction::SetTrue)
+        .help("Display the details of any mismatches for each request"))
       )
     .subcommand(Command::new("shutdown")
       .about("Shutdown the mock server by id or port number, releasing all its resources")
@@ -0,0 +1,97 @@
+//! Exporting of the mock server request spans to an OpenTelemetry collector with OTLP
+
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+use opentelemetry::global;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::{debug, warn};
+use tracing_core::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Environment variable that can be used to set the OTLP endpoint
+const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// OTLP endpoint to export spans to
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum OtlpEndpoint {
+  /// Endpoint URL that was provided
+  Url(String),
+  /// The exporter resolves the endpoint from the `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable
+  FromEnvironment
+}
+
+/// Returns the OTLP endpoint to export spans to, either the one provided or from the
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable. If the provided endpoint has no path, the
+/// default traces path (`/v1/traces`) is added. Returns `None` if no endpoint is configured.
+pub(crate) fn otlp_endpoint(endpoint: Option<&str>) -> Option<OtlpEndpoint> {
+  match endpoint {
+    Some(endpoint) => {
+      let trimmed = endpoint.trim_end_matches('/');
+      match url::Url::parse(endpoint) {
+        Ok(url) if url.path() == "/" || url.path().is_empty() => Some(OtlpEndpoint::Url(format!("{}/v1/traces", trimmed))),
+        _ => Some(OtlpEndpoint::Url(endpoint.to_string()))
+      }
+    }
+    None => std::env::var(OTLP_ENDPOINT_ENV).ok()
+      .filter(|endpoint| !endpoint.is_empty())
+      .map(|_| OtlpEndpoint::FromEnvironment)
+  }
+}
+
+/// Creates a tracing layer that exports spans to the OTLP/HTTP endpoint, and sets the W3C trace
+/// context propagator so that the trace context of received requests is used
+pub(crate) fn tracing_layer<S>(endpoint: &OtlpEndpoint) -> anyhow::Result<OpenTelemetryLayer<S, SdkTracer>>
+  where S: Subscriber + for<'span> LookupSpan<'span> {
+  let mut builder = SpanExporter::builder().with_http();
+  if let OtlpEndpoint::Url(url) = endpoint {
+    builder = builder.with_endpoint(url);
+  }
+  let exporter = builder.build()
+    .map_err(|err| anyhow!("Failed to create the OTLP exporter: {}", err))?;
+  let provider = SdkTracerProvider::builder()
+    .with_batch_exporter(exporter)
+    .with_resource(Resource::builder().with_service_name("pact-mock-server").build())
+    .build();
+  let tracer = provider.tracer("pact_mock_server");
+
+  global::set_text_map_propagator(TraceContextPropagator::new());
+  global::set_tracer_provider(provider.clone());
+  let _ = TRACER_PROVIDER.set(provider);
+  debug!(?endpoint, "Exporting spans with OTLP");
+
+  Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Flushes any spans that have not been exported yet, and shuts the exporter down
+pub(crate) fn shutdown_tracing() {
+  if let Some(provider) = TRACER_PROVIDER.get() {
+    if let Err(err) = provider.shutdown() {
+      warn!("Failed to shut down the OTLP exporter: {}", err);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+
+  use super::*;
+
+  #[test]
+  fn otlp_endpoint_test() {
+    expect!(otlp_endpoint(Some("http://localhost:4318")))
+      .to(be_some().value(OtlpEndpoint::Url("http://localhost:4318/v1/traces".to_string())));
+    expect!(otlp_endpoint(Some("http://localhost:4318/")))
+      .to(be_some().value(OtlpEndpoint::Url("http://localhost:4318/v1/traces".to_string())));
+    expect!(otlp_endpoint(Some("http://collector/custom/traces")))
+      .to(be_some().value(OtlpEndpoint::Url("http://collector/custom/traces".to_string())));
+  }
+}
@@ -0,0 +1,206 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use pact_models::pact::{read_pact, Pact};
+#[cfg(feature = "tls")] use rustls::crypto::ring::default_provider;
+#[cfg(feature = "tls")] use rustls::crypto::CryptoProvider;
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+use pact_mock_server::builder::MockServerBuilder;
+use pact_mock_server::mock_server::{MockServer, MockServerConfig};
+
+use crate::display_error;
+use crate::verify;
+
+/// Time to allow in-flight requests to complete when the mock server is shut down
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Starts a single mock server from the pact file in the foreground, without a master server.
+/// When a SIGINT or SIGTERM signal is received, the mock server is shut down and verified, and
+/// if all the requests matched, the pact file is written out.
+pub async fn serve_mock_server(port: Option<u16>, matches: &ArgMatches, usage: &str) -> Result<(), i32> {
+  let file = matches.get_one::<String>("file").unwrap();
+  info!("Starting mock server from file {}", file);
+
+  let pact = match read_pact(Path::new(file)) {
+    Ok(pact) => pact,
+    Err(err) => display_error(format!("Failed to load pact file '{}': {}", file, err), usage, 70)
+  };
+  let config = mock_server_config(port.unwrap_or(0), pact.as_ref(), matches);
+  debug!("Mock server config = {:?}", config);
+
+  let builder = MockServerBuilder::new()
+    .with_pact(pact)
+    .with_config(config);
+  let result = start_mock_server(builder, matches).await;
+  let mut mock_server = match result {
+    Ok(mock_server) => mock_server,
+    Err(err) => display_error(format!("Failed to start the mock server: {}", err), usage, 70)
+  };
+
+  println!("Mock server {} started on {}", mock_server.id, mock_server.url());
+  for url in mock_server.urls().iter().skip(1) {
+    println!("Mock server {} also listening on {}", mock_server.id, url);
+  }
+
+  wait_for_shutdown_signal().await;
+  info!("Shutting mock server {} down", mock_server.id);
+  match mock_server.shutdown_async(SHUTDOWN_TIMEOUT).await {
+    Ok(report) => if !report.requests_cut_off.is_empty() {
+      warn!("Requests that were cut off by the shutdown: {:?}", report.requests_cut_off);
+    },
+    Err(err) => display_error(format!("Failed to shutdown the mock server: {}", err), usage, 70)
+  }
+
+  // Writing the pact file can block while waiting for the lock on the file
+  tokio::task::block_in_place(|| {
+    verify_and_write_pact(&mock_server, matches.get_one::<String>("output").cloned(), usage)
+  })
+}
+
+/// Creates the config for the mock server from the command line arguments. The mock server will
+/// bind to the given port on all the addresses (defaults to 127.0.0.1).
+pub(crate) fn mock_server_config(port: u16, pact: &dyn Pact, matches: &ArgMatches) -> MockServerConfig {
+  let mut addresses = matches.get_many::<IpAddr>("bind")
+    .map(|addresses| addresses.cloned().collect::<Vec<_>>())
+    .unwrap_or_default();
+  if addresses.is_empty() {
+    addresses.push(IpAddr::V4(Ipv4Addr::LOCALHOST));
+  }
+
+  MockServerConfig {
+    cors_preflight: matches.get_flag("cors"),
+    pact_specification: matches.get_one::<String>("specification")
+      .map(|spec| spec.as_str().into())
+      .unwrap_or_else(|| pact.specification_version()),
+    metrics_path: matches.get_one::<String>("metrics-path").cloned(),
+    admin_path: matches.get_one::<String>("admin-path").cloned(),
+    admin_output_dir: matches.get_one::<String>("output").cloned(),
+    strict_spec_validation: matches.get_flag("strict-spec-validation"),
+    write_exercised_only: matches.get_flag("exercised-only"),
+    annotate_match_counts: matches.get_flag("match-counts"),
+    merge_policy: matches.get_one::<String>("merge-policy")
+      .and_then(|policy| policy.parse().ok())
+      .unwrap_or_default(),
+    address: SocketAddr::new(addresses[0], port).to_string(),
+    additional_addresses: addresses[1..].iter()
+      .map(|address| SocketAddr::new(*address, 0).to_string())
+      .collect(),
+    .. MockServerConfig::default()
+  }
+}
+
+#[cfg(feature = "tls")]
+pub(crate) async fn start_mock_server(builder: MockServerBuilder, matches: &ArgMatches) -> anyhow::Result<MockServer> {
+  if matches.get_flag("tls") {
+    if CryptoProvider::get_default().is_none() && CryptoProvider::install_default(default_provider()).is_err() {
+      anyhow::bail!("Failed to install the default FIPS cryptographic provider");
+    }
+    builder.with_self_signed_tls()?.start_https().await
+  } else {
+    builder.start().await
+  }
+}
+
+#[cfg(not(feature = "tls"))]
+pub(crate) async fn start_mock_server(builder: MockServerBuilder, _matches: &ArgMatches) -> anyhow::Result<MockServer> {
+  builder.start().await
+}
+
+#[cfg(unix)]
+async fn wait_for_shutdown_signal() {
+  use tokio::signal::unix::{signal, SignalKind};
+
+  match signal(SignalKind::terminate()) {
+    Ok(mut terminate) => {
+      tokio::select! {
+        _ = tokio::signal::ctrl_c() => debug!("Received SIGINT"),
+        _ = terminate.recv() => debug!("Received SIGTERM")
+      }
+    }
+    Err(err) => {
+      warn!("Could not listen for the SIGTERM signal - {}", err);
+      let _ = tokio::signal::ctrl_c().await;
+    }
+  }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_shutdown_signal() {
+  let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Verifies the mock server, and writes the pact file out if all the requests matched. Exits with
+/// a status of 2 if there were any mismatches.
+fn verify_and_write_pact(mock_server: &MockServer, output_path: Option<String>, usage: &str) -> Result<(), i32> {
+  verify::display_skipped_interactions(mock_server);
+  let mismatches = mock_server.mismatches();
+  if mismatches.is_empty() {
+    match mock_server.write_pact(&output_path, false) {
+      Ok(_) => {
+        println!("Mock server {} verified ok, pact file written", mock_server.id);
+        Ok(())
+      }
+      Err(err) => display_error(format!("Failed to write the pact file: {}", err), usage, 70)
+    }
+  } else {
+    let json = json!({
+      "mismatches": mismatches.iter().map(|mismatch| mismatch.to_json()).collect::<Vec<_>>()
+    });
+    verify::display_verification_errors(&mock_server.id, mock_server.port() as u64, &json);
+    Err(2)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use pact_models::PactSpecification;
+  use pact_models::sync_pact::RequestResponsePact;
+
+  use pact_mock_server::pact_writer::MergePolicy;
+
+  use crate::setup_args;
+
+  use super::*;
+
+  #[test]
+  fn mock_server_config_defaults_to_the_ip4_loopback_adapter() {
+    let matches = setup_args().get_matches_from(["pact_mock_server_cli", "serve", "-f", "pact.json"]);
+    let (_, serve_matches) = matches.subcommand().unwrap();
+    let pact = RequestResponsePact::default();
+
+    let config = mock_server_config(1234, &pact, serve_matches);
+    expect!(config.address).to(be_equal_to("127.0.0.1:1234"));
+    expect!(config.additional_addresses.is_empty()).to(be_true());
+    expect!(config.pact_specification).to(be_equal_to(PactSpecification::V3));
+    expect!(config.cors_preflight).to(be_false());
+    expect!(config.strict_spec_validation).to(be_false());
+  }
+
+  #[test]
+  fn mock_server_config_from_args() {
+    let matches = setup_args().get_matches_from([
+      "pact_mock_server_cli", "serve", "-f", "pact.json", "--bind", "::1", "--bind", "0.0.0.0",
+      "--cors-preflight", "--specification", "V2", "--strict-spec-validation", "--exercised-only", "--merge-policy", "prefer-existing",
+      "--admin-path", "/__pact", "-o", "target/pacts"
+    ]);
+    let (_, serve_matches) = matches.subcommand().unwrap();
+    let pact = RequestResponsePact::default();
+
+    let config = mock_server_config(0, &pact, serve_matches);
+    expect!(config.address).to(be_equal_to("[::1]:0"));
+    expect!(config.additional_addresses).to(be_equal_to(vec!["0.0.0.0:0".to_string()]));
+    expect!(config.pact_specification).to(be_equal_to(PactSpecification::V2));
+    expect!(config.strict_spec_validation).to(be_true());
+    expect!(config.write_exercised_only).to(be_true());
+    expect!(config.annotate_match_counts).to(be_false());
+    expect!(config.merge_policy).to(be_equal_to(MergePolicy::PreferExisting));
+    expect!(config.cors_preflight).to(be_true());
+    expect!(config.admin_path).to(be_some().value("/__pact"));
+    expect!(config.admin_output_dir).to(be_some().value("target/pacts"));
+  }
+}
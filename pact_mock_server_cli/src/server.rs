@@ -5,12 +5,13 @@ use std::{
   thread,
   time::Duration
 };
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use bytes::Bytes;
-use http::Request;
+use http::{Request, Response};
+use http_body_util::BodyExt;
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
@@ -32,10 +33,14 @@ use webmachine_rust::context::*;
 use webmachine_rust::headers::*;
 
 use pact_mock_server::builder::MockServerBuilder;
+use pact_mock_server::logs::mock_server_logs;
+use pact_mock_server::metrics::format_openmetrics;
 use pact_mock_server::mock_server::{MockServer, MockServerConfig};
 
 use crate::{SERVER_MANAGER, ServerOpts};
+use crate::config::ConfigFile;
 use crate::verify;
+use crate::watch::{events_request_id, events_response, ResponseBody};
 
 fn json_error(error: String) -> Bytes {
   let json_response = json!({ "error" : json!(error) });
@@ -103,13 +108,54 @@ fn start_provider(context: &mut WebmachineContext) -> Result<bool, u16> {
             None => None
           };
 
+          let bind_addresses = parse_bind_addresses(context).map_err(|err| {
+            error!("{}", err);
+            context.response.body = Some(json_error(err));
+            422_u16
+          })?;
+
           let mut config = MockServerConfig {
             cors_preflight: query_param_set(context, "cors"),
+            strict_spec_validation: query_param_set(context, "strictSpecValidation"),
+            write_exercised_only: query_param_set(context, "writeExercisedOnly"),
+            annotate_match_counts: query_param_set(context, "annotateMatchCounts"),
+            metrics_path: context.request.query.get("metricsPath")
+              .and_then(|values| values.first())
+              .filter(|path| !path.is_empty())
+              .cloned(),
+            admin_path: context.request.query.get("adminPath")
+              .and_then(|values| values.first())
+              .filter(|path| !path.is_empty())
+              .cloned(),
             .. MockServerConfig::default()
             };
           if let Some(spec) = pact_specification {
             config.pact_specification = spec.into();
           }
+          let port = match context.request.query.get("port").and_then(|values| values.first()).cloned() {
+            Some(port) if !port.is_empty() => port.parse::<u16>().map_err(|err| {
+              error!("'{}' is not a valid port number - {}", port, err);
+              context.response.body = Some(json_error(format!("'{}' is not a valid port number - {}", port, err)));
+              422_u16
+            })?,
+            _ => get_next_port(base_port)
+          };
+          if let Some(policy) = context.request.query.get("mergePolicy").and_then(|values| values.first()).cloned() {
+            config.merge_policy = policy.parse().map_err(|err: anyhow::Error| {
+              error!("{}", err);
+              context.response.body = Some(json_error(err.to_string()));
+              422_u16
+            })?;
+          }
+          if let Some((address, additional)) = bind_addresses.split_first() {
+            config.address = SocketAddr::new(*address, port).to_string();
+            config.additional_addresses = additional.iter()
+              .map(|address| SocketAddr::new(*address, 0).to_string())
+              .collect();
+          }
+          // The admin API of the mock server can only write pact files under the output directory
+          // of the master server
+          config.admin_output_dir = context.metadata.get("output_path").map(|path| path.to_string());
           debug!("Mock server config = {:?}", config);
 
           #[allow(unused_assignments)]
@@ -122,19 +168,17 @@ fn start_provider(context: &mut WebmachineContext) -> Result<bool, u16> {
                   error!("Failed to install the default FIPS cryptographic provider");
                   result = Err(anyhow!("Failed to install the default FIPS cryptographic provider"))
                 } else {
-                  result = start_https_server(pact, config, get_next_port(base_port), &mock_server_id)
+                  result = start_https_server(pact, config, port, &mock_server_id)
                 }
               } else {
-                result = start_https_server(pact, config, get_next_port(base_port), &mock_server_id)
+                result = start_https_server(pact, config, port, &mock_server_id)
               }
             } else {
               debug!("Starting mock server with id {}", &mock_server_id);
               let mut server_manager = SERVER_MANAGER.lock().unwrap();
               trace!("Unlocked server manager");
-              result = MockServerBuilder::new()
+              result = bind_to_default_address(MockServerBuilder::new().with_config(config), port)
                 .with_pact(pact)
-                .with_config(config)
-                .bind_to_ip4_port(get_next_port(base_port))
                 .with_id(mock_server_id.as_str())
                 .attach_to_manager(&mut server_manager);
             };
@@ -145,10 +189,8 @@ fn start_provider(context: &mut WebmachineContext) -> Result<bool, u16> {
             debug!("Starting mock server with id {}", &mock_server_id);
             let mut server_manager = crate::SERVER_MANAGER.lock().unwrap();
             trace!("Unlocked server manager");
-            result = MockServerBuilder::new()
+            result = bind_to_default_address(MockServerBuilder::new().with_config(config), port)
               .with_pact(pact)
-              .with_config(config)
-              .bind_to_ip4_port(get_next_port(base_port))
               .with_id(mock_server_id.as_str())
               .attach_to_manager(&mut server_manager);
           }
@@ -159,6 +201,8 @@ fn start_provider(context: &mut WebmachineContext) -> Result<bool, u16> {
               let mock_server_json = json!({
                 "id" : json!(mock_server_id),
                 "port" : json!(mock_server.port() as i64),
+                "url" : json!(mock_server.url()),
+                "addresses" : json!(mock_server.addresses().iter().map(|a| a.to_string()).collect::<Vec<_>>()),
               });
               let json_response = json!({ "mockServer" : mock_server_json });
               context.response.body = Some(Bytes::from(json_response.to_string()));
@@ -197,15 +241,58 @@ fn start_https_server(
   debug!("Starting TLS mock server with id {}", id);
   let mut server_manager = SERVER_MANAGER.lock().unwrap();
   trace!("Unlocked server manager");
-  MockServerBuilder::new()
+  let builder = MockServerBuilder::new().with_config(config);
+  let builder = if builder.address_assigned() { builder } else { builder.bind_to_port(port) };
+  builder
     .with_pact(pact)
-    .with_config(config)
-    .bind_to_port(port)
     .with_id(id.as_str())
     .with_self_signed_tls()?
     .attach_to_manager(&mut server_manager)
 }
 
+/// Starts the mock servers from the config file, and registers them with the server manager
+fn start_configured_mock_servers(
+  config: &ConfigFile,
+  base_port: Option<u16>,
+  output_path: Option<String>
+) -> anyhow::Result<()> {
+  for definition in &config.mock_servers {
+    let port = definition.port.unwrap_or_else(|| get_next_port(base_port));
+    let mut builder = definition.builder(config, port)?;
+    if let Some(output_path) = &output_path {
+      builder = builder.with_admin_output_dir(output_path);
+    }
+    let mut server_manager = SERVER_MANAGER.lock().unwrap();
+    let mock_server = server_manager.spawn_mock_server(builder)
+      .with_context(|| format!("Could not start a mock server for the pact file '{}'", definition.pact.display()))?;
+    info!("Mock server {} started on {} for pact file '{}'", mock_server.id, mock_server.url(), definition.pact.display());
+  }
+  Ok(())
+}
+
+/// Binds the mock server to the IP4 loopback adapter if no address has been set from the `bind`
+/// query parameters
+fn bind_to_default_address(builder: MockServerBuilder, port: u16) -> MockServerBuilder {
+  if builder.address_assigned() {
+    builder
+  } else {
+    builder.bind_to_ip4_port(port)
+  }
+}
+
+/// Parses the IP addresses from any `bind` query parameters
+fn parse_bind_addresses(context: &WebmachineContext) -> Result<Vec<IpAddr>, String> {
+  context.request.query.get("bind")
+    .map(|values| values.iter()
+      .flat_map(|value| value.split(','))
+      .map(|value| value.trim())
+      .filter(|value| !value.is_empty())
+      .map(|value| value.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()
+        .map_err(|err| format!("'{}' is not a valid IP address to bind to - {}", value, err)))
+      .collect())
+    .unwrap_or_else(|| Ok(vec![]))
+}
+
 fn query_param_set(context: &mut WebmachineContext, name: &str) -> bool {
   context.request.query.get(name)
     .unwrap_or(&vec![]).first().unwrap_or(&String::default())
@@ -223,6 +310,9 @@ pub fn verify_mock_server_request(context: &mut WebmachineContext, output_path:
   match verify::validate_id(&id, &SERVER_MANAGER) {
     Ok(ms) => {
       let mut map = btreemap!{ "mockServer" => ms.to_json() };
+      for interaction in ms.skipped_interactions() {
+        info!("Mock server {} skipped {}, as it is not an HTTP interaction", ms.id, interaction);
+      }
       let mismatches = ms.mismatches();
       if !mismatches.is_empty() {
         map.insert("mismatches", json!(mismatches.iter()
@@ -230,7 +320,9 @@ pub fn verify_mock_server_request(context: &mut WebmachineContext, output_path:
         context.response.body = Some(Bytes::from(json!(map).to_string()));
         Err(422)
       } else {
-        match ms.write_pact(output_path, false) {
+        // Writing the pact file can block while waiting for the lock on the file, and this
+        // callback is not async
+        match tokio::task::block_in_place(|| ms.write_pact(output_path, false)) {
           Ok(_) => Ok(true),
           Err(err) => {
             map.insert("error", json!(format!("Failed to write pact to file - {}", err)));
@@ -281,6 +373,7 @@ fn shutdown_resource(auth: String, shutdown_tx: Arc<Sender<()>>) -> WebmachineRe
             info!("Scheduling master server to shutdown in {}ms", period);
             thread::sleep(Duration::from_millis(period));
             info!("Shutting down");
+            #[cfg(feature = "otel")] crate::otel::shutdown_tracing();
             process::exit(0);
           });
           Ok(true)
@@ -292,6 +385,49 @@ fn shutdown_resource(auth: String, shutdown_tx: Arc<Sender<()>>) -> WebmachineRe
   }
 }
 
+/// Returns the interactions of the mock server with the status of each one, based on the requests
+/// that the mock server has received. The status is `matched` if requests only matched the
+/// interaction, `mismatched` if any requests did not match it, and `unmatched` if no request for
+/// it was received. Interactions that are not HTTP interactions are not handled by the mock
+/// server, and have a status of `skipped`.
+fn interactions_json(mock_server: &MockServer) -> Value {
+  let requests = mock_server.interaction_requests();
+  let interactions = mock_server.pact.interactions.iter().zip(requests).map(|(interaction, requests)| {
+    let (status, calls, mismatches) = match interaction.as_v4_http() {
+      Some(_) => {
+        let calls = requests.matched;
+        let mismatches = requests.mismatched;
+        let status = if mismatches > 0 {
+          "mismatched"
+        } else if calls > 0 {
+          "matched"
+        } else {
+          "unmatched"
+        };
+        (status, calls, mismatches)
+      }
+      None => ("skipped", 0, 0)
+    };
+    json!({
+      "description": interaction.description(),
+      "providerStates": interaction.provider_states().iter()
+        .map(|state| state.name.clone())
+        .collect::<Vec<_>>(),
+      "key": interaction.key().unwrap_or_else(|| interaction.unique_key()),
+      "type": interaction.type_of(),
+      "status": status,
+      "calls": calls,
+      "mismatches": mismatches
+    })
+  }).collect::<Vec<_>>();
+
+  json!({
+    "id": mock_server.id,
+    "port": mock_server.port(),
+    "interactions": interactions
+  })
+}
+
 fn mock_server_resource(options: ServerOpts) -> WebmachineResource {
   let output_path = options.output_path.clone();
   WebmachineResource {
@@ -310,7 +446,7 @@ fn mock_server_resource(options: ServerOpts) -> WebmachineResource {
             context.metadata.insert("port".to_string(), ms.port().into());
             if paths.len() > 1 {
               context.metadata.insert("subpath".to_string(), paths[1].as_str().into());
-              paths[1] == "verify"
+              paths[1] == "verify" || paths[1] == "logs" || paths[1] == "interactions" || paths[1] == "pact"
             } else {
               true
             }
@@ -347,6 +483,65 @@ fn mock_server_resource(options: ServerOpts) -> WebmachineResource {
           }
             .map(|res| Bytes::from(res))
         }
+        Some(subpath) if subpath.to_string() == "interactions" => {
+          let id = context.metadata.get("id").unwrap_or_default().to_string();
+          let response = {
+            let guard = SERVER_MANAGER.lock().unwrap();
+            guard.find_mock_server_by_id(&id, &|_, ms| match ms {
+              Either::Left(ms) => Ok(interactions_json(ms)),
+              Either::Right(_plugin) => {
+                error!("Plugin mock servers are not currently supported");
+                Err(422)
+              }
+            })
+          };
+          match response {
+            Some(Ok(json)) => Some(Bytes::from(json.to_string())),
+            Some(Err(status)) => {
+              context.response.status = status;
+              None
+            }
+            None => {
+              context.response.status = 404;
+              None
+            }
+          }
+        }
+        Some(subpath) if subpath.to_string() == "pact" => {
+          let id = context.metadata.get("id").unwrap_or_default().to_string();
+          let response = {
+            let guard = SERVER_MANAGER.lock().unwrap();
+            guard.find_mock_server_by_id(&id, &|_, ms| match ms {
+              Either::Left(ms) => ms.pact_json().map_err(|err| {
+                error!("Failed to generate the pact for mock server {} - {}", ms.id, err);
+                (422, Some(err.to_string()))
+              }),
+              Either::Right(_plugin) => {
+                error!("Plugin mock servers are not currently supported");
+                Err((422, None))
+              }
+            })
+          };
+          match response {
+            Some(Ok(json)) => Some(Bytes::from(json.to_string())),
+            Some(Err((status, message))) => {
+              context.response.status = status;
+              message.map(json_error)
+            }
+            None => {
+              context.response.status = 404;
+              None
+            }
+          }
+        }
+        Some(subpath) if subpath.to_string() == "logs" => {
+          let id = context.metadata.get("id").unwrap_or_default().to_string();
+          let logs = json!({
+            "id": id,
+            "logs": mock_server_logs(&id)
+          });
+          Some(Bytes::from(logs.to_string()))
+        }
         Some(_) => {
           context.response.status = 405;
           None
@@ -382,6 +577,36 @@ fn mock_server_resource(options: ServerOpts) -> WebmachineResource {
   }
 }
 
+fn metrics_resource() -> WebmachineResource {
+  WebmachineResource {
+    allowed_methods: owned_vec(&["OPTIONS", "GET", "HEAD"]),
+    produces: owned_vec(&["application/openmetrics-text", "text/plain"]),
+    charsets_provided: owned_vec(&["UTF-8"]),
+    resource_exists: callback(|context, _| context.request.request_path.trim_matches('/').is_empty()),
+    render_response: callback(|_, _| {
+      trace!("metrics_resource -> render_response");
+      let server_manager = SERVER_MANAGER.lock().unwrap();
+      let metrics = server_manager.map_mock_servers(|ms| (ms.id.clone(), ms.metrics.lock().unwrap().clone()));
+      let body = format_openmetrics(metrics.iter().map(|(id, metrics)| (id.as_str(), metrics)));
+      Some(Bytes::from(body))
+    }),
+    .. WebmachineResource::default()
+  }
+}
+
+/// Handles a request to the master server. Requests for the events of a mock server are streamed,
+/// all others are dispatched to the webmachine resources.
+async fn handle_request(
+  req: Request<Incoming>,
+  dispatcher: Arc<WebmachineDispatcher>
+) -> http::Result<Response<ResponseBody>> {
+  match events_request_id(req.method(), req.uri().path()) {
+    Some(id) => events_response(&id),
+    None => dispatcher.dispatch(req).await
+      .map(|response| response.map(|body| body.boxed_unsync()))
+  }
+}
+
 pub async fn start_server(port: u16, options: ServerOpts) -> Result<(), i32> {
   let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
   let listener = tokio::net::TcpListener::bind(addr).await
@@ -401,8 +626,25 @@ pub async fn start_server(port: u16, options: ServerOpts) -> Result<(), i32> {
   info!("Master server started on port {}", local_addr.port());
   info!("Server key: '{}'", options.server_key);
 
+  if let Some(config) = options.config.clone() {
+    let base_port = options.base_port;
+    let output_path = options.output_path.clone();
+    match thread::spawn(move || start_configured_mock_servers(&config, base_port, output_path)).join() {
+      Ok(Ok(())) => {},
+      Ok(Err(err)) => {
+        error!("Failed to start the mock servers from the config file: {:#}", err);
+        return Err(5);
+      }
+      Err(err) => {
+        error!("Failed to spawn new thread to start the mock servers - {:?}", err);
+        return Err(5);
+      }
+    }
+  }
+
   let auth = format!("Bearer {}", options.server_key);
   let base_port = options.base_port.clone();
+  let output_path = options.output_path.clone();
   let options = options.clone();
   let dispatcher = Arc::new(WebmachineDispatcher {
     routes: btreemap! {
@@ -431,6 +673,9 @@ pub async fn start_server(port: u16, options: ServerOpts) -> Result<(), i32> {
           if let Some(base_port) = base_port {
             context.metadata.insert("base_port".to_string(), base_port.into());
           }
+          if let Some(output_path) = &output_path {
+            context.metadata.insert("output_path".to_string(), output_path.as_str().into());
+          }
           if let Err(err) = tx.send(context.clone()) {
             error!("Failed to send context to start new mock server - {:?}", err);
             return Err(500)
@@ -473,6 +718,7 @@ pub async fn start_server(port: u16, options: ServerOpts) -> Result<(), i32> {
         }),
         .. WebmachineResource::default()
       },
+      "/metrics" => metrics_resource(),
       "/mockserver" => mock_server_resource(options.clone()),
       "/shutdown" => shutdown_resource(auth, Arc::new(shutdown_tx))
     }
@@ -489,7 +735,7 @@ pub async fn start_server(port: u16, options: ServerOpts) -> Result<(), i32> {
             let io = TokioIo::new(stream);
             join_set.spawn(async move {
               if let Err(err) = http1::Builder::new()
-                .serve_connection(io, service_fn(|req: Request<Incoming>| dispatcher.dispatch(req))).await {
+                .serve_connection(io, service_fn(|req: Request<Incoming>| handle_request(req, dispatcher.clone()))).await {
                 error!("Failed to serve incoming connection: {err}");
               }
             });
@@ -511,3 +757,132 @@ pub async fn start_server(port: u16, options: ServerOpts) -> Result<(), i32> {
   trace!("Main server loop done");
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+  use pact_models::prelude::{Consumer, Provider};
+  use pact_models::prelude::v4::{SynchronousHttp, V4Pact};
+  use pact_models::v4::http_parts::HttpRequest;
+  use pact_models::v4::interaction::V4Interaction;
+
+  use super::*;
+
+  fn test_pact() -> V4Pact {
+    V4Pact {
+      consumer: Consumer { name: "test-consumer".to_string() },
+      provider: Provider { name: "test-provider".to_string() },
+      interactions: vec![
+        SynchronousHttp {
+          description: "a request for a".to_string(),
+          request: HttpRequest {
+            path: "/a".to_string(),
+            ..HttpRequest::default()
+          },
+          ..SynchronousHttp::default()
+        }.boxed_v4(),
+        SynchronousHttp {
+          description: "a request for b".to_string(),
+          request: HttpRequest {
+            path: "/b".to_string(),
+            ..HttpRequest::default()
+          },
+          ..SynchronousHttp::default()
+        }.boxed_v4()
+      ],
+      ..V4Pact::default()
+    }
+  }
+
+  fn start_test_mock_server(id: &str) -> MockServer {
+    let mut server_manager = SERVER_MANAGER.lock().unwrap();
+    MockServerBuilder::new()
+      .bind_to_ip4_port(0)
+      .with_v4_pact(test_pact())
+      .with_id(id)
+      .attach_to_manager(&mut server_manager)
+      .unwrap()
+  }
+
+  fn get(path: &str) -> WebmachineContext {
+    let dispatcher = WebmachineDispatcher {
+      routes: btreemap! {
+        "/mockserver" => mock_server_resource(ServerOpts {
+          output_path: None,
+          base_port: None,
+          server_key: "test-key".to_string(),
+          config: None
+        })
+      }
+    };
+    let mut context = WebmachineContext {
+      request: WebmachineRequest {
+        request_path: path.to_string(),
+        .. WebmachineRequest::default()
+      },
+      .. WebmachineContext::default()
+    };
+    dispatcher.dispatch_to_resource(&mut context);
+    context
+  }
+
+  fn body_json(context: &WebmachineContext) -> Value {
+    serde_json::from_slice(context.response.body.as_ref().unwrap()).unwrap()
+  }
+
+  #[test]
+  fn interactions_request_returns_the_status_of_each_interaction() {
+    let mock_server = start_test_mock_server("interactions-test");
+    let response = reqwest::blocking::get(format!("http://127.0.0.1:{}/a", mock_server.port()))
+      .unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(200));
+
+    let context = get("/mockserver/interactions-test/interactions");
+    SERVER_MANAGER.lock().unwrap().shutdown_mock_server_by_id("interactions-test".to_string());
+
+    expect!(context.response.status).to(be_equal_to(200));
+    let json = body_json(&context);
+    expect!(json["id"].clone()).to(be_equal_to(json!("interactions-test")));
+    expect!(json["port"].clone()).to(be_equal_to(json!(mock_server.port())));
+    let interactions = json["interactions"].as_array().unwrap();
+    expect!(interactions.len()).to(be_equal_to(2));
+    expect!(interactions[0]["description"].clone()).to(be_equal_to(json!("a request for a")));
+    expect!(interactions[0]["status"].clone()).to(be_equal_to(json!("matched")));
+    expect!(interactions[0]["calls"].clone()).to(be_equal_to(json!(1)));
+    expect!(interactions[0]["mismatches"].clone()).to(be_equal_to(json!(0)));
+    expect!(interactions[1]["description"].clone()).to(be_equal_to(json!("a request for b")));
+    expect!(interactions[1]["status"].clone()).to(be_equal_to(json!("unmatched")));
+    expect!(interactions[1]["calls"].clone()).to(be_equal_to(json!(0)));
+  }
+
+  #[test]
+  fn interactions_request_returns_not_found_for_an_unknown_mock_server() {
+    let context = get("/mockserver/unknown-mock-server/interactions");
+    expect!(context.response.status).to(be_equal_to(404));
+  }
+
+  #[test]
+  fn pact_request_returns_the_pact_of_the_mock_server() {
+    let mock_server = start_test_mock_server("pact-test");
+    let response = reqwest::blocking::get(format!("http://127.0.0.1:{}/a", mock_server.port()))
+      .unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(200));
+
+    let context = get("/mockserver/pact-test/pact");
+    SERVER_MANAGER.lock().unwrap().shutdown_mock_server_by_id("pact-test".to_string());
+
+    expect!(context.response.status).to(be_equal_to(200));
+    let json = body_json(&context);
+    expect!(json["consumer"]["name"].clone()).to(be_equal_to(json!("test-consumer")));
+    expect!(json["provider"]["name"].clone()).to(be_equal_to(json!("test-provider")));
+    let interactions = json["interactions"].as_array().unwrap();
+    expect!(interactions.len()).to(be_equal_to(2));
+    expect!(interactions[0]["description"].clone()).to(be_equal_to(json!("a request for a")));
+  }
+
+  #[test]
+  fn pact_request_returns_not_found_for_an_unknown_mock_server() {
+    let context = get("/mockserver/unknown-mock-server/pact");
+    expect!(context.response.status).to(be_equal_to(404));
+  }
+}
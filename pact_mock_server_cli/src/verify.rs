@@ -8,6 +8,7 @@ use serde_json::Value;
 use tracing::error;
 
 use pact_mock_server::{
+  broker::{self, BrokerAuth, PublishOptions},
   mock_server::MockServer,
   server_manager::ServerManager
 };
@@ -68,7 +69,10 @@ pub async fn verify_mock_server(host: &str, port: u16, matches: &ArgMatches, usa
         }
       } else {
         println!("Mock server with {} '{}' verified ok", id, id_type);
-        Ok(())
+        match publish_options(matches) {
+          Some(options) => publish_pact(&client, host, port, &id, &options, usage).await,
+          None => Ok(())
+        }
       }
     },
     Err(err) => {
@@ -77,6 +81,58 @@ pub async fn verify_mock_server(host: &str, port: u16, matches: &ArgMatches, usa
   }
 }
 
+/// Returns the options to publish the pact to a Pact Broker with, if a broker URL and consumer
+/// version were provided. The broker URL and credentials can come from the environment, so the
+/// pact is only published if a consumer version is explicitly given, and a token takes precedence
+/// over a username and password.
+fn publish_options(matches: &ArgMatches) -> Option<PublishOptions> {
+  let broker_url = matches.get_one::<String>("broker-url")?;
+  matches.get_one::<String>("consumer-version").map(|consumer_version| {
+    let auth = match (matches.get_one::<String>("broker-token"), matches.get_one::<String>("broker-username")) {
+      (Some(token), _) => Some(BrokerAuth::Token(token.clone())),
+      (None, Some(username)) => Some(BrokerAuth::Basic(username.clone(), matches.get_one::<String>("broker-password").cloned())),
+      (None, None) => None
+    };
+    PublishOptions {
+      broker_url: broker_url.clone(),
+      consumer_version: consumer_version.clone(),
+      branch: matches.get_one::<String>("branch").cloned(),
+      tags: matches.get_many::<String>("tag")
+        .map(|tags| tags.cloned().collect())
+        .unwrap_or_default(),
+      auth
+    }
+  })
+}
+
+/// Fetches the pact of the mock server from the master server, and publishes it to the Pact Broker
+async fn publish_pact(
+  client: &reqwest::Client,
+  host: &str,
+  port: u16,
+  id: &str,
+  options: &PublishOptions,
+  usage: &str
+) -> Result<(), i32> {
+  let url = format!("http://{}:{}/mockserver/{}/pact", host, port, id);
+  let pact = match client.get(&url).send().await {
+    Ok(response) if response.status().is_success() => match response.json::<Value>().await {
+      Ok(pact) => pact,
+      Err(err) => crate::display_error(format!("Failed to parse the pact from the master mock server: {}", err), usage, 40)
+    },
+    Ok(response) => crate::display_error(format!("Unexpected response from master mock server '{}': {}", url, response.status()), usage, 40),
+    Err(err) => crate::display_error(format!("Failed to connect to the master mock server '{}': {}", url, err), usage, 40)
+  };
+
+  match broker::publish_pact(&pact, options).await {
+    Ok(_) => {
+      println!("Pact published to {} for consumer version {}", options.broker_url, options.consumer_version);
+      Ok(())
+    }
+    Err(err) => crate::display_error(format!("Failed to publish the pact: {:#}", err), usage, 40)
+  }
+}
+
 fn validate_port(port: u16, server_manager: &Mutex<ServerManager>) -> anyhow::Result<MockServer> {
   server_manager.lock().unwrap()
     .find_mock_server_by_port(port, &|_, _, ms| ms.left().cloned())
@@ -99,7 +155,7 @@ pub fn validate_id(id: &str, server_manager: &Mutex<ServerManager>) -> anyhow::R
     }
 }
 
-fn display_verification_errors(id: &str, port: u64, json: &serde_json::Value) {
+pub(crate) fn display_verification_errors(id: &str, port: u64, json: &serde_json::Value) {
   let mismatches = json.get("mismatches").unwrap().as_array().unwrap();
   println!("Mock server {}/{} failed verification with {} errors\n", id, port, mismatches.len());
 
@@ -126,3 +182,61 @@ fn display_verification_errors(id: &str, port: u64, json: &serde_json::Value) {
     }
   }
 }
+
+/// Displays the interactions of the mock server that were not verified, as they are not HTTP
+/// interactions
+pub(crate) fn display_skipped_interactions(mock_server: &MockServer) {
+  for interaction in mock_server.skipped_interactions() {
+    println!("Mock server {} skipped {}, as it is not an HTTP interaction", mock_server.id, interaction);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use expectest::prelude::*;
+
+  use crate::setup_args;
+
+  use super::*;
+
+  #[test]
+  fn publish_options_test() {
+    let matches = setup_args().get_matches_from(["pact_mock_server_cli", "verify", "-m", "1234"]);
+    let (_, verify_matches) = matches.subcommand().unwrap();
+    expect!(publish_options(verify_matches)).to(be_none());
+
+    let matches = setup_args().get_matches_from([
+      "pact_mock_server_cli", "verify", "-m", "1234", "--broker-url", "http://localhost:9292",
+      "--consumer-version", "1.0.0", "--branch", "main", "--tag", "dev", "--tag", "prod",
+      "--broker-username", "user", "--broker-password", "pass"
+    ]);
+    let (_, verify_matches) = matches.subcommand().unwrap();
+    expect!(publish_options(verify_matches)).to(be_some().value(PublishOptions {
+      broker_url: "http://localhost:9292".to_string(),
+      consumer_version: "1.0.0".to_string(),
+      branch: Some("main".to_string()),
+      tags: vec!["dev".to_string(), "prod".to_string()],
+      auth: Some(BrokerAuth::Basic("user".to_string(), Some("pass".to_string())))
+    }));
+
+    let matches = setup_args().get_matches_from([
+      "pact_mock_server_cli", "verify", "-m", "1234", "--broker-url", "http://localhost:9292"
+    ]);
+    let (_, verify_matches) = matches.subcommand().unwrap();
+    expect!(publish_options(verify_matches)).to(be_none());
+
+    let matches = setup_args().get_matches_from([
+      "pact_mock_server_cli", "verify", "-m", "1234", "--broker-url", "http://localhost:9292",
+      "--consumer-version", "1.0.0", "--broker-token", "token", "--broker-username", "user",
+      "--broker-password", "pass"
+    ]);
+    let (_, verify_matches) = matches.subcommand().unwrap();
+    expect!(publish_options(verify_matches)).to(be_some().value(PublishOptions {
+      broker_url: "http://localhost:9292".to_string(),
+      consumer_version: "1.0.0".to_string(),
+      branch: None,
+      tags: vec![],
+      auth: Some(BrokerAuth::Token("token".to_string()))
+    }));
+  }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use clap::ArgMatches;
+use futures::{stream, StreamExt};
+use http::StatusCode;
+use http_body_util::{BodyExt, Full, StreamBody};
+use http_body_util::combinators::UnsyncBoxBody;
+use hyper::body::Frame;
+use hyper::Response;
+use itertools::Itertools;
+use pact_models::json_utils::json_to_string;
+use pact_models::v4::http_parts::HttpRequest;
+use serde_json::{json, Value};
+use tracing::debug;
+
+use pact_mock_server::matching::MatchResult;
+use pact_mock_server::mock_server::MockServerEvent;
+
+use crate::{display_error, SERVER_MANAGER};
+use crate::verify;
+
+/// Body of the responses from the master server
+pub(crate) type ResponseBody = UnsyncBoxBody<Bytes, Infallible>;
+
+/// Returns the ID of the mock server if the request is for the events of a mock server
+/// (`GET /mockserver/:id/events`)
+pub(crate) fn events_request_id(method: &http::Method, path: &str) -> Option<String> {
+  let paths = path.split('/').filter(|p| !p.is_empty()).collect_vec();
+  match (method, paths.as_slice()) {
+    (&http::Method::GET, ["mockserver", id, "events"]) => Some(id.to_string()),
+    _ => None
+  }
+}
+
+/// Streams the events of the mock server as server-sent events. The stream ends when the mock
+/// server is shut down.
+pub(crate) fn events_response(id: &str) -> http::Result<Response<ResponseBody>> {
+  match verify::validate_id(id, &SERVER_MANAGER) {
+    Ok(mock_server) => {
+      debug!("Streaming events for mock server {}", mock_server.id);
+      let events = mock_server.subscribe()
+        .filter_map(|event| async move { event_to_sse(&event) });
+      let body = stream::once(async { ": connected\n\n".to_string() })
+        .chain(events)
+        .map(|event| Ok(Frame::data(Bytes::from(event))));
+      Response::builder()
+        .status(200)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(StreamBody::new(body).boxed_unsync())
+    }
+    Err(err) => Response::builder()
+      .status(404)
+      .header("Content-Type", "application/json")
+      .body(Full::new(Bytes::from(json!({ "error": err.to_string() }).to_string())).boxed_unsync())
+  }
+}
+
+/// Formats a mock server event as a server-sent event. Only the request and shutdown events are
+/// sent.
+fn event_to_sse(event: &MockServerEvent) -> Option<String> {
+  let (name, data) = match event {
+    MockServerEvent::RequestRecorded(request) => ("match", json!({
+      "requestId": request.details.request_id,
+      "remoteAddress": request.details.remote_address.to_string(),
+      "request": received_request(&request.result).map(request_json),
+      "result": request.result.to_json()
+    })),
+    MockServerEvent::ResponsePrepared(details) => ("response", json!({
+      "requestId": details.request_id,
+      "status": details.status,
+      "bodySize": details.body_size,
+      "durationMs": details.duration.as_secs_f64() * 1000.0
+    })),
+    MockServerEvent::ServerShutdown => ("shutdown", json!({})),
+    _ => return None
+  };
+  Some(format!("event: {}\ndata: {}\n\n", name, data))
+}
+
+fn received_request(result: &MatchResult) -> Option<&HttpRequest> {
+  match result {
+    MatchResult::RequestMatch(_, _, request) => Some(request),
+    MatchResult::RequestMismatch(_, request, _) => Some(request),
+    MatchResult::RequestNotFound(request) => Some(request),
+    MatchResult::MissingRequest(_) => None
+  }
+}
+
+fn request_json(request: &HttpRequest) -> Value {
+  let query = request.query.as_ref()
+    .map(|query| query.iter()
+      .sorted_by(|a, b| a.0.cmp(b.0))
+      .flat_map(|(name, values)| values.iter().map(move |value| match value {
+        Some(value) => format!("{}={}", name, value),
+        None => name.clone()
+      }))
+      .join("&"))
+    .unwrap_or_default();
+  json!({
+    "method": request.method,
+    "path": request.path,
+    "query": query
+  })
+}
+
+/// Parses a stream of server-sent events, returning the event name and data of each complete event
+#[derive(Debug, Default)]
+struct SseParser {
+  buffer: String
+}
+
+impl SseParser {
+  fn push(&mut self, chunk: &[u8]) -> Vec<(String, String)> {
+    self.buffer.push_str(&String::from_utf8_lossy(chunk).replace("\r\n", "\n"));
+    let mut events = vec![];
+    while let Some(index) = self.buffer.find("\n\n") {
+      let block = self.buffer[..index].to_string();
+      self.buffer.drain(..index + 2);
+      let mut name = "message".to_string();
+      let mut data = vec![];
+      for line in block.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+          name = value.trim().to_string();
+        } else if let Some(value) = line.strip_prefix("data:") {
+          data.push(value.trim_start());
+        }
+      }
+      if !data.is_empty() {
+        events.push((name, data.join("\n")));
+      }
+    }
+    events
+  }
+}
+
+pub async fn watch_mock_server(host: &str, port: u16, matches: &ArgMatches, usage: &str) -> Result<(), i32> {
+  let mock_server_id = matches.get_one::<String>("mock-server-id");
+  let mock_server_port = matches.get_one::<u16>("mock-server-port");
+  let (id, id_type) = match (mock_server_id, mock_server_port) {
+    (Some(id), _) => (id.clone(), "id"),
+    (_, Some(port)) => (port.to_string(), "port"),
+    _ => display_error("Either an ID or port must be provided".to_string(), usage, 60)
+  };
+  let show_mismatches = matches.get_flag("mismatches");
+  let colour = !matches.get_flag("no-term-log");
+
+  let client = reqwest::Client::new();
+  let url = format!("http://{}:{}/mockserver/{}/events", host, port, id);
+  let mut response = match client.get(&url).send().await {
+    Ok(response) if response.status() == StatusCode::NOT_FOUND => {
+      println!("No mock server found with {} '{}', use the 'list' command to get a list of available mock servers.", id_type, id);
+      return Err(3);
+    }
+    Ok(response) if !response.status().is_success() => {
+      display_error(format!("Master mock server returned an error: {}", response.status()), usage, 60);
+    }
+    Ok(response) => response,
+    Err(err) => {
+      display_error(format!("Failed to connect to the master mock server '{}': {}", url, err), usage, 60);
+    }
+  };
+
+  println!("Watching mock server with {} '{}', press Ctrl-C to stop", id_type, id);
+  let mut parser = SseParser::default();
+  let mut pending: HashMap<u64, Value> = HashMap::new();
+  loop {
+    match response.chunk().await {
+      Ok(Some(chunk)) => for (event, data) in parser.push(&chunk) {
+        let data: Value = serde_json::from_str(&data).unwrap_or_default();
+        match event.as_str() {
+          "match" => {
+            let request_id = data.get("requestId").and_then(Value::as_u64).unwrap_or_default();
+            pending.insert(request_id, data);
+          }
+          "response" => {
+            let request_id = data.get("requestId").and_then(Value::as_u64).unwrap_or_default();
+            if let Some(match_event) = pending.remove(&request_id) {
+              print_request(&match_event, Some(&data), show_mismatches, colour);
+            }
+          }
+          "shutdown" => {
+            for (_, match_event) in pending.drain().sorted_by_key(|(id, _)| *id) {
+              print_request(&match_event, None, show_mismatches, colour);
+            }
+            println!("Mock server has shut down");
+            return Ok(());
+          }
+          _ => {}
+        }
+      },
+      Ok(None) => {
+        println!("Mock server has shut down");
+        return Ok(());
+      }
+      Err(err) => {
+        display_error(format!("Failed to read the events from the master mock server: {}", err), usage, 60);
+      }
+    }
+  }
+}
+
+fn print_request(match_event: &Value, response: Option<&Value>, show_mismatches: bool, colour: bool) {
+  println!("{}", request_summary(match_event, response, colour));
+  if show_mismatches {
+    for line in mismatch_details(match_event) {
+      println!("    {}", line);
+    }
+  }
+}
+
+/// One line summary of a request and its match result
+fn request_summary(match_event: &Value, response: Option<&Value>, colour: bool) -> String {
+  let request = &match_event["request"];
+  let query = json_to_string(&request["query"]);
+  let path = if query.is_empty() {
+    json_to_string(&request["path"])
+  } else {
+    format!("{}?{}", json_to_string(&request["path"]), query)
+  };
+  let (label, colour_code) = match match_event["result"]["type"].as_str().unwrap_or_default() {
+    "request-match" => ("MATCHED", "32"),
+    "request-mismatch" => ("MISMATCHED", "31"),
+    "request-not-found" => ("NOT FOUND", "33"),
+    other => (other, "0")
+  };
+  let label = if colour {
+    format!("\x1b[{}m{}\x1b[0m", colour_code, label)
+  } else {
+    label.to_string()
+  };
+  let response = response
+    .map(|response| format!("{} ({:.1} ms)", response["status"], response["durationMs"].as_f64().unwrap_or_default()))
+    .unwrap_or_else(|| "no response".to_string());
+  format!("[{}] {} {} -> {} {}", match_event["requestId"], json_to_string(&request["method"]), path,
+    response, label)
+}
+
+/// Details of any mismatches for a request
+fn mismatch_details(match_event: &Value) -> Vec<String> {
+  match_event["result"]["mismatches"].as_array()
+    .map(|mismatches| mismatches.iter().flat_map(|mismatch| {
+      let mut lines = vec![json_to_string(&mismatch["mismatch"])];
+      if let Some(expected) = mismatch.get("expected") {
+        lines.push(format!("  expected: {}", json_to_string(expected)));
+      }
+      if let Some(actual) = mismatch.get("actual") {
+        lines.push(format!("  actual:   {}", json_to_string(actual)));
+      }
+      lines
+    }).collect())
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::SocketAddr;
+  use std::time::{Duration, SystemTime};
+
+  use expectest::prelude::*;
+  use pact_matching::Mismatch;
+  use pact_mock_server::mock_server::{RecordedRequest, RequestDetails, ResponseDetails};
+
+  use super::*;
+
+  #[test]
+  fn events_request_id_test() {
+    expect!(events_request_id(&http::Method::GET, "/mockserver/1234ABCD/events")).to(be_some().value("1234ABCD"));
+    expect!(events_request_id(&http::Method::POST, "/mockserver/1234ABCD/events")).to(be_none());
+    expect!(events_request_id(&http::Method::GET, "/mockserver/1234ABCD")).to(be_none());
+  }
+
+  #[test]
+  fn sse_round_trip_test() {
+    let address: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+    let request = HttpRequest {
+      path: "/orders".to_string(),
+      query: Some(hashmap_query()),
+      .. HttpRequest::default()
+    };
+    let result = MatchResult::RequestMismatch(request.clone(), request, vec![
+      Mismatch::QueryMismatch {
+        parameter: "id".to_string(),
+        expected: "1".to_string(),
+        actual: "2".to_string(),
+        mismatch: "Expected '1' but received '2' for query parameter 'id'".to_string()
+      }
+    ]);
+    let details = RequestDetails {
+      request_id: 3,
+      remote_address: address,
+      local_address: address,
+      http_version: "HTTP/1.1".to_string(),
+      received_at: SystemTime::now()
+    };
+    let match_event = event_to_sse(&MockServerEvent::RequestRecorded(RecordedRequest { result, interaction: Some(0), details, raw_body: None, raw_headers: None })).unwrap();
+    let response_event = event_to_sse(&MockServerEvent::ResponsePrepared(ResponseDetails {
+      request_id: 3,
+      status: 500,
+      body_size: 10,
+      prepared_at: SystemTime::now(),
+      duration: Duration::from_millis(2)
+    })).unwrap();
+    expect!(event_to_sse(&MockServerEvent::ConnectionOpened(address))).to(be_none());
+
+    // Events can be split over chunks
+    let text = format!("{}{}", match_event, response_event);
+    let (first, second) = text.split_at(20);
+    let mut parser = SseParser::default();
+    let mut events = parser.push(first.as_bytes());
+    expect!(events.is_empty()).to(be_true());
+    events.extend(parser.push(second.as_bytes()));
+    expect!(events.len()).to(be_equal_to(2));
+    expect!(events[0].0.as_str()).to(be_equal_to("match"));
+    expect!(events[1].0.as_str()).to(be_equal_to("response"));
+
+    let match_json: Value = serde_json::from_str(&events[0].1).unwrap();
+    let response_json: Value = serde_json::from_str(&events[1].1).unwrap();
+    expect!(request_summary(&match_json, Some(&response_json), false))
+      .to(be_equal_to("[3] GET /orders?id=2 -> 500 (2.0 ms) MISMATCHED".to_string()));
+    expect!(request_summary(&match_json, None, true))
+      .to(be_equal_to("[3] GET /orders?id=2 -> no response \x1b[31mMISMATCHED\x1b[0m".to_string()));
+    expect!(mismatch_details(&match_json)).to(be_equal_to(vec![
+      "Expected '1' but received '2' for query parameter 'id'".to_string(),
+      "  expected: 1".to_string(),
+      "  actual:   2".to_string()
+    ]));
+  }
+
+  fn hashmap_query() -> HashMap<String, Vec<Option<String>>> {
+    HashMap::from([("id".to_string(), vec![Some("2".to_string())])])
+  }
+}